@@ -16,18 +16,28 @@ use crate::ui::UI;
 pub mod galaxy;
 use galaxy::Galaxy;
 
-mod render;
-use render::Renderer;
+pub mod render;
+use render::{Renderer, HDR_FORMAT};
 
 pub mod camera;
 use camera::{Camera, PerspectiveProjection, Projection};
 
 pub mod post_processing;
 use post_processing::bloom::Bloom;
-use post_processing::present::Present;
+use post_processing::chain::{PostChain, PostEffectKind};
 
 pub mod timestamps;
-use timestamps::Timestamps;
+
+pub mod graph;
+use graph::{GraphContext, PassDescriptor, PassKind, RenderGraph, TransientTexture};
+
+// Render graph resources, Scene and Post are the post chain's ping-pong textures
+const SCENE: &str = "Scene";
+const POST: &str = "Post";
+const SURFACE: &str = "Surface";
+const STARS: &str = "Stars";
+const CAMERA: &str = "Camera";
+const DENSITY_HISTOGRAM: &str = "Density Histogram";
 
 
 pub struct AppState<'window> {
@@ -39,128 +49,199 @@ pub struct AppState<'window> {
     pub camera: Camera<PerspectiveProjection>,
 
     // Post processing
-    pub bloom: Bloom,
-    pub present: Present,
+    pub post_chain: PostChain,
 
     //UI
     pub ui: UI,
 
-    //Profiling
-    timestamps: Timestamps
+    // Frame passes, owns the timestamps
+    graph: RenderGraph<AppState<'window>>,
+    last_update: time::Instant,
 }
 
 impl<'window> AppState<'window> {
     
     pub fn new(wgpu_state: WgpuState<'window>, config: Config, size: &PhysicalSize<u32>) -> Self {
         // Simulation
-        let galaxy = Galaxy::new(&wgpu_state.device, &config);
+        let galaxy = Galaxy::new(&wgpu_state.device, &wgpu_state.queue, &config);
 
         // Primary Rendering
         let camera = Camera::<PerspectiveProjection>::new(&wgpu_state.device, &config);
-        let renderer = Renderer::new(&wgpu_state.device, &config, wgpu_state.config.format, &camera);
+
+        // Owns the texture the scene is rendered into
+        let mut graph = Self::create_graph(&wgpu_state.device, size);
 
         // Post-Porcessing
-        let bloom = Bloom::new(&wgpu_state.device, &config, wgpu_state.config.format, size);
-        let present = Present::new(&wgpu_state.device, &config, wgpu_state.config.format, size, &bloom.mipchain_views[0]);
+        let post_chain = PostChain::new(&wgpu_state.device, &wgpu_state.queue, &config, wgpu_state.config.format, size, [graph.resources().texture(SCENE), graph.resources().texture(POST)]);
+        let renderer = Renderer::new(&wgpu_state.device, &config, HDR_FORMAT, size, graph.resources().view(SCENE), &camera, &galaxy);
 
         let ui = UI::new(&wgpu_state.device, wgpu_state.config.format, &wgpu_state.window);
-        let timestamps = Timestamps::new(&wgpu_state.device);
+        graph.compile(&wgpu_state.device, &wgpu_state.queue);
 
         Self {
             wgpu_state,
             galaxy,
             renderer,
             camera,
-            bloom,
-            present,
+            post_chain,
             ui,
-            timestamps,
-            config
+            graph,
+            config,
+            last_update: time::Instant::now(),
         }
     }   
 
-    pub fn update(&mut self) {
-        self.galaxy.add_stars(&self.config, &self.wgpu_state.queue, 200);
-        self.camera.update(&self.wgpu_state.queue);
-    }
-
-    //Move this to Renderer
-    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
-        let output = self.wgpu_state.surface.get_current_texture()?;
-
-        
-        let mut encoder = self.wgpu_state.device.create_command_encoder(&wgpu::CommandEncoderDescriptor::default());
-        
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &self.bloom.mipchain_views[0],
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: wgpu::StoreOp::Store,
-                    }
-                })], 
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: Some(RenderPassTimestampWrites {
-                    query_set: &self.timestamps.query_set,
-                    beginning_of_pass_write_index: Some(0),
-                    end_of_pass_write_index: Some(1)
-                }),
+    // Compute work, the scene, trails, post processing and the UI, in that order
+    fn create_graph(device: &Device, size: &PhysicalSize<u32>) -> RenderGraph<Self> {
+        let mut graph: RenderGraph<Self> = RenderGraph::new(size);
+
+        for name in [SCENE, POST] {
+            graph.add_texture(device, TransientTexture {
+                name,
+                format: HDR_FORMAT,
+                // Copies are the hot pixel test and the trail accumulation writing the scene
+                usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+                scale: 1.0,
             });
-
-            
-            self.renderer.render(&mut render_pass, &self.galaxy);
         }
-
-        // Bloom
-        self.bloom.render(&mut encoder, &self.wgpu_state.queue);
-
-        //Present
-        let output_view = output.texture.create_view(&TextureViewDescriptor::default());//&self.bloom.mipchain_views[0];
-        {
-            let mut render_pass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
-                label: Some("Render Pass"),
-                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
-                    view: &output_view,
-                    resolve_target: None,
-                    ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
-                        store: wgpu::StoreOp::Store,
+        graph.import_texture(SURFACE);
+        graph.import_buffer(STARS);
+        graph.import_buffer(CAMERA);
+        graph.import_buffer(DENSITY_HISTOGRAM);
+
+        // Only dispatches in the density splat render mode
+        graph.add_pass(PassDescriptor {
+            name: "Density Splat",
+            kind: PassKind::Compute,
+            reads: vec![STARS, CAMERA],
+            writes: vec![DENSITY_HISTOGRAM],
+            scopes: vec![],
+            execute: |app, ctx| {
+                app.renderer.prepare(ctx.encoder, &app.wgpu_state.device, &app.galaxy, &app.camera, ctx.timestamps);
+            },
+            resize: None,
+        });
+
+        graph.add_pass(PassDescriptor {
+            name: "Scene",
+            kind: PassKind::Render,
+            reads: vec![STARS, CAMERA, DENSITY_HISTOGRAM],
+            writes: vec![SCENE],
+            scopes: vec![],
+            execute: |app, ctx| {
+                // Replaces the scene while bloom runs its test pattern
+                let hot_pixel_bloom = app.post_chain.get::<Bloom>().filter(|bloom| bloom.enabled && bloom.hot_pixel_test);
+                let scene = ctx.view(SCENE);
+
+                {
+                    let mut render_pass = ctx.encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                        label: Some("Render Pass"),
+                        color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                            view: scene,
+                            resolve_target: None,
+                            ops: wgpu::Operations {
+                                load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                                store: wgpu::StoreOp::Store,
+                            }
+                        })],
+                        depth_stencil_attachment: None,
+                        occlusion_query_set: None,
+                        timestamp_writes: ctx.timestamps.map(|scope| scope.render_writes(true, true)),
+                    });
+
+                    if hot_pixel_bloom.is_none() {
+                        app.renderer.render(&mut render_pass, &app.galaxy);
                     }
-                })], 
-                depth_stencil_attachment: None,
-                occlusion_query_set: None,
-                timestamp_writes: Some(RenderPassTimestampWrites {
-                    query_set: &self.timestamps.query_set,
-                    beginning_of_pass_write_index: Some(2),
-                    end_of_pass_write_index: Some(3)
-                }),
-            });
+                }
 
-            self.present.render(&mut render_pass);
-        }
+                if let Some(bloom) = hot_pixel_bloom {
+                    bloom.write_hot_pixel(ctx.encoder, ctx.texture(SCENE));
+                }
+            },
+            resize: Some(|app, resources| {
+                app.renderer.resize(&app.wgpu_state.device, &resources.size(), resources.view(SCENE));
+            }),
+        });
+
+        graph.add_pass(PassDescriptor {
+            name: "Trails",
+            kind: PassKind::Render,
+            reads: vec![SCENE],
+            writes: vec![SCENE],
+            scopes: vec![],
+            execute: |app, ctx| {
+                app.renderer.trails.accumulate(ctx.encoder, ctx.texture(SCENE), ctx.timestamps);
+            },
+            resize: None,
+        });
+
+        // Ends with the result in the surface, every effect gets its own scope
+        graph.add_pass(PassDescriptor {
+            name: "Post Processing",
+            kind: PassKind::Render,
+            // Post is scratch the chain writes before reading it
+            reads: vec![SCENE],
+            writes: vec![SCENE, POST, SURFACE],
+            scopes: PostEffectKind::ALL.iter().map(|kind| kind.name()).collect(),
+            execute: |app, ctx| {
+                let timestamps = PostEffectKind::ALL.map(|kind| ctx.scope(kind.name()));
+                let surface = ctx.view(SURFACE);
+                app.post_chain.render(ctx.encoder, &app.wgpu_state.device, &app.wgpu_state.queue, &timestamps, surface);
+            },
+            resize: Some(|app, resources| {
+                //Effects rebuild their own bindgroups to the new chain textures
+                app.post_chain.resize(&app.wgpu_state.device, &app.wgpu_state.queue, &resources.size(), [resources.texture(SCENE), resources.texture(POST)]);
+            }),
+        });
+
+        graph.add_pass(PassDescriptor {
+            name: "UI",
+            kind: PassKind::Render,
+            reads: vec![SURFACE],
+            writes: vec![SURFACE],
+            scopes: vec![],
+            execute: |app, ctx| {
+                //UI (maybe figure out some abtraction instead of passing all used structs, maybe just pass the specific parameters)
+                let ui_output = app.ui.update(&app.wgpu_state, &mut app.camera, ctx.times, &mut app.galaxy, &mut app.renderer, &mut app.post_chain);
+
+                let size: [u32;2] = app.wgpu_state.window.inner_size().into();
+                let screen_descriptor = ScreenDescriptor {
+                    size_in_pixels: size,
+                    pixels_per_point: 1.0,
+                };
+                app.ui.render(ctx.encoder, &app.wgpu_state, screen_descriptor, ctx.view(SURFACE), ui_output, ctx.timestamps);
+            },
+            resize: None,
+        });
 
-        //UI (maybe figure out some abtraction instead of passing all used structs, maybe just pass the specific parameters)
-        let ui_output = self.ui.update(&self.wgpu_state, &mut self.camera, &mut self.bloom, &self.timestamps, &self.galaxy);
+        graph
+    }
+
+    pub fn update(&mut self) {
+        let now = time::Instant::now();
+        let dt = now.duration_since(self.last_update).as_secs_f32();
+        self.last_update = now;
+
+        self.galaxy.update(&self.config, &self.wgpu_state.queue, dt);
+        self.camera.update(&self.wgpu_state.queue);
+        self.renderer.update(&self.wgpu_state.queue, &self.galaxy, &self.camera, (self.wgpu_state.config.width, self.wgpu_state.config.height), dt);
+        self.post_chain.update(&self.wgpu_state.device, &self.wgpu_state.queue, dt);
+    }
 
-        let size: [u32;2] = self.wgpu_state.window.inner_size().into();
-        let screen_descriptor = ScreenDescriptor {
-            size_in_pixels: size,
-            pixels_per_point: 1.0,
-        };
-        self.ui.render(&mut encoder, &self.wgpu_state, screen_descriptor, &output_view, ui_output);
+    pub fn render(&mut self) -> Result<(), wgpu::SurfaceError> {
+        let output = self.wgpu_state.surface.get_current_texture()?;
+        let output_view = output.texture.create_view(&TextureViewDescriptor::default());
 
-        
-        //Profiling
-        self.timestamps.resolve(&mut encoder, &self.wgpu_state.device);
+        // Passes get the whole app state, so the graph is moved out while it records them
+        let mut graph = std::mem::take(&mut self.graph);
+        let commands = graph.execute(self, &[(SURFACE, &output_view)]);
+        self.graph = graph;
 
-        self.wgpu_state.queue.submit(std::iter::once(encoder.finish()));
+        self.wgpu_state.queue.submit(std::iter::once(commands));
         output.present();
 
-        self.timestamps.update_times(&mut self.wgpu_state.device);
+        self.graph.after_submit(&mut self.wgpu_state.device);
+        self.post_chain.after_submit();
 
         Ok(())
     }
@@ -181,10 +262,29 @@ impl<'window> AppState<'window> {
                 PhysicalKey::Code(KeyCode::KeyS) | PhysicalKey::Code(KeyCode::ArrowDown) => {
                     self.camera.orbit_vertical(-self.camera.orbit_speed);
                 },
+                PhysicalKey::Code(code) => self.hotkey_input(code),
                 _ => {}
             }
         }
     }
+
+    // Config-defined hotkeys, compared against the KeyCode variant name
+    fn hotkey_input(&mut self, code: winit::keyboard::KeyCode) {
+        let name = format!("{:?}", code);
+        let keybinds = &self.config.keybind_config;
+
+        if name == keybinds.clear {
+            self.galaxy.clear();
+        } else if name == keybinds.reset {
+            self.galaxy.reset(&self.wgpu_state.queue);
+        } else if name == keybinds.delete_selection {
+            self.galaxy.remove_selection(&self.wgpu_state.queue);
+        } else if name == keybinds.burst {
+            self.galaxy.emitter.burst();
+        } else if name == keybinds.dump_render_graph {
+            log::info!("{}", self.graph.dump());
+        }
+    }
     
     pub fn mouse_wheel_input(&mut self, delta: &winit::event::MouseScrollDelta, phase: &winit::event::TouchPhase) {
         use winit::event::MouseScrollDelta;
@@ -204,8 +304,15 @@ impl<'window> AppState<'window> {
 
     pub fn resize(&mut self, new_size: &PhysicalSize<u32>) {
         self.wgpu_state.resize(new_size);
-        self.bloom.recreate_mipchain_and_bindgroups(&self.wgpu_state.device, self.wgpu_state.config.format, new_size);
-        //Recreate bindgroup which depends on the mipchain
-        self.present.recreate_bindgroup(&self.wgpu_state.device, &self.bloom.mipchain_views[0])
+        // Passes rebind the recreated textures in their resize hooks
+        let mut graph = std::mem::take(&mut self.graph);
+        graph.resize(self, new_size);
+        self.graph = graph;
+    }
+}
+
+impl<'window> GraphContext for AppState<'window> {
+    fn device(&self) -> &Device {
+        &self.wgpu_state.device
     }
 }
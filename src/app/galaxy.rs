@@ -1,121 +1,315 @@
-use nalgebra::Vector3;
 use bytemuck::{Pod, Zeroable};
 use wgpu::{core::device, util::DeviceExt, BufferUsages, Queue};
 
-//BHOT = Barnes-Hut Oct-Tree
-struct BHOTNode  {
-    indirection_index: usize,
-    leaf: bool,
-    total_mass: f32,
-    center_of_mass: Vector3<f32>,
-}
+pub mod emission;
+use emission::Emitter;
 
-struct BHOT {
-    root: BHOTNode,
-    nodes: Vec<BHOTNode>,
-}
+pub mod evolution;
+
+pub mod scenario;
+
+pub mod star_formation;
+
+//BHOT = Barnes-Hut Oct-Tree
+pub mod bhot;
+use star_formation::StarFormation;
+use scenario::{Plummer, Satellite, ScenarioKind, POPULATION_HOST, POPULATION_SATELLITE};
 
 #[repr(C)]
 #[derive(Clone, Copy, Pod, Zeroable, Debug)]
 pub struct Star {
     pub position: [f32; 3],
+    pub population: u32,
+    pub velocity: [f32; 3],
+    pub mass: f32, // solar masses
+    pub birth_time: f32, // Myr, compared against Galaxy::time
+}
+
+// Upper bound on stars the GPU buffer can hold
+pub const MAX_STARS: usize = 10_000_000;
+
+// Caps the leapfrog substeps taken in one frame after a long stall
+const MAX_SUBSTEPS: usize = 64;
+
+// Volume used for selection-based deletion
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Region {
+    Sphere { center: [f32; 3], radius: f32 },
+    Box { min: [f32; 3], max: [f32; 3] },
+}
+
+impl Region {
+    pub fn contains(&self, p: &[f32; 3]) -> bool {
+        match self {
+            Region::Sphere { center, radius } => {
+                let d = [p[0] - center[0], p[1] - center[1], p[2] - center[2]];
+                d[0] * d[0] + d[1] * d[1] + d[2] * d[2] <= radius * radius
+            },
+            Region::Box { min, max } => {
+                (0..3).all(|i| p[i] >= min[i] && p[i] <= max[i])
+            }
+        }
+    }
+}
+
+impl Default for Region {
+    fn default() -> Self {
+        Region::Sphere { center: [0.0, 0.0, 0.0], radius: 100.0 }
+    }
 }
 
 #[derive(Debug)]
 pub struct Galaxy {
     pub stars: Vec<Star>,
     pub stars_buffer: wgpu::Buffer,
-    //bhot: BHOT,
-}
+    pub selection: Region,
+    pub emitter: Emitter,
+    // Bumped when stars are removed or respawned, indices taken before may point at other stars since
+    pub generation: u32,
 
-use rand::Rng;
-use rand_distr::StandardNormal;
+    pub scenario: ScenarioKind,
+    pub satellite: Option<Satellite>,
+    host: Plummer,
+    scenario_config: ScenarioConfig,
 
-use crate::config::Config;
+    // Simulation time in Myr, drives stellar evolution
+    pub time: f32,
+    pub myr_per_second: f32,
+    evolution_config: EvolutionConfig,
 
-impl Galaxy {
-    pub fn new(device: &wgpu::Device, config: &crate::config::Config) -> Self {
-        let star_count = 0;
+    pub star_formation: StarFormation,
 
-        let mut stars = Vec::with_capacity(star_count);
-        let mut rng = rand::thread_rng();
+    // Seeded so reset() replays the same initial conditions
+    seed: u64,
+    rng: StdRng,
+}
 
-        for _ in 0..star_count {
-            let arm: u32 = rng.gen::<u32>() % config.sim_config.arm_count;
-            let _starting_theta = ((arm as f32) / (config.sim_config.arm_count as f32)) * 2.0 * std::f32::consts::PI;
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use rand_distr::StandardNormal;
 
-            let spiralness_diff: f32 = rng.sample(StandardNormal);
-            let r: f32 = rng.gen::<f32>() * config.sim_config.galaxy_radius;
-            let theta: f32 = _starting_theta + r * (config.sim_config.spiralness + spiralness_diff * 0.0005);
+use crate::config::{Config, EvolutionConfig, ScenarioConfig};
 
-            let random_offset: (f32, f32, f32) = (rng.sample(StandardNormal), rng.sample(StandardNormal), rng.sample(StandardNormal));
+impl Galaxy {
+    pub fn new(device: &wgpu::Device, queue: &Queue, config: &crate::config::Config) -> Self {
+        let star_count = 0;
 
-            let x = r * theta.cos() + random_offset.0 * config.sim_config.noise_scale;
-            let y = 0.0;// random_offset.1 * config.sim_config.noise_scale;
-            let z = r * theta.sin()  + random_offset.2 * config.sim_config.noise_scale;
+        let seed = config.sim_config.seed;
+        let mut rng = StdRng::seed_from_u64(seed);
 
-            stars.push(Star {
-                position: [x, y, z],
-            });
-        }
+        let stars = (0..star_count).map(|_| Self::generate_star(&mut rng, config, 0.0)).collect();
 
         let stars_buffer = device.create_buffer(&wgpu::BufferDescriptor {
             label: Some("Stars Buffer"),
-            size: 12 * 10000000,
-            usage: BufferUsages::VERTEX | BufferUsages::COPY_DST,
+            size: (std::mem::size_of::<Star>() * MAX_STARS) as u64,
+            usage: BufferUsages::VERTEX | BufferUsages::STORAGE | BufferUsages::COPY_DST,
             mapped_at_creation: false
         });
 
-        Self {
+        let scenario_config = config.scenario_config.clone();
+
+        let mut galaxy = Self {
             stars,
             stars_buffer: stars_buffer,
+            selection: Region::default(),
+            emitter: Emitter::new(config),
+            generation: 0,
+            scenario: scenario_config.scenario,
+            satellite: None,
+            host: Plummer {
+                mass: scenario_config.host_mass,
+                scale_radius: scenario_config.host_scale_radius,
+            },
+            scenario_config,
+            time: 0.0,
+            myr_per_second: config.evolution_config.myr_per_second,
+            evolution_config: config.evolution_config.clone(),
+            star_formation: StarFormation::new(config),
+            seed,
+            rng,
+        };
+        galaxy.spawn_scenario(queue);
+        galaxy
+    }
+
+    fn spawn_scenario(&mut self, queue: &Queue) {
+        self.satellite = match self.scenario {
+            ScenarioKind::Spiral => None,
+            ScenarioKind::TidalDisruption => Some(Satellite::new(&self.scenario_config)),
+        };
+
+        if let Some(satellite) = &self.satellite {
+            let count = self.scenario_config.satellite_star_count as usize;
+            let new_stars = (0..count).map(|_| {
+                let (position, velocity) = satellite.potential.sample(&mut self.rng);
+                Star {
+                    position: [0, 1, 2].map(|i| position[i] + satellite.position[i]),
+                    population: POPULATION_SATELLITE,
+                    velocity: [0, 1, 2].map(|i| velocity[i] + satellite.velocity[i]),
+                    mass: evolution::sample_mass(&mut self.rng, &self.evolution_config),
+                    birth_time: evolution::sample_birth_time(&mut self.rng, &self.evolution_config, self.time),
+                }
+            }).collect();
+            self.push_stars(queue, new_stars);
+        }
+    }
+
+    fn generate_star(rng: &mut StdRng, config: &Config, time: f32) -> Star {
+        let arm: u32 = rng.gen::<u32>() % config.sim_config.arm_count;
+        let _starting_theta = ((arm as f32) / (config.sim_config.arm_count as f32)) * 2.0 * std::f32::consts::PI;
+
+        let spiralness_diff: f32 = rng.sample(StandardNormal);
+        let r: f32 = rng.gen::<f32>() * config.sim_config.galaxy_radius;
+        let theta: f32 = _starting_theta + r * (config.sim_config.spiralness + spiralness_diff * 0.0005);
+
+        let random_offset: (f32, f32, f32) = (rng.sample(StandardNormal), rng.sample(StandardNormal), rng.sample(StandardNormal));
+
+        let x = r * theta.cos() + random_offset.0 * config.sim_config.noise_scale;
+        let y = 0.0;// random_offset.1 * config.sim_config.noise_scale;
+        let z = r * theta.sin()  + random_offset.2 * config.sim_config.noise_scale;
+
+        Star {
+            position: [x, y, z],
+            population: POPULATION_HOST,
+            velocity: [0.0; 3],
+            mass: evolution::sample_mass(rng, &config.evolution_config),
+            birth_time: evolution::sample_birth_time(rng, &config.evolution_config, time),
         }
     }
 
-    pub fn add_stars(&mut self, config: &Config, queue: &Queue, count: usize) {
-        let mut rng = rand::thread_rng();
-
-        let mut new_stars: Vec<Star> = (0..count).map(|_| {
-            let arm: u32 = rng.gen::<u32>() % config.sim_config.arm_count;
-            let _starting_theta = ((arm as f32) / (config.sim_config.arm_count as f32)) * 2.0 * std::f32::consts::PI;
-    
-            let spiralness_diff: f32 = rng.sample(StandardNormal);
-            let r: f32 = rng.gen::<f32>() * config.sim_config.galaxy_radius;
-            let theta: f32 = _starting_theta + r * (config.sim_config.spiralness + spiralness_diff * 0.0005);
-    
-            let random_offset: (f32, f32, f32) = (rng.sample(StandardNormal), rng.sample(StandardNormal), rng.sample(StandardNormal));
-    
-            let x = r * theta.cos() + random_offset.0 * config.sim_config.noise_scale;
-            let y = 0.0;// random_offset.1 * config.sim_config.noise_scale;
-            let z = r * theta.sin()  + random_offset.2 * config.sim_config.noise_scale;
-
-            Star {
-                position: [x, y, z],
+    // Spawns whatever the emitter schedules for this frame, dt in seconds
+    pub fn update(&mut self, config: &Config, queue: &Queue, dt: f32) {
+        // Emitted before the clock advances, so the first frame's stars are the back-dated initial population.
+        // Scheduled stars fill the buffer first, bursts get what's left
+        let (scheduled, burst) = self.emitter.emit(dt);
+        let added = self.add_stars(config, queue, scheduled + burst);
+        self.emitter.record(added.min(scheduled), added.saturating_sub(scheduled));
+
+        let dt_myr = dt * self.myr_per_second;
+        self.time += dt_myr;
+
+        let new_stars = self.star_formation.step(&mut self.rng, dt_myr, self.time);
+        self.push_stars(queue, new_stars);
+
+        self.step_satellite(queue, dt * self.scenario_config.time_scale);
+    }
+
+    // Integrates satellite stars as test particles, host stars stay put
+    fn step_satellite(&mut self, queue: &Queue, dt: f32) {
+        let Some(satellite) = &mut self.satellite else {
+            return;
+        };
+
+        let steps = ((dt / self.scenario_config.max_step).ceil() as usize).clamp(1, MAX_SUBSTEPS);
+        let h = dt / steps as f32;
+
+        let mut range: Option<(usize, usize)> = None;
+        for _ in 0..steps {
+            for (i, star) in self.stars.iter_mut().enumerate() {
+                if star.population != POPULATION_SATELLITE {
+                    continue;
+                }
+                scenario::leapfrog(&mut star.position, &mut star.velocity, h, |p| satellite.acceleration(&self.host, p));
+                range = Some(range.map_or((i, i), |(lo, hi)| (lo.min(i), hi.max(i))));
             }
-        }).collect();
-        
-        queue.write_buffer(&self.stars_buffer, 12 * self.stars.len() as u64, bytemuck::cast_slice(&new_stars));
+            satellite.step(&self.host, h);
+        }
+
+        if let Some((lo, hi)) = range {
+            queue.write_buffer(&self.stars_buffer, Self::offset_of(lo), bytemuck::cast_slice(&self.stars[lo..=hi]));
+        }
+    }
+
+    // Returns the number of stars added
+    pub fn add_stars(&mut self, config: &Config, queue: &Queue, count: usize) -> usize {
+        let new_stars: Vec<Star> = (0..count.min(MAX_STARS - self.stars.len()))
+            .map(|_| Self::generate_star(&mut self.rng, config, self.time))
+            .collect();
+        self.push_stars(queue, new_stars)
+    }
+
+    // Appends to the CPU copy and the GPU buffer, truncating at MAX_STARS. Returns the number pushed
+    fn push_stars(&mut self, queue: &Queue, mut new_stars: Vec<Star>) -> usize {
+        new_stars.truncate(MAX_STARS - self.stars.len());
+        if new_stars.is_empty() {
+            return 0;
+        }
+
+        queue.write_buffer(&self.stars_buffer, Self::offset_of(self.stars.len()), bytemuck::cast_slice(&new_stars));
+        let pushed = new_stars.len();
         self.stars.append(&mut new_stars);
+        pushed
+    }
+
+    // Swap-removes, so only the star moved into the hole needs re-uploading
+    pub fn remove_star(&mut self, queue: &Queue, index: usize) -> Option<Star> {
+        if index >= self.stars.len() {
+            return None;
+        }
+
+        let removed = self.stars.swap_remove(index);
+        self.generation += 1;
+        if index < self.stars.len() {
+            queue.write_buffer(&self.stars_buffer, Self::offset_of(index), bytemuck::cast_slice(&self.stars[index..index + 1]));
+        }
+        Some(removed)
+    }
+
+    // Returns the number of stars removed
+    pub fn remove_region(&mut self, queue: &Queue, region: &Region) -> usize {
+        let before = self.stars.len();
+        self.stars.retain(|star| !region.contains(&star.position));
+
+        let removed = before - self.stars.len();
+        if removed > 0 {
+            self.generation += 1;
+            self.upload(queue);
+        }
+        removed
+    }
+
+    pub fn remove_selection(&mut self, queue: &Queue) -> usize {
+        let selection = self.selection;
+        self.remove_region(queue, &selection)
+    }
+
+    // Draw count follows stars.len(), so stale buffer contents are never read
+    pub fn clear(&mut self) {
+        self.stars.clear();
+        self.generation += 1;
+    }
+
+    pub fn reset(&mut self, queue: &Queue) {
+        self.clear();
+        self.emitter.reset();
+        self.time = 0.0;
+        self.star_formation.reset();
+        self.rng = StdRng::seed_from_u64(self.seed);
+        self.spawn_scenario(queue);
+    }
+
+    fn upload(&self, queue: &Queue) {
+        if !self.stars.is_empty() {
+            queue.write_buffer(&self.stars_buffer, 0, bytemuck::cast_slice(&self.stars));
+        }
+    }
+
+    fn offset_of(index: usize) -> u64 {
+        (std::mem::size_of::<Star>() * index) as u64
     }
 }
 
 
 impl Star {
-    const ATTRIBS : [wgpu::VertexAttribute; 1] = 
-        wgpu::vertex_attr_array![0 => Float32x3];
+    const ATTRIBS : [wgpu::VertexAttribute; 5] = 
+        wgpu::vertex_attr_array![0 => Float32x3, 1 => Uint32, 2 => Float32x3, 3 => Float32, 4 => Float32];
 
     pub fn desc() -> wgpu::VertexBufferLayout<'static> {
         wgpu::VertexBufferLayout {
             array_stride: std::mem::size_of::<Star>() as wgpu::BufferAddress,
-            step_mode: wgpu::VertexStepMode::Vertex,
+            step_mode: wgpu::VertexStepMode::Instance, // One sprite per star
             attributes: &Self::ATTRIBS,
         }
     }
-
-    pub fn new(position: [f32; 3]) -> Self {
-        Self {
-            position,
-        }
-    }
 }
 
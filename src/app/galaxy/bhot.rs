@@ -0,0 +1,205 @@
+use nalgebra::Vector3;
+
+use super::Star;
+
+/*
+    Barnes-Hut octree over Galaxy::stars, rebuilt from scratch whenever it's needed.
+    nodes[0] is the root, the children of an internal node are 8 consecutive nodes in octant order (x + 2y + 4z),
+    leaves point into BHOT::indices, which holds the star indices grouped by leaf.
+*/
+
+pub const LEAF_CAPACITY: usize = 8;
+pub const MAX_DEPTH: u32 = 20;
+
+#[derive(Clone, Debug)]
+pub struct BHOTNode {
+    pub indirection_index: usize, // first child, or first entry in BHOT::indices for leaves
+    pub count: usize, // stars in the subtree
+    pub leaf: bool,
+    pub depth: u32,
+    pub center: Vector3<f32>,
+    pub half_size: f32,
+    pub total_mass: f32,
+    pub center_of_mass: Vector3<f32>,
+}
+
+#[derive(Debug, Default)]
+pub struct BHOT {
+    pub nodes: Vec<BHOTNode>,
+    pub indices: Vec<u32>,
+}
+
+impl BHOTNode {
+    fn new(center: Vector3<f32>, half_size: f32, depth: u32, count: usize) -> Self {
+        Self {
+            indirection_index: 0,
+            count,
+            leaf: true,
+            depth,
+            center,
+            half_size,
+            total_mass: 0.0,
+            center_of_mass: center,
+        }
+    }
+
+    pub fn children(&self) -> std::ops::Range<usize> {
+        if self.leaf {
+            0..0
+        } else {
+            self.indirection_index..self.indirection_index + 8
+        }
+    }
+}
+
+impl BHOT {
+    pub fn build(stars: &[Star]) -> Self {
+        let mut tree = BHOT {
+            nodes: Vec::new(),
+            indices: (0..stars.len() as u32).collect(),
+        };
+        if stars.is_empty() {
+            return tree;
+        }
+
+        let mut min = Vector3::repeat(f32::MAX);
+        let mut max = Vector3::repeat(f32::MIN);
+        for star in stars {
+            let p = Vector3::from(star.position);
+            min = min.inf(&p);
+            max = max.sup(&p);
+        }
+        let center = (min + max) * 0.5;
+        let half_size = (max - min).max() * 0.5 * 1.0001 + 1e-3; // Keeps boundary stars strictly inside
+
+        tree.nodes.push(BHOTNode::new(center, half_size, 0, stars.len()));
+        tree.subdivide(0, stars, 0, stars.len());
+        tree
+    }
+
+    fn subdivide(&mut self, node: usize, stars: &[Star], start: usize, count: usize) {
+        let (center, half_size, depth) = {
+            let node = &self.nodes[node];
+            (node.center, node.half_size, node.depth)
+        };
+
+        if count <= LEAF_CAPACITY || depth >= MAX_DEPTH {
+            let mut mass = 0.0;
+            let mut weighted = Vector3::zeros();
+            for &i in &self.indices[start..start + count] {
+                let star = &stars[i as usize];
+                mass += star.mass;
+                weighted += Vector3::from(star.position) * star.mass;
+            }
+
+            let node = &mut self.nodes[node];
+            node.indirection_index = start;
+            node.total_mass = mass;
+            if mass > 0.0 {
+                node.center_of_mass = weighted / mass;
+            }
+            return;
+        }
+
+        // Counting sort of this node's stars by octant
+        let octant = |i: u32| {
+            let p = stars[i as usize].position;
+            (p[0] >= center.x) as usize | ((p[1] >= center.y) as usize) << 1 | ((p[2] >= center.z) as usize) << 2
+        };
+        let mut counts = [0usize; 8];
+        for &i in &self.indices[start..start + count] {
+            counts[octant(i)] += 1;
+        }
+        let mut offsets = [0usize; 8];
+        for o in 1..8 {
+            offsets[o] = offsets[o - 1] + counts[o - 1];
+        }
+        let mut cursor = offsets;
+        let mut sorted = vec![0u32; count];
+        for &i in &self.indices[start..start + count] {
+            let o = octant(i);
+            sorted[cursor[o]] = i;
+            cursor[o] += 1;
+        }
+        self.indices[start..start + count].copy_from_slice(&sorted);
+
+        let first_child = self.nodes.len();
+        let quarter = half_size * 0.5;
+        for (o, &child_count) in counts.iter().enumerate() {
+            let offset = Vector3::new(
+                if o & 1 != 0 { quarter } else { -quarter },
+                if o & 2 != 0 { quarter } else { -quarter },
+                if o & 4 != 0 { quarter } else { -quarter },
+            );
+            self.nodes.push(BHOTNode::new(center + offset, quarter, depth + 1, child_count));
+        }
+        self.nodes[node].indirection_index = first_child;
+        self.nodes[node].leaf = false;
+
+        let mut mass = 0.0;
+        let mut weighted = Vector3::zeros();
+        for o in 0..8 {
+            self.subdivide(first_child + o, stars, start + offsets[o], counts[o]);
+            let child = &self.nodes[first_child + o];
+            mass += child.total_mass;
+            weighted += child.center_of_mass * child.total_mass;
+        }
+
+        let node = &mut self.nodes[node];
+        node.total_mass = mass;
+        if mass > 0.0 {
+            node.center_of_mass = weighted / mass;
+        }
+    }
+
+    pub fn leaf_stars(&self, node: &BHOTNode) -> &[u32] {
+        &self.indices[node.indirection_index..node.indirection_index + node.count]
+    }
+
+    // Acceleration and potential at a point (G = 1), a node is used as a point mass once size / distance < theta.
+    // `opened` is called for every node whose contents had to be visited
+    pub fn field(&self, stars: &[Star], position: Vector3<f32>, exclude: Option<usize>, theta: f32, softening: f32, mut opened: impl FnMut(usize)) -> (Vector3<f32>, f32) {
+        let mut acceleration = Vector3::zeros();
+        let mut potential = 0.0;
+        if self.nodes.is_empty() {
+            return (acceleration, potential);
+        }
+
+        let eps2 = softening * softening;
+        let mut add = |mass: f32, source: Vector3<f32>| {
+            let r = source - position;
+            let d2 = r.norm_squared() + eps2;
+            let inv_d = 1.0 / d2.sqrt();
+            acceleration += r * (mass * inv_d * inv_d * inv_d);
+            potential -= mass * inv_d;
+        };
+
+        let mut stack = vec![0usize];
+        while let Some(index) = stack.pop() {
+            let node = &self.nodes[index];
+            if node.count == 0 {
+                continue;
+            }
+
+            let distance = (node.center_of_mass - position).norm();
+            if 2.0 * node.half_size < theta * distance {
+                add(node.total_mass, node.center_of_mass);
+                continue;
+            }
+
+            opened(index);
+            if node.leaf {
+                for &i in self.leaf_stars(node) {
+                    if Some(i as usize) != exclude {
+                        let star = &stars[i as usize];
+                        add(star.mass, Vector3::from(star.position));
+                    }
+                }
+            } else {
+                stack.extend(node.children());
+            }
+        }
+
+        (acceleration, potential)
+    }
+}
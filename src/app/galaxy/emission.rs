@@ -0,0 +1,102 @@
+use serde::Deserialize;
+
+use crate::config::Config;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum EmissionMode {
+    AllAtOnce,
+    Rate,
+    Burst,
+}
+
+impl EmissionMode {
+    pub const ALL: [EmissionMode; 3] = [EmissionMode::AllAtOnce, EmissionMode::Rate, EmissionMode::Burst];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            EmissionMode::AllAtOnce => "All At Once",
+            EmissionMode::Rate => "Rate",
+            EmissionMode::Burst => "Burst",
+        }
+    }
+}
+
+// Decides how many stars the galaxy spawns each frame
+#[derive(Debug)]
+pub struct Emitter {
+    pub mode: EmissionMode,
+    pub rate: f32, // stars per second
+    pub burst_size: usize,
+    pub target: usize,
+
+    emitted: usize, // Counts against the target
+    burst_emitted: usize,
+    accumulator: f32, // fractional stars carried between frames
+    pending_burst: usize,
+}
+
+impl Emitter {
+    pub fn new(config: &Config) -> Self {
+        Self {
+            mode: config.sim_config.emission_mode,
+            rate: config.sim_config.emission_rate,
+            burst_size: config.sim_config.burst_size,
+            target: config.sim_config.star_count as usize,
+            emitted: 0,
+            burst_emitted: 0,
+            accumulator: 0.0,
+            pending_burst: 0,
+        }
+    }
+
+    // Bursts are on demand and not capped by the target
+    pub fn burst(&mut self) {
+        self.pending_burst += self.burst_size;
+    }
+
+    pub fn reset(&mut self) {
+        self.emitted = 0;
+        self.burst_emitted = 0;
+        self.accumulator = 0.0;
+        self.pending_burst = 0;
+    }
+
+    pub fn emitted(&self) -> usize {
+        self.emitted
+    }
+
+    pub fn burst_emitted(&self) -> usize {
+        self.burst_emitted
+    }
+
+    // Stars to spawn this frame as (scheduled, burst), dt in seconds.
+    // Nothing counts as emitted until the galaxy records what it actually created
+    pub fn emit(&mut self, dt: f32) -> (usize, usize) {
+        let remaining = self.target.saturating_sub(self.emitted);
+
+        let scheduled = match self.mode {
+            EmissionMode::AllAtOnce => remaining,
+            EmissionMode::Rate => {
+                self.accumulator += self.rate * dt;
+                let whole = self.accumulator.floor();
+                self.accumulator -= whole;
+                (whole as usize).min(remaining)
+            },
+            EmissionMode::Burst => 0,
+        };
+
+        // Don't let a rate that was capped build up a backlog
+        if remaining == 0 {
+            self.accumulator = 0.0;
+        }
+
+        (scheduled, std::mem::take(&mut self.pending_burst))
+    }
+
+    // Stars created out of the last emit, fewer when the star buffer was full
+    pub fn record(&mut self, scheduled: usize, burst: usize) {
+        self.emitted += scheduled;
+        self.burst_emitted += burst;
+    }
+}
@@ -0,0 +1,32 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use crate::config::EvolutionConfig;
+
+/*
+    Stars only carry a mass (solar masses) and a birth time (Myr).
+    The track itself lives in render.wgsl, evaluated against Galaxy::time:
+        Main sequence: t_ms = 10 Gyr * M^-2.5, L = M^3.5, T = 5772K * M^0.475
+        Giant branch:  last 10% of the lifetime past t_ms, cooling towards 3500K while brightening
+        Remnant:       M < 8 cools as a white dwarf, heavier stars go dark
+*/
+
+// Salpeter-style power law IMF, dN/dM ~ M^-slope, sampled by inverting its CDF
+pub fn sample_mass(rng: &mut StdRng, config: &EvolutionConfig) -> f32 {
+    sample_power_law(rng, config.min_mass, config.max_mass, config.imf_slope)
+}
+
+pub fn sample_power_law(rng: &mut StdRng, min: f32, max: f32, slope: f32) -> f32 {
+    let k = 1.0 - slope;
+    let lo = min.powf(k);
+    let hi = max.powf(k);
+    (lo + rng.gen::<f32>() * (hi - lo)).powf(1.0 / k)
+}
+
+// Stars that exist from the start (now == 0) are spread over the past, later ones are born now
+pub fn sample_birth_time(rng: &mut StdRng, config: &EvolutionConfig, now: f32) -> f32 {
+    if now > 0.0 {
+        return now;
+    }
+    now - rng.gen::<f32>() * config.age_spread
+}
@@ -0,0 +1,119 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+use serde::Deserialize;
+
+use crate::config::ScenarioConfig;
+
+// Population ids, the renderer colors stars by these
+pub const POPULATION_HOST: u32 = 0;
+pub const POPULATION_SATELLITE: u32 = 1;
+
+// G = 1 in simulation units
+const SOFTENING: f32 = 1.0;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ScenarioKind {
+    Spiral,
+    TidalDisruption,
+}
+
+impl ScenarioKind {
+    pub const ALL: [ScenarioKind; 2] = [ScenarioKind::Spiral, ScenarioKind::TidalDisruption];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ScenarioKind::Spiral => "Spiral",
+            ScenarioKind::TidalDisruption => "Tidal Disruption",
+        }
+    }
+}
+
+// Plummer sphere potential, used for both the host and the satellite
+#[derive(Clone, Copy, Debug)]
+pub struct Plummer {
+    pub mass: f32,
+    pub scale_radius: f32,
+}
+
+impl Plummer {
+    pub fn acceleration(&self, center: [f32; 3], p: [f32; 3]) -> [f32; 3] {
+        let d = [center[0] - p[0], center[1] - p[1], center[2] - p[2]];
+        let r2 = d[0] * d[0] + d[1] * d[1] + d[2] * d[2] + self.scale_radius * self.scale_radius + SOFTENING;
+        let f = self.mass / (r2 * r2.sqrt());
+        [d[0] * f, d[1] * f, d[2] * f]
+    }
+
+    // Samples positions and velocities relative to the sphere's center (Aarseth, Henon & Wielen 1974)
+    pub fn sample(&self, rng: &mut StdRng) -> ([f32; 3], [f32; 3]) {
+        let x: f32 = rng.gen_range(1e-4..0.99); // enclosed mass fraction, capped to avoid r -> inf
+        let r = self.scale_radius / (x.powf(-2.0 / 3.0) - 1.0).sqrt();
+
+        // Rejection sample q = v / v_escape from g(q) = q^2 (1 - q^2)^3.5
+        let q = loop {
+            let q: f32 = rng.gen();
+            let g: f32 = rng.gen::<f32>() * 0.1;
+            if g < q * q * (1.0 - q * q).powf(3.5) {
+                break q;
+            }
+        };
+        let v_escape = (2.0 * self.mass / (r * r + self.scale_radius * self.scale_radius).sqrt()).sqrt();
+
+        (
+            Self::isotropic(rng, r),
+            Self::isotropic(rng, q * v_escape),
+        )
+    }
+
+    fn isotropic(rng: &mut StdRng, length: f32) -> [f32; 3] {
+        let cos_theta: f32 = rng.gen_range(-1.0..1.0);
+        let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+        let phi: f32 = rng.gen_range(0.0..std::f32::consts::TAU);
+        [length * sin_theta * phi.cos(), length * cos_theta, length * sin_theta * phi.sin()]
+    }
+}
+
+// A bound satellite falling through the host potential, its stars are test particles
+#[derive(Debug)]
+pub struct Satellite {
+    pub position: [f32; 3],
+    pub velocity: [f32; 3],
+    pub potential: Plummer,
+}
+
+impl Satellite {
+    pub fn new(config: &ScenarioConfig) -> Self {
+        Self {
+            position: config.satellite_position,
+            velocity: config.satellite_velocity,
+            potential: Plummer {
+                mass: config.satellite_mass,
+                scale_radius: config.satellite_scale_radius,
+            },
+        }
+    }
+
+    // Kick-drift-kick leapfrog of the satellite's center in the host potential
+    pub fn step(&mut self, host: &Plummer, dt: f32) {
+        leapfrog(&mut self.position, &mut self.velocity, dt, |p| host.acceleration([0.0; 3], p));
+    }
+
+    // Host pull plus the satellite's own (moving) potential
+    pub fn acceleration(&self, host: &Plummer, p: [f32; 3]) -> [f32; 3] {
+        let a = host.acceleration([0.0; 3], p);
+        let b = self.potential.acceleration(self.position, p);
+        [a[0] + b[0], a[1] + b[1], a[2] + b[2]]
+    }
+}
+
+pub fn leapfrog(position: &mut [f32; 3], velocity: &mut [f32; 3], dt: f32, acceleration: impl Fn([f32; 3]) -> [f32; 3]) {
+    let a = acceleration(*position);
+    for i in 0..3 {
+        velocity[i] += a[i] * dt * 0.5;
+        position[i] += velocity[i] * dt;
+    }
+    let a = acceleration(*position);
+    for i in 0..3 {
+        velocity[i] += a[i] * dt * 0.5;
+    }
+}
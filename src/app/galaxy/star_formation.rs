@@ -0,0 +1,137 @@
+use rand::Rng;
+use rand::rngs::StdRng;
+
+use crate::config::{Config, StarFormationConfig};
+
+use super::evolution;
+use super::scenario::POPULATION_HOST;
+use super::Star;
+
+/*
+    Gas surface density on a square grid in the galaxy plane (x/z), laid out along the same spiral
+    arms the initial stars follow. Each cell forms stars with a Kennicutt-Schmidt law,
+        dM/dt = M / t_dep * (Sigma / Sigma_ref)^0.4   (i.e. Sigma_sfr ~ Sigma^1.4)
+    and slowly relaxes back towards its initial density so formation keeps going.
+*/
+#[derive(Debug)]
+pub struct StarFormation {
+    pub enabled: bool,
+    pub depletion_time: f32, // Myr at the reference density
+    pub replenish_time: f32, // Myr
+
+    pub resolution: usize,
+    pub extent: f32, // grid spans [-extent, extent] on x and z
+    pub density: Vec<f32>, // gas mass per cell
+
+    pub reference_density: f32, // densest initial cell
+
+    initial_density: Vec<f32>,
+    formed: Vec<f32>, // mass formed but not yet spawned as a star, per cell
+
+    particle_mass: f32,
+    young_min_mass: f32,
+    young_max_mass: f32,
+    imf_slope: f32,
+    max_stars_per_frame: usize,
+}
+
+impl StarFormation {
+    pub fn new(config: &Config) -> Self {
+        let sf_config: &StarFormationConfig = &config.star_formation_config;
+        let resolution = sf_config.grid_resolution as usize;
+        let extent = config.sim_config.galaxy_radius * 1.1;
+        let cell_size = 2.0 * extent / resolution as f32;
+
+        let arm_count = config.sim_config.arm_count;
+        let arm_width = config.sim_config.noise_scale * 2.0;
+        let disk_scale = config.sim_config.galaxy_radius / 3.0;
+
+        let mut density: Vec<f32> = (0..resolution * resolution).map(|i| {
+            let x = -extent + ((i % resolution) as f32 + 0.5) * cell_size;
+            let z = -extent + ((i / resolution) as f32 + 0.5) * cell_size;
+            let r = (x * x + z * z).sqrt();
+            if r > config.sim_config.galaxy_radius {
+                return 0.0;
+            }
+
+            // Distance along the circle to the nearest arm
+            let theta = z.atan2(x);
+            let arm_distance = (0..arm_count).map(|arm| {
+                let arm_theta = (arm as f32 / arm_count as f32) * std::f32::consts::TAU + r * config.sim_config.spiralness;
+                let d = (theta - arm_theta).rem_euclid(std::f32::consts::TAU);
+                d.min(std::f32::consts::TAU - d) * r
+            }).fold(f32::MAX, f32::min);
+
+            let arm_profile = (-(arm_distance * arm_distance) / (2.0 * arm_width * arm_width)).exp();
+            (-r / disk_scale).exp() * (sf_config.interarm_fraction + arm_profile)
+        }).collect();
+
+        // Normalize to the configured total gas mass
+        let total: f32 = density.iter().sum();
+        if total > 0.0 {
+            density.iter_mut().for_each(|d| *d *= sf_config.gas_mass / total);
+        }
+        let reference_density = density.iter().cloned().fold(0.0, f32::max);
+
+        Self {
+            enabled: sf_config.enabled,
+            depletion_time: sf_config.depletion_time,
+            replenish_time: sf_config.replenish_time,
+            resolution,
+            extent,
+            initial_density: density.clone(),
+            density,
+            reference_density,
+            formed: vec![0.0; resolution * resolution],
+            particle_mass: sf_config.particle_mass,
+            young_min_mass: sf_config.young_min_mass,
+            young_max_mass: config.evolution_config.max_mass,
+            imf_slope: config.evolution_config.imf_slope,
+            max_stars_per_frame: sf_config.max_stars_per_frame as usize,
+        }
+    }
+
+    pub fn reset(&mut self) {
+        self.density.copy_from_slice(&self.initial_density);
+        self.formed.iter_mut().for_each(|f| *f = 0.0);
+    }
+
+    pub fn total_gas(&self) -> f32 {
+        self.density.iter().sum()
+    }
+
+    // Advances the gas by dt Myr and returns the stars born at `time`
+    pub fn step(&mut self, rng: &mut StdRng, dt: f32, time: f32) -> Vec<Star> {
+        let mut new_stars = Vec::new();
+        if !self.enabled || dt <= 0.0 || self.reference_density <= 0.0 {
+            return new_stars;
+        }
+
+        let cell_size = 2.0 * self.extent / self.resolution as f32;
+
+        for i in 0..self.density.len() {
+            let gas = self.density[i];
+            let rate = gas / self.depletion_time * (gas / self.reference_density).powf(0.4);
+            let formed = (rate * dt).min(gas);
+
+            self.density[i] += (self.initial_density[i] - self.density[i]) * (dt / self.replenish_time).min(1.0) - formed;
+            self.formed[i] += formed;
+
+            while self.formed[i] >= self.particle_mass && new_stars.len() < self.max_stars_per_frame {
+                self.formed[i] -= self.particle_mass;
+
+                let x = -self.extent + ((i % self.resolution) as f32 + rng.gen::<f32>()) * cell_size;
+                let z = -self.extent + ((i / self.resolution) as f32 + rng.gen::<f32>()) * cell_size;
+                new_stars.push(Star {
+                    position: [x, 0.0, z],
+                    population: POPULATION_HOST,
+                    velocity: [0.0; 3],
+                    mass: evolution::sample_power_law(rng, self.young_min_mass, self.young_max_mass, self.imf_slope),
+                    birth_time: time,
+                });
+            }
+        }
+
+        new_stars
+    }
+}
@@ -0,0 +1,369 @@
+use std::{cell::Cell, fmt::Write};
+
+use wgpu::*;
+use winit::dpi::PhysicalSize;
+
+use super::timestamps::{TimestampScope, Timestamps};
+
+/*
+    Small render graph for one frame. Passes are named, declare the resources they read and write and
+    run in the order they were added. The declarations validate that order, tell the dump where every
+    input comes from and decide which textures get recreated on resize.
+    Transient textures are owned by the graph and sized relative to the surface. Everything else
+    (the surface, buffers owned by the simulation and the renderer) is imported by name and only tracked,
+    imported textures get their view per frame.
+    Every pass gets a timestamp scope named after it, passes made of several timed parts (the post chain)
+    declare their scopes instead and get none of their own. Scope i owns queries 2i and 2i + 1, only the
+    scopes written this frame are resolved, the rest read back as None (skipped or disabled parts).
+    Passes are plain functions over the owner's state, the owner moves the graph out while it runs them.
+    Compute passes slot in the same way with PassKind::Compute, the simulation still integrates on the CPU
+    and writes the star buffer through the queue, so it only shows up as an imported buffer.
+*/
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PassKind {
+    Render,
+    Compute,
+}
+
+impl PassKind {
+    pub fn name(&self) -> &'static str {
+        match self {
+            PassKind::Render => "render",
+            PassKind::Compute => "compute",
+        }
+    }
+}
+
+// Graph owned texture, recreated at scale times the surface size
+pub struct TransientTexture {
+    pub name: &'static str,
+    pub format: TextureFormat,
+    pub usage: TextureUsages,
+    pub scale: f32,
+}
+
+enum Resource {
+    Transient {
+        desc: TransientTexture,
+        texture: Texture,
+        view: TextureView,
+    },
+    ImportedTexture(&'static str),
+    ImportedBuffer(&'static str),
+}
+
+impl Resource {
+    fn name(&self) -> &'static str {
+        match self {
+            Resource::Transient { desc, .. } => desc.name,
+            Resource::ImportedTexture(name) | Resource::ImportedBuffer(name) => name,
+        }
+    }
+}
+
+pub struct GraphResources {
+    resources: Vec<Resource>,
+    size: PhysicalSize<u32>,
+}
+
+impl GraphResources {
+    pub fn size(&self) -> PhysicalSize<u32> {
+        self.size
+    }
+
+    fn find(&self, name: &str) -> Option<&Resource> {
+        self.resources.iter().find(|resource| resource.name() == name)
+    }
+
+    // Only transient textures are owned here, panics on anything else
+    pub fn texture(&self, name: &str) -> &Texture {
+        match self.find(name) {
+            Some(Resource::Transient { texture, .. }) => texture,
+            _ => panic!("Render graph has no transient texture {}", name),
+        }
+    }
+
+    pub fn view(&self, name: &str) -> &TextureView {
+        match self.find(name) {
+            Some(Resource::Transient { view, .. }) => view,
+            _ => panic!("Render graph has no transient texture {}", name),
+        }
+    }
+}
+
+pub struct PassDescriptor<C> {
+    pub name: &'static str,
+    pub kind: PassKind,
+    pub reads: Vec<&'static str>,
+    pub writes: Vec<&'static str>,
+    // Timestamp scopes replacing the pass's own
+    pub scopes: Vec<&'static str>,
+    pub execute: fn(&mut C, &mut PassContext),
+    // After the transient textures were recreated, for anything bound to them
+    pub resize: Option<fn(&mut C, &GraphResources)>,
+}
+
+pub struct PassContext<'a> {
+    pub encoder: &'a mut CommandEncoder,
+    // The pass's own scope, None without timestamp queries or with declared scopes
+    pub timestamps: Option<TimestampScope<'a>>,
+    // Last measured time per scope, None when it wasn't written that frame
+    pub times: &'a [(&'static str, Option<f64>)],
+
+    resources: &'a GraphResources,
+    imports: &'a [(&'static str, &'a TextureView)],
+    query_set: Option<&'a QuerySet>,
+    scopes: &'a [&'static str],
+    written: &'a [Cell<bool>],
+}
+
+impl<'a> PassContext<'a> {
+    pub fn texture(&self, name: &str) -> &'a Texture {
+        self.resources.texture(name)
+    }
+
+    // Imported views for this frame first, then the transient textures
+    pub fn view(&self, name: &str) -> &'a TextureView {
+        match self.imports.iter().find(|(import, _)| *import == name) {
+            Some((_, view)) => view,
+            None => self.resources.view(name),
+        }
+    }
+
+    // One of the scopes the pass declared
+    pub fn scope(&self, name: &str) -> Option<TimestampScope<'a>> {
+        find_scope(self.query_set?, self.scopes, self.written, name)
+    }
+}
+
+fn find_scope<'a>(query_set: &'a QuerySet, scopes: &[&'static str], written: &'a [Cell<bool>], name: &str) -> Option<TimestampScope<'a>> {
+    let index = scopes.iter().position(|scope| *scope == name)?;
+    Some(TimestampScope {
+        query_set,
+        begin: 2 * index as u32,
+        end: 2 * index as u32 + 1,
+        written: &written[index],
+    })
+}
+
+// The pass's own scope unless it declared its own set
+fn pass_scopes<C>(pass: &PassDescriptor<C>) -> Vec<&'static str> {
+    if pass.scopes.is_empty() { vec![pass.name] } else { pass.scopes.clone() }
+}
+
+fn create_transient_texture(device: &Device, desc: &TransientTexture, size: &PhysicalSize<u32>) -> (Texture, TextureView) {
+    let texture = device.create_texture(&TextureDescriptor {
+        label: Some(desc.name),
+        size: Extent3d {
+            width: ((size.width as f32 * desc.scale) as u32).max(1),
+            height: ((size.height as f32 * desc.scale) as u32).max(1),
+            depth_or_array_layers: 1
+        },
+        mip_level_count: 1,
+        sample_count: 1,
+        dimension: TextureDimension::D2,
+        format: desc.format,
+        usage: desc.usage,
+        view_formats: &[]
+    });
+    let view = texture.create_view(&TextureViewDescriptor::default());
+
+    (texture, view)
+}
+
+// What the graph needs from the state its passes run on
+pub trait GraphContext {
+    fn device(&self) -> &Device;
+}
+
+pub struct RenderGraph<C> {
+    resources: GraphResources,
+    passes: Vec<PassDescriptor<C>>,
+
+    scopes: Vec<&'static str>,
+    timestamps: Option<Timestamps>,
+    times: Vec<(&'static str, Option<f64>)>,
+}
+
+// Empty placeholder, left behind while the real graph is moved out to run
+impl<C> Default for RenderGraph<C> {
+    fn default() -> Self {
+        Self::new(&PhysicalSize::new(0, 0))
+    }
+}
+
+impl<C> RenderGraph<C> {
+    pub fn new(size: &PhysicalSize<u32>) -> Self {
+        Self {
+            resources: GraphResources {
+                resources: Vec::new(),
+                size: *size,
+            },
+            passes: Vec::new(),
+
+            scopes: Vec::new(),
+            timestamps: None,
+            times: Vec::new(),
+        }
+    }
+
+    pub fn add_texture(&mut self, device: &Device, desc: TransientTexture) {
+        let (texture, view) = create_transient_texture(device, &desc, &self.resources.size);
+        self.resources.resources.push(Resource::Transient { desc, texture, view });
+    }
+
+    pub fn import_texture(&mut self, name: &'static str) {
+        self.resources.resources.push(Resource::ImportedTexture(name));
+    }
+
+    pub fn import_buffer(&mut self, name: &'static str) {
+        self.resources.resources.push(Resource::ImportedBuffer(name));
+    }
+
+    pub fn add_pass(&mut self, pass: PassDescriptor<C>) {
+        self.passes.push(pass);
+    }
+
+    pub fn resources(&self) -> &GraphResources {
+        &self.resources
+    }
+
+    // Validates the declared accesses and hands out the timestamp scopes, call after the last add_pass
+    pub fn compile(&mut self, device: &Device, queue: &Queue) {
+        let mut written: Vec<&str> = Vec::new();
+        for pass in &self.passes {
+            for name in pass.reads.iter().chain(&pass.writes) {
+                if self.resources.find(name).is_none() {
+                    panic!("Render graph pass {} uses undeclared resource {}", pass.name, name);
+                }
+            }
+            for name in &pass.reads {
+                let transient = matches!(self.resources.find(name), Some(Resource::Transient { .. }));
+                if transient && !written.contains(name) {
+                    log::warn!("Render graph pass {} reads {} before any pass writes it", pass.name, name);
+                }
+            }
+            written.extend(&pass.writes);
+        }
+
+        self.scopes = self.passes.iter().flat_map(pass_scopes).collect();
+        for (index, scope) in self.scopes.iter().enumerate() {
+            if self.scopes[..index].contains(scope) {
+                log::warn!("Render graph timestamp scope {} is declared more than once, only the first is measured", scope);
+            }
+        }
+
+        // The fallback adapter path doesn't request timestamp queries
+        let supported = device.features().contains(Features::TIMESTAMP_QUERY);
+        self.timestamps = (supported && !self.scopes.is_empty()).then(|| Timestamps::new(device, queue, self.scopes.len() as u32));
+        self.times = self.scopes.iter().map(|scope| (*scope, None)).collect();
+
+        log::debug!("{}", self.dump());
+    }
+
+    pub fn after_submit(&mut self, device: &mut Device) {
+        if let Some(timestamps) = &mut self.timestamps {
+            timestamps.update_times(device);
+        }
+    }
+
+    // Passes in order with their accesses, where each input was last written and the resources
+    pub fn dump(&self) -> String {
+        let mut out = String::new();
+        writeln!(out, "Render graph, {} passes at {}x{}", self.passes.len(), self.resources.size.width, self.resources.size.height).unwrap();
+
+        writeln!(out, "Resources:").unwrap();
+        for resource in &self.resources.resources {
+            match resource {
+                Resource::Transient { desc, texture, .. } => writeln!(out, "  {} texture {:?} {}x{} (transient, scale {})", desc.name, desc.format, texture.width(), texture.height(), desc.scale),
+                Resource::ImportedTexture(name) => writeln!(out, "  {} texture (imported)", name),
+                Resource::ImportedBuffer(name) => writeln!(out, "  {} buffer (imported)", name),
+            }.unwrap();
+        }
+
+        writeln!(out, "Passes:").unwrap();
+        for (index, pass) in self.passes.iter().enumerate() {
+            let scopes: Vec<String> = pass_scopes(pass).into_iter()
+                .filter_map(|scope| self.scopes.iter().position(|s| *s == scope).map(|i| format!("{} {}/{}", scope, 2 * i, 2 * i + 1)))
+                .collect();
+            let timestamps = if self.timestamps.is_some() { scopes.join(", ") } else { "off".to_string() };
+            writeln!(out, "  {} {} ({}), timestamps: {}", index, pass.name, pass.kind.name(), timestamps).unwrap();
+
+            for name in &pass.reads {
+                let source = match self.passes[..index].iter().rev().find(|earlier| earlier.writes.contains(name)) {
+                    Some(earlier) => earlier.name,
+                    None if matches!(self.resources.find(name), Some(Resource::Transient { .. })) => "nothing",
+                    None => "imported",
+                };
+                writeln!(out, "    reads  {} <- {}", name, source).unwrap();
+            }
+            for name in &pass.writes {
+                writeln!(out, "    writes {}", name).unwrap();
+            }
+        }
+
+        out
+    }
+}
+
+impl<C: GraphContext> RenderGraph<C> {
+    // Recreates the transient textures at the new size, then lets every pass rebind them in order
+    pub fn resize(&mut self, ctx: &mut C, size: &PhysicalSize<u32>) {
+        self.resources.size = *size;
+
+        let device = ctx.device();
+        for resource in &mut self.resources.resources {
+            if let Resource::Transient { desc, texture, view } = resource {
+                texture.destroy();
+                (*texture, *view) = create_transient_texture(device, desc, size);
+            }
+        }
+
+        for pass in &self.passes {
+            if let Some(resize) = pass.resize {
+                resize(ctx, &self.resources);
+            }
+        }
+    }
+
+    // Records every pass into one command buffer, imports are the views only valid this frame (the surface)
+    pub fn execute(&mut self, ctx: &mut C, imports: &[(&'static str, &TextureView)]) -> CommandBuffer {
+        if let Some(timestamps) = &self.timestamps {
+            let last_frame_times = timestamps.last_frame_times.lock().unwrap();
+            // In microseconds
+            for ((_, time), ticks) in self.times.iter_mut().zip(last_frame_times.iter()) {
+                *time = ticks.map(|ticks| ticks as f64 * timestamps.period as f64 / 1000.0);
+            }
+        }
+
+        let mut encoder = ctx.device().create_command_encoder(&CommandEncoderDescriptor {
+            label: Some("Render Graph Encoder"),
+        });
+
+        let query_set = self.timestamps.as_ref().map(|timestamps| &timestamps.query_set);
+        let written = vec![Cell::new(false); self.scopes.len()];
+        for pass in &self.passes {
+            let own = query_set.filter(|_| pass.scopes.is_empty());
+            let mut pass_ctx = PassContext {
+                encoder: &mut encoder,
+                timestamps: own.and_then(|query_set| find_scope(query_set, &self.scopes, &written, pass.name)),
+                times: &self.times,
+
+                resources: &self.resources,
+                imports,
+                query_set,
+                scopes: &self.scopes,
+                written: &written,
+            };
+            (pass.execute)(ctx, &mut pass_ctx);
+        }
+
+        if let Some(timestamps) = &mut self.timestamps {
+            let written: Vec<bool> = written.iter().map(Cell::get).collect();
+            timestamps.resolve(&mut encoder, ctx.device(), &written);
+        }
+
+        encoder.finish()
+    }
+}
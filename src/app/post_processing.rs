@@ -1,2 +1,8 @@
 pub mod bloom;
-pub mod present;
\ No newline at end of file
+pub mod lens_dirt;
+pub mod present;
+pub mod auto_exposure;
+pub mod film_grain;
+pub mod lens;
+pub mod color_grading;
+pub mod chain;
@@ -0,0 +1,357 @@
+use std::{collections::VecDeque, sync::{Arc, Mutex}};
+
+use wgpu::*;
+use bytemuck::{Pod, Zeroable};
+
+use crate::config::Config;
+use crate::app::timestamps::TimestampScope;
+use super::chain::{EffectContext, PostEffect, PostEffectKind};
+
+/*
+    Builds a log-luminance histogram of the HDR scene in a compute pass,
+    reduces it to an average and adapts the exposure over time.
+    The result stays on the GPU in exposure_buffer, which Present reads directly.
+    Only measures its input, so it doesn't advance the chain's ping-pong textures.
+*/
+
+pub const HISTOGRAM_BINS: usize = 256;
+const HISTOGRAM_SIZE: u64 = (HISTOGRAM_BINS * 4) as u64;
+const STATE_SIZE: u64 = std::mem::size_of::<ExposureState>() as u64;
+const WORKGROUP_SIZE: u32 = 16;
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct AutoExposureUniform {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    dt: f32,
+    speed_up: f32,
+    speed_down: f32,
+    key: f32,
+    _padding: [f32; 2],
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable, Default, Debug)]
+pub struct ExposureState {
+    pub luminance: f32,
+    pub exposure: f32,
+}
+
+pub struct AutoExposure {
+    pub exposure_buffer: Buffer,
+    histogram_buffer: Buffer,
+    settings_buffer: Buffer,
+
+    bindgroup_layout: BindGroupLayout,
+    bindgroups: [BindGroup; 2],
+    build_pipeline: ComputePipeline,
+    average_pipeline: ComputePipeline,
+
+    // Readback for the UI, histogram followed by the exposure state
+    pub last_histogram: Arc<Mutex<[u32; HISTOGRAM_BINS]>>,
+    pub last_state: Arc<Mutex<ExposureState>>,
+    unmapped_ring: Arc<Mutex<VecDeque<Buffer>>>,
+
+    // Settings
+    pub enabled: bool,
+    pub min_ev: f32,
+    pub max_ev: f32,
+    pub speed_up: f32,
+    pub speed_down: f32,
+    pub key: f32,
+}
+
+impl AutoExposure {
+    pub fn new(device: &Device, queue: &Queue, config: &Config, inputs: &[TextureView; 2]) -> Self {
+        let auto_exposure_config = &config.auto_exposure_config;
+
+        let histogram_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Luminance Histogram Buffer"),
+            size: HISTOGRAM_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC,
+            mapped_at_creation: false
+        });
+
+        let exposure_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Exposure State Buffer"),
+            size: STATE_SIZE,
+            usage: BufferUsages::STORAGE | BufferUsages::COPY_SRC | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+        // Start adapted to middle grey, i.e. an exposure of 1
+        queue.write_buffer(&exposure_buffer, 0, bytemuck::cast_slice(&[ExposureState {
+            luminance: auto_exposure_config.key,
+            exposure: 1.0,
+        }]));
+
+        let settings_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Auto Exposure Settings Uniform"),
+            size: std::mem::size_of::<AutoExposureUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Auto Exposure Bindgroup Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None
+                    },
+                    count: None
+                },
+            ]
+        });
+
+        let bindgroups = Self::create_bind_groups(device, &bindgroup_layout, inputs, &histogram_buffer, &exposure_buffer, &settings_buffer);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Auto Exposure Pipeline Layout"),
+            bind_group_layouts: &[
+                &bindgroup_layout
+            ],
+            push_constant_ranges: &[]
+        });
+
+        let shader = device.create_shader_module(include_wgsl!("../shaders/luminance_histogram.wgsl"));
+
+        let build_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Luminance Histogram Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "build_histogram",
+        });
+
+        let average_pipeline = device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some("Luminance Average Pipeline"),
+            layout: Some(&pipeline_layout),
+            module: &shader,
+            entry_point: "average_histogram",
+        });
+
+        Self {
+            exposure_buffer,
+            histogram_buffer,
+            settings_buffer,
+
+            bindgroup_layout,
+            bindgroups,
+            build_pipeline,
+            average_pipeline,
+
+            last_histogram: Arc::new(Mutex::new([0; HISTOGRAM_BINS])),
+            last_state: Arc::new(Mutex::new(ExposureState::default())),
+            unmapped_ring: Arc::new(Mutex::new(VecDeque::new())),
+
+            enabled: auto_exposure_config.enabled,
+            min_ev: auto_exposure_config.min_ev,
+            max_ev: auto_exposure_config.max_ev,
+            speed_up: auto_exposure_config.speed_up,
+            speed_down: auto_exposure_config.speed_down,
+            key: auto_exposure_config.key,
+        }
+    }
+
+    fn create_bind_groups(device: &Device, layout: &BindGroupLayout, inputs: &[TextureView; 2], histogram_buffer: &Buffer, exposure_buffer: &Buffer, settings_buffer: &Buffer) -> [BindGroup; 2] {
+        inputs.each_ref().map(|hdr_view| device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Auto Exposure Bindgroup"),
+            layout: layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(hdr_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: histogram_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: exposure_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+            ]
+        }))
+    }
+
+    fn get_readback_buffer_descriptor() -> BufferDescriptor<'static> {
+        BufferDescriptor {
+            label: Some("Luminance Histogram Mapped Buffer"),
+            size: HISTOGRAM_SIZE + STATE_SIZE,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false
+        }
+    }
+
+    // Call after submitting, same ring scheme as Timestamps
+    fn update_readback(&mut self) {
+        if !self.enabled {
+            return;
+        }
+
+        let Some(buffer) = self.unmapped_ring.lock().unwrap().pop_front() else {
+            return;
+        };
+        let buffer_to_map = Arc::new(buffer);
+        let last_histogram = self.last_histogram.clone();
+        let last_state = self.last_state.clone();
+        let unmapped_ring = self.unmapped_ring.clone();
+
+        buffer_to_map.clone().slice(..).map_async(MapMode::Read, move |res| {
+            if res.is_ok() {
+                let buffer_view = buffer_to_map.slice(..).get_mapped_range();
+                let (histogram, state) = buffer_view.split_at(HISTOGRAM_SIZE as usize);
+                last_histogram.lock().unwrap().copy_from_slice(bytemuck::cast_slice(histogram));
+                *last_state.lock().unwrap() = *bytemuck::from_bytes(state);
+                drop(buffer_view);
+                buffer_to_map.unmap();
+            }
+            unmapped_ring.lock().unwrap().push_back(Arc::into_inner(buffer_to_map).unwrap());
+        });
+    }
+
+    // Bar chart of the luminance histogram, bin 0 (black) is skipped since it dwarfs the rest
+    fn draw_histogram(ui: &mut egui::Ui, histogram: &[u32]) {
+        let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 60.0), egui::Sense::hover());
+        let rect = response.rect;
+        painter.rect_filled(rect, 0.0, egui::Color32::from_gray(20));
+
+        let bins = &histogram[1..];
+        let max = bins.iter().cloned().max().unwrap_or(0).max(1) as f32;
+        let bar_width = rect.width() / bins.len() as f32;
+        for (i, count) in bins.iter().enumerate() {
+            let height = rect.height() * (*count as f32 / max);
+            let x = rect.left() + i as f32 * bar_width;
+            painter.rect_filled(
+                egui::Rect::from_min_max(egui::pos2(x, rect.bottom() - height), egui::pos2(x + bar_width, rect.bottom())),
+                0.0,
+                egui::Color32::LIGHT_GRAY,
+            );
+        }
+    }
+}
+
+impl PostEffect for AutoExposure {
+    fn kind(&self) -> PostEffectKind {
+        PostEffectKind::AutoExposure
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn writes_output(&self) -> bool {
+        false
+    }
+
+    fn resize(&mut self, ctx: &EffectContext) {
+        self.bindgroups = Self::create_bind_groups(ctx.device, &self.bindgroup_layout, ctx.inputs, &self.histogram_buffer, &self.exposure_buffer, &self.settings_buffer);
+    }
+
+    fn update(&mut self, ctx: &EffectContext, dt: f32) {
+        ctx.queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[AutoExposureUniform {
+            min_log_luminance: self.min_ev,
+            log_luminance_range: (self.max_ev - self.min_ev).max(0.01),
+            dt,
+            speed_up: self.speed_up,
+            speed_down: self.speed_down,
+            key: self.key,
+            _padding: [0.0; 2],
+        }]));
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, ctx: &EffectContext, timestamps: Option<TimestampScope>, input: usize, _output: &TextureView) {
+        let mut unmapped_ring = self.unmapped_ring.lock().unwrap();
+        if unmapped_ring.is_empty() {
+            unmapped_ring.push_back(ctx.device.create_buffer(&Self::get_readback_buffer_descriptor()));
+        }
+        let readback = unmapped_ring.front().unwrap();
+
+        encoder.push_debug_group("Auto Exposure");
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Luminance Histogram Pass"),
+                timestamp_writes: timestamps.map(|scope| scope.compute_writes(true, false))
+            });
+            compute_pass.set_pipeline(&self.build_pipeline);
+            compute_pass.set_bind_group(0, &self.bindgroups[input], &[]);
+            compute_pass.dispatch_workgroups(ctx.size.width.div_ceil(WORKGROUP_SIZE), ctx.size.height.div_ceil(WORKGROUP_SIZE), 1);
+        }
+
+        // The average pass clears the histogram, so grab it for the UI first
+        encoder.copy_buffer_to_buffer(&self.histogram_buffer, 0, readback, 0, HISTOGRAM_SIZE);
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Luminance Average Pass"),
+                timestamp_writes: timestamps.map(|scope| scope.compute_writes(false, true))
+            });
+            compute_pass.set_pipeline(&self.average_pipeline);
+            compute_pass.set_bind_group(0, &self.bindgroups[input], &[]);
+            compute_pass.dispatch_workgroups(1, 1, 1);
+        }
+
+        encoder.copy_buffer_to_buffer(&self.exposure_buffer, 0, readback, HISTOGRAM_SIZE, STATE_SIZE);
+        encoder.pop_debug_group();
+    }
+
+    fn after_submit(&mut self) {
+        self.update_readback();
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, _ctx: &EffectContext) {
+        ui.add(egui::Slider::new(&mut self.min_ev, -16.0..=0.0).text("Min EV"));
+        ui.add(egui::Slider::new(&mut self.max_ev, 0.0..=16.0).text("Max EV"));
+        ui.add(egui::Slider::new(&mut self.speed_up, 0.1..=10.0).text("Speed Up"));
+        ui.add(egui::Slider::new(&mut self.speed_down, 0.1..=10.0).text("Speed Down"));
+        ui.add(egui::Slider::new(&mut self.key, 0.01..=1.0).text("Key"));
+
+        if self.enabled {
+            let state = *self.last_state.lock().unwrap();
+            ui.label(format!("Average Luminance: {:.4}, Exposure: {:.2}", state.luminance, state.exposure));
+            Self::draw_histogram(ui, &self.last_histogram.lock().unwrap()[..]);
+        }
+    }
+}
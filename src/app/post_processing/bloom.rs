@@ -1,39 +1,134 @@
 use wgpu::*;
 use winit::dpi::PhysicalSize;
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
 
 use crate::{config::Config};
+use crate::app::timestamps::TimestampScope;
+use super::chain::{EffectContext, PostEffect, PostEffectKind};
 
 
 // Contains the Pipelines and Resources used for Bloom
 
 /*
-TODO
-    Blend the bloom shader
-    Find good filter size
-    Add UI controls
+    Chain input (full res)
+        -> prefilter (threshold + soft knee) + downsample into mip 0 (half res)
+        -> downsample mip i into mip i + 1
+        -> upsample mip i additively into mip i - 1, bloom ends up in mip 0
+        -> composite input + bloom * intensity into the chain output
+    Mip 0 stays readable after the chain moved on, lens dirt (its own effect) samples it.
+
+    Each mip is scaled by its weight and tint, mip i - 1 through the blend constant while mip i is added onto it,
+    so the weights stay independent instead of compounding down the chain.
+
+    The compute path does the same in a single compute pass, but upsamples into a separate up chain
+    (a storage write can't blend or read its own mip), whose mip 0 is copied back into the mipchain.
 */
 
-pub const MIP_LEVELS: usize = 5;
+pub const MAX_MIP_LEVELS: usize = 12;
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct MipUniform {
+    scale: [f32; 4],
+    target_scale: [f32; 4], // Compute path only, raster uses the blend constant
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum BloomMode {
+    Raster,
+    Compute,
+}
+
+impl BloomMode {
+    pub const ALL: [BloomMode; 2] = [BloomMode::Raster, BloomMode::Compute];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            BloomMode::Raster => "Raster",
+            BloomMode::Compute => "Compute",
+        }
+    }
+}
+
+const COMPUTE_TILE: u32 = 8;
+
+// Rgba16Float bits of (1024, 1024, 1024, 1)
+const HOT_PIXEL: [u16; 4] = [0x6400, 0x6400, 0x6400, 0x3C00];
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct BloomUniform {
+    filter_size: f32,
+    threshold: f32,
+    soft_knee: f32,
+    intensity: f32,
+    karis_average: u32,
+    _padding: [u32; 3],
+}
+
 pub struct Bloom {
+    format: TextureFormat,
+
     pub mipchain: wgpu::Texture,
     pub mipchain_views: Vec<TextureView>,
+    mip_count: usize,
+    upchain: wgpu::Texture,
+    upchain_views: Vec<TextureView>,
 
     sampler: Sampler,
     sampling_bindgroup_layout: BindGroupLayout,
     sampling_bindgroups: Vec<BindGroup>,
+    input_sampling_bindgroups: [BindGroup; 2],
 
-    downsample_pipeline: wgpu::RenderPipeline,
+    settings: wgpu::Buffer,
+    settings_bindgroup: wgpu::BindGroup,
+
+    mip_settings: wgpu::Buffer,
+    mip_settings_stride: u64,
+    mip_settings_bindgroup: wgpu::BindGroup,
 
+    prefilter_pipeline: wgpu::RenderPipeline,
+    downsample_pipeline: wgpu::RenderPipeline,
     upsample_pipeline: wgpu::RenderPipeline,
-    upsample_settings: wgpu::Buffer,
-    upsample_settings_bindgroup: wgpu::BindGroup,
+
+    composite_pipeline: wgpu::RenderPipeline,
+    composite_bindgroup_layout: BindGroupLayout,
+    composite_bindgroups: [BindGroup; 2],
+
+    compute_downsample_layout: BindGroupLayout,
+    compute_upsample_layout: BindGroupLayout,
+    compute_prefilter_bindgroups: [BindGroup; 2],
+    compute_downsample_bindgroups: Vec<BindGroup>,
+    compute_upsample_bindgroups: Vec<BindGroup>,
+    compute_prefilter_pipeline: ComputePipeline,
+    compute_downsample_pipeline: ComputePipeline,
+    compute_upsample_pipeline: ComputePipeline,
+
+    hot_pixel_buffer: wgpu::Buffer,
+    hot_pixel_frame: u32,
 
     // Settings
+    pub enabled: bool,
+    pub mode: BloomMode,
     pub filter_size: f32,
+    pub threshold: f32,
+    pub soft_knee: f32,
+    pub intensity: f32,
+    pub karis_average: bool,
+    pub hot_pixel_test: bool, // Replaces the scene with a single hot pixel moving sub-pixel, to check for fireflies
+    pub mip_levels: u32, // 0 derives the count from the resolution
+    pub min_mip_size: u32,
+    pub mip_weights: [f32; MAX_MIP_LEVELS],
+    pub mip_tints: [[f32; 3]; MAX_MIP_LEVELS],
 }
 
 impl Bloom {
-    pub fn new(device: &Device, _config: &Config, format: TextureFormat, size: &PhysicalSize<u32>) -> Bloom {
+    pub fn new(device: &Device, config: &Config, format: TextureFormat, size: &PhysicalSize<u32>, inputs: &[TextureView; 2]) -> Bloom {
+        let bloom_config = &config.bloom_config;
+        let mip_count = Self::mip_count_for(bloom_config.mip_levels, bloom_config.min_mip_size, size);
+
         let sampler = device.create_sampler(&SamplerDescriptor {
             label: Some("Downsample Sampler"),
             mag_filter: wgpu::FilterMode::Linear,
@@ -41,7 +136,8 @@ impl Bloom {
             ..Default::default()
         });
 
-        let (mipchain, mipchain_views) =  Bloom::create_mipchain_and_views(device, format, size);
+        let (mipchain, mipchain_views) =  Bloom::create_mipchain_and_views(device, format, size, mip_count, "bloom_mipmap");
+        let (upchain, upchain_views) =  Bloom::create_mipchain_and_views(device, format, size, mip_count, "bloom_upchain");
 
         let sampling_bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
             label: Some("Bloom Sampling Bindgroup Layout"), //Same bindgroup for downsample and upsample
@@ -66,23 +162,110 @@ impl Bloom {
         });
 
         let sampling_bindgroups = Self::create_sampling_bindgroups(device, &sampler, &sampling_bindgroup_layout, &mipchain_views);
-        
-        
+        let input_sampling_bindgroups = inputs.each_ref().map(|view| Self::create_sampling_bindgroup(device, &sampler, &sampling_bindgroup_layout, view));
+
+        /*
+            Settings, shared by every bloom shader
+            Uniforms: Filter Radius, Threshold, Soft Knee, Intensity
+        */
+        let settings = device.create_buffer(&BufferDescriptor { 
+            label: Some("Bloom Settings Uniform"),
+            size: std::mem::size_of::<BloomUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let settings_bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Bloom Settings Bindgroup Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    ty: BindingType::Buffer { 
+                        ty: BufferBindingType::Uniform, 
+                        has_dynamic_offset: false, 
+                        min_binding_size: None,
+                    },
+                    count: None,
+                    visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE
+                },
+            ]
+        });
+
+        let settings_bindgroup = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Bloom Settings Bindgroup"),
+            layout: &settings_bindgroup_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &settings,
+                        offset: 0,
+                        size: None
+                    })
+                }
+            ],
+        });
+
+        /*
+            Per mip settings, one aligned slot per mip selected with a dynamic offset
+            Uniforms: Weight * Tint
+        */
+        let mip_settings_stride = (std::mem::size_of::<MipUniform>() as u64).next_multiple_of(device.limits().min_uniform_buffer_offset_alignment as u64);
+        let mip_settings = device.create_buffer(&BufferDescriptor {
+            label: Some("Bloom Mip Settings Uniform"),
+            size: mip_settings_stride * MAX_MIP_LEVELS as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let mip_settings_bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Bloom Mip Settings Bindgroup Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: true,
+                        min_binding_size: BufferSize::new(std::mem::size_of::<MipUniform>() as u64),
+                    },
+                    count: None,
+                    visibility: ShaderStages::FRAGMENT | ShaderStages::COMPUTE
+                },
+            ]
+        });
+
+        let mip_settings_bindgroup = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Bloom Mip Settings Bindgroup"),
+            layout: &mip_settings_bindgroup_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::Buffer(BufferBinding {
+                        buffer: &mip_settings,
+                        offset: 0,
+                        size: BufferSize::new(std::mem::size_of::<MipUniform>() as u64)
+                    })
+                }
+            ],
+        });
+
         /*
             Downsample
+            The first pass reads the scene and applies the threshold
         */
-        let downsample_shader = device.create_shader_module(include_wgsl!("../shaders/downsample.wgsl"));
+        let downsample_shader = Self::create_karis_shader(device, "downsample.wgsl", include_str!("../shaders/downsample.wgsl"));
         
         let downsample_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Bloom Downsample Pipeline Descriptor"),
             bind_group_layouts: &[
                 &sampling_bindgroup_layout,
+                &settings_bindgroup_layout,
             ],
             push_constant_ranges: &[]
         });
 
-        let downsample_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
-            label: Some("Bloom Downsample Pipeline"),
+        let create_downsample_pipeline = |label: &str, entry_point: &str| device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some(label),
             layout: Some(&downsample_pipeline_layout),
             vertex: VertexState {
                 module: &downsample_shader,
@@ -91,7 +274,7 @@ impl Bloom {
             },
             fragment: Some(FragmentState {
                 module: &downsample_shader,
-                entry_point: "fs",
+                entry_point,
                 targets: &[
                     Some(ColorTargetState {
                         format,
@@ -106,57 +289,22 @@ impl Bloom {
             multiview: None
         });
 
+        let prefilter_pipeline = create_downsample_pipeline("Bloom Prefilter Pipeline", "fs_prefilter");
+        let downsample_pipeline = create_downsample_pipeline("Bloom Downsample Pipeline", "fs");
+
 
         /*
             Upsampling
-            Takes a texture mip as input, render target is the next biggest mip, until ful res
-            Uniforms: Filter Radius
+            Takes a texture mip as input, render target is the next biggest mip, until mip 0
         */
         let upsample_shader = device.create_shader_module(include_wgsl!("../shaders/upsample.wgsl"));
 
-        let upsample_settings = device.create_buffer(&BufferDescriptor { 
-            label: Some("Upsample Settings Uniform"),
-            size: 4,
-            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
-            mapped_at_creation: false
-        });
-
-        let upsample_settings_bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Upsample Settings Bindgroup Layout"),
-            entries: &[
-                BindGroupLayoutEntry {
-                    binding: 0,
-                    ty: BindingType::Buffer { 
-                        ty: BufferBindingType::Uniform, 
-                        has_dynamic_offset: false, 
-                        min_binding_size: None,
-                    },
-                    count: None,
-                    visibility: ShaderStages::FRAGMENT
-                },
-            ]
-        });
-
-        let upsample_settings_bindgroup = device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Bloom Upsample Settings Bindgroup"),
-            layout: &upsample_settings_bindgroup_layout,
-            entries: &[
-                BindGroupEntry {
-                    binding: 0,
-                    resource: BindingResource::Buffer(BufferBinding {
-                        buffer: &upsample_settings,
-                        offset: 0,
-                        size: None
-                    })
-                }
-            ],
-        });
-
         let upsample_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Upsample Pipeline Layout"),
             bind_group_layouts: &[
                 &sampling_bindgroup_layout,
-                &upsample_settings_bindgroup_layout
+                &settings_bindgroup_layout,
+                &mip_settings_bindgroup_layout
             ],
             push_constant_ranges: &[],
         });
@@ -179,7 +327,7 @@ impl Bloom {
                         color: BlendComponent {
                             operation: BlendOperation::Add,
                             src_factor: BlendFactor::One,
-                            dst_factor: BlendFactor::One
+                            dst_factor: BlendFactor::Constant
                         },
                         alpha: BlendComponent {
                             operation: BlendOperation::Add,
@@ -195,42 +343,288 @@ impl Bloom {
             multiview: None
         });
 
+        /*
+            Composite
+            Scene + bloom * intensity, at full resolution
+        */
+        let composite_shader = device.create_shader_module(include_wgsl!("../shaders/bloom_composite.wgsl"));
+
+        let composite_bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Bloom Composite Bindgroup Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        view_dimension: TextureViewDimension::D2,
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None
+                }
+            ]
+        });
+
+        let composite_bindgroups = inputs.each_ref().map(|view| Self::create_composite_bindgroup(device, &sampler, &composite_bindgroup_layout, view, &mipchain_views[0]));
+
+        let composite_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Bloom Composite Pipeline Layout"),
+            bind_group_layouts: &[
+                &composite_bindgroup_layout,
+                &settings_bindgroup_layout
+            ],
+            push_constant_ranges: &[],
+        });
+
+        let composite_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Bloom Composite Pipeline"),
+            layout: Some(&composite_pipeline_layout),
+            vertex: VertexState {
+                module: &composite_shader,
+                entry_point: "vs",
+                buffers: &[]
+            },
+            fragment: Some(FragmentState {
+                module: &composite_shader,
+                entry_point: "fs",
+                targets: &[Some(ColorTargetState {
+                    format,
+                    blend: Some(BlendState::REPLACE),
+                    write_mask: ColorWrites::ALL
+                })],
+            }),
+            multisample: MultisampleState::default(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multiview: None
+        });
+
+        /*
+            Compute
+            Downsample: input mip (or scene) -> storage mip, Upsample: input mip + target down mip -> storage up mip
+        */
+        let compute_shader = Self::create_karis_shader(device, "bloom_compute.wgsl", include_str!("../shaders/bloom_compute.wgsl"));
+
+        let storage_entry = |binding: u32| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::StorageTexture {
+                access: StorageTextureAccess::WriteOnly,
+                format,
+                view_dimension: TextureViewDimension::D2
+            },
+            count: None
+        };
+        let texture_entry = |binding: u32| BindGroupLayoutEntry {
+            binding,
+            visibility: ShaderStages::COMPUTE,
+            ty: BindingType::Texture {
+                view_dimension: TextureViewDimension::D2,
+                sample_type: TextureSampleType::Float { filterable: true },
+                multisampled: false
+            },
+            count: None
+        };
+
+        let compute_downsample_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Bloom Compute Downsample Bindgroup Layout"),
+            entries: &[
+                texture_entry(0),
+                storage_entry(1),
+            ]
+        });
+
+        let compute_upsample_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Bloom Compute Upsample Bindgroup Layout"),
+            entries: &[
+                texture_entry(0),
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::COMPUTE,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None
+                },
+                texture_entry(2),
+                storage_entry(3),
+            ]
+        });
+
+        let compute_prefilter_bindgroups = inputs.each_ref().map(|view| Self::create_compute_downsample_bindgroup(device, &compute_downsample_layout, view, &mipchain_views[0]));
+        let (compute_downsample_bindgroups, compute_upsample_bindgroups) = Self::create_compute_bindgroups(device, &sampler, &compute_downsample_layout, &compute_upsample_layout, &mipchain_views, &upchain_views);
+
+        let compute_downsample_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Bloom Compute Downsample Pipeline Layout"),
+            bind_group_layouts: &[
+                &compute_downsample_layout,
+                &settings_bindgroup_layout,
+            ],
+            push_constant_ranges: &[]
+        });
+
+        let compute_upsample_pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Bloom Compute Upsample Pipeline Layout"),
+            bind_group_layouts: &[
+                &compute_upsample_layout,
+                &settings_bindgroup_layout,
+                &mip_settings_bindgroup_layout,
+            ],
+            push_constant_ranges: &[]
+        });
+
+        let create_compute_pipeline = |label: &str, layout: &PipelineLayout, entry_point: &str| device.create_compute_pipeline(&ComputePipelineDescriptor {
+            label: Some(label),
+            layout: Some(layout),
+            module: &compute_shader,
+            entry_point,
+        });
+
+        let compute_prefilter_pipeline = create_compute_pipeline("Bloom Compute Prefilter Pipeline", &compute_downsample_pipeline_layout, "downsample_prefilter");
+        let compute_downsample_pipeline = create_compute_pipeline("Bloom Compute Downsample Pipeline", &compute_downsample_pipeline_layout, "downsample");
+        let compute_upsample_pipeline = create_compute_pipeline("Bloom Compute Upsample Pipeline", &compute_upsample_pipeline_layout, "upsample");
+
+        let hot_pixel_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Bloom Hot Pixel Buffer"),
+            size: std::mem::size_of_val(&HOT_PIXEL) as u64,
+            usage: BufferUsages::COPY_SRC,
+            mapped_at_creation: true
+        });
+        hot_pixel_buffer.slice(..).get_mapped_range_mut().copy_from_slice(bytemuck::cast_slice(&HOT_PIXEL));
+        hot_pixel_buffer.unmap();
+
+        // Missing weights / tints default to 1.0 / white
+        let mut mip_weights = [1.0; MAX_MIP_LEVELS];
+        let mut mip_tints = [[1.0; 3]; MAX_MIP_LEVELS];
+        for (weight, configured) in mip_weights.iter_mut().zip(&bloom_config.mip_weights) {
+            *weight = *configured;
+        }
+        for (tint, configured) in mip_tints.iter_mut().zip(&bloom_config.mip_tints) {
+            *tint = *configured;
+        }
+
         Self {
+            format: format,
+
             mipchain: mipchain,
             mipchain_views: mipchain_views,
-            
+            mip_count: mip_count,
+            upchain: upchain,
+            upchain_views: upchain_views,
+
+
             sampler: sampler,
             sampling_bindgroup_layout: sampling_bindgroup_layout,
             sampling_bindgroups: sampling_bindgroups,
+            input_sampling_bindgroups: input_sampling_bindgroups,
+
+            settings: settings,
+            settings_bindgroup: settings_bindgroup,
+
+            mip_settings: mip_settings,
+            mip_settings_stride: mip_settings_stride,
+            mip_settings_bindgroup: mip_settings_bindgroup,
             
+            prefilter_pipeline: prefilter_pipeline,
             downsample_pipeline: downsample_pipeline,
-
             upsample_pipeline: upsample_pipeline,
-            upsample_settings_bindgroup: upsample_settings_bindgroup,
-            upsample_settings: upsample_settings,
 
-            filter_size: 0.001
+            composite_pipeline: composite_pipeline,
+            composite_bindgroup_layout: composite_bindgroup_layout,
+            composite_bindgroups: composite_bindgroups,
+
+            compute_downsample_layout: compute_downsample_layout,
+            compute_upsample_layout: compute_upsample_layout,
+            compute_prefilter_bindgroups: compute_prefilter_bindgroups,
+            compute_downsample_bindgroups: compute_downsample_bindgroups,
+            compute_upsample_bindgroups: compute_upsample_bindgroups,
+            compute_prefilter_pipeline: compute_prefilter_pipeline,
+            compute_downsample_pipeline: compute_downsample_pipeline,
+            compute_upsample_pipeline: compute_upsample_pipeline,
+
+            hot_pixel_buffer: hot_pixel_buffer,
+            hot_pixel_frame: 0,
+
+
+            enabled: true,
+            mode: bloom_config.mode,
+            filter_size: bloom_config.filter_size,
+            threshold: bloom_config.threshold,
+            soft_knee: bloom_config.soft_knee,
+            intensity: bloom_config.intensity,
+            karis_average: bloom_config.karis_average,
+            hot_pixel_test: false,
+            mip_levels: bloom_config.mip_levels,
+            min_mip_size: bloom_config.min_mip_size,
+            mip_weights: mip_weights,
+            mip_tints: mip_tints,
+        }
+    }
+
+    pub fn mip_count(&self) -> usize {
+        self.mip_count
+    }
+
+    // Requested count, clamped so the smallest mip (mip 0 is half res) stays at least min_mip_size
+    // At least two, mip 0's weight and tint are applied while upsampling into it
+    pub fn mip_count_for(mip_levels: u32, min_mip_size: u32, physical_size: &PhysicalSize<u32>) -> usize {
+        let mut smallest = (physical_size.width.min(physical_size.height) / 2).max(1);
+        let mut max_levels = 1;
+        while max_levels < MAX_MIP_LEVELS && smallest / 2 >= min_mip_size.max(1) {
+            smallest /= 2;
+            max_levels += 1;
         }
+
+        let count = match mip_levels {
+            0 => max_levels,
+            requested => (requested as usize).min(max_levels),
+        };
+        // Only a 1x1 mip 0 can't hold a second level
+        let largest = (physical_size.width.max(physical_size.height) / 2).max(1);
+        count.max(2).min(32 - largest.leading_zeros() as usize)
+    }
+
+    // Both downsamples call karis_average, it lives in karis.wgsl so the two paths can't drift apart
+    pub fn create_karis_shader(device: &Device, label: &str, source: &str) -> ShaderModule {
+        device.create_shader_module(ShaderModuleDescriptor {
+            label: Some(label),
+            source: ShaderSource::Wgsl(format!("{}\n{}", include_str!("../shaders/karis.wgsl"), source).into()),
+        })
     }
 
     // The create_resources functions are always static, enabling use for initialization aswell as recreation
-    pub fn create_mipchain_and_views(device: &Device, format: TextureFormat, physical_size: &PhysicalSize<u32>) -> (Texture, Vec<TextureView>) {
+    // Mip 0 is half the scene resolution
+    pub fn create_mipchain_and_views(device: &Device, format: TextureFormat, physical_size: &PhysicalSize<u32>, mip_count: usize, label: &str) -> (Texture, Vec<TextureView>) {
         let new_mipchain = device.create_texture(&TextureDescriptor { 
-            label: Some("bloom_mipmap"), 
+            label: Some(label), 
             size: Extent3d {
-                width: physical_size.width,
-                height: physical_size.height,
+                width: (physical_size.width / 2).max(1),
+                height: (physical_size.height / 2).max(1),
                 depth_or_array_layers: 1
             }, 
-            mip_level_count: MIP_LEVELS as u32, 
+            mip_level_count: mip_count as u32, 
             sample_count: 1, 
             dimension: TextureDimension::D2, 
             format: format,
-            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST, 
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::STORAGE_BINDING | TextureUsages::COPY_SRC | TextureUsages::COPY_DST, 
             view_formats: &[] 
         });
 
-        let new_mipchain_views = (0..MIP_LEVELS).map(|i: usize| {
+        let new_mipchain_views = (0..mip_count).map(|i: usize| {
             new_mipchain.create_view(&TextureViewDescriptor {
                 label: Some("Bloom Mipchain Main View"),
                 format: Some(format),
@@ -246,44 +640,201 @@ impl Bloom {
         (new_mipchain, new_mipchain_views)
     }
 
-    pub fn create_sampling_bindgroups(device: &Device, sampler: &Sampler, layout: &BindGroupLayout, views: &Vec<TextureView>) -> Vec<BindGroup> {
-        (0..MIP_LEVELS).map(|i| {
+    pub fn create_sampling_bindgroup(device: &Device, sampler: &Sampler, layout: &BindGroupLayout, view: &TextureView) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Bloom Sampling Bindgroup"),
+            layout: layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(view)
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(sampler),
+                }
+            ]
+        })
+    }
+
+    pub fn create_sampling_bindgroups(device: &Device, sampler: &Sampler, layout: &BindGroupLayout, views: &[TextureView]) -> Vec<BindGroup> {
+        views.iter().map(|view| Self::create_sampling_bindgroup(device, sampler, layout, view)).collect()
+    }
+
+    pub fn create_composite_bindgroup(device: &Device, sampler: &Sampler, layout: &BindGroupLayout, scene_view: &TextureView, bloom_view: &TextureView) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Bloom Composite Bindgroup"),
+            layout: layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(scene_view)
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(bloom_view)
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(sampler),
+                }
+            ]
+        })
+    }
+
+    pub fn create_compute_downsample_bindgroup(device: &Device, layout: &BindGroupLayout, input: &TextureView, output: &TextureView) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Bloom Compute Downsample Bindgroup"),
+            layout: layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(input)
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(output)
+                }
+            ]
+        })
+    }
+
+    // Downsample i - 1 reads mip i - 1 into mip i (the prefilter into mip 0 is per input), upsample i - 1 reads up mip i (the down mip for the deepest level)
+    pub fn create_compute_bindgroups(device: &Device, sampler: &Sampler, downsample_layout: &BindGroupLayout, upsample_layout: &BindGroupLayout, mipchain_views: &[TextureView], upchain_views: &[TextureView]) -> (Vec<BindGroup>, Vec<BindGroup>) {
+        let mip_count = mipchain_views.len();
+
+        let downsample_bindgroups = (1..mip_count).map(|i| {
+            Self::create_compute_downsample_bindgroup(device, downsample_layout, &mipchain_views[i - 1], &mipchain_views[i])
+        }).collect();
+
+        let upsample_bindgroups = (1..mip_count).map(|i| {
+            let input = if i == mip_count - 1 { &mipchain_views[i] } else { &upchain_views[i] };
             device.create_bind_group(&BindGroupDescriptor {
-                label: Some("Bloom Sampling Bindgroup"),
-                layout: layout,
+                label: Some("Bloom Compute Upsample Bindgroup"),
+                layout: upsample_layout,
                 entries: &[
                     BindGroupEntry {
                         binding: 0,
-                        resource: BindingResource::TextureView(&views[i])
+                        resource: BindingResource::TextureView(input)
                     },
                     BindGroupEntry {
                         binding: 1,
-                        resource: BindingResource::Sampler(sampler),
+                        resource: BindingResource::Sampler(sampler)
+                    },
+                    BindGroupEntry {
+                        binding: 2,
+                        resource: BindingResource::TextureView(&mipchain_views[i - 1])
+                    },
+                    BindGroupEntry {
+                        binding: 3,
+                        resource: BindingResource::TextureView(&upchain_views[i - 1])
                     }
                 ]
             })
-        }).collect()
-    }
+        }).collect();
 
-    pub fn recreate_mipchain_and_bindgroups(&mut self, device: &Device, format: TextureFormat, physical_size: &PhysicalSize<u32>) -> () {
-        self.mipchain.destroy(); //Also "destroys" the texture views and bindgroups, in that everything will be cleaned up when those references are dropped
+        (downsample_bindgroups, upsample_bindgroups)
+    }
 
-        (self.mipchain, self.mipchain_views) = Self::create_mipchain_and_views(device, format, physical_size);
+    // Used on resize and when the mip count changes, the inputs are the chain's ping-pong views
+    pub fn recreate_mipchain(&mut self, device: &Device, inputs: &[TextureView; 2], physical_size: &PhysicalSize<u32>) {
+        // The old chains are dropped with their views and bindgroups, not destroyed, the mip count changes from the UI
+        // while the frame's commands still sample them
+        self.mip_count = Self::mip_count_for(self.mip_levels, self.min_mip_size, physical_size);
+        (self.mipchain, self.mipchain_views) = Self::create_mipchain_and_views(device, self.format, physical_size, self.mip_count, "bloom_mipmap");
+        (self.upchain, self.upchain_views) = Self::create_mipchain_and_views(device, self.format, physical_size, self.mip_count, "bloom_upchain");
 
         self.sampling_bindgroups = Self::create_sampling_bindgroups(device, &self.sampler, &self.sampling_bindgroup_layout, &self.mipchain_views);
+        self.input_sampling_bindgroups = inputs.each_ref().map(|view| Self::create_sampling_bindgroup(device, &self.sampler, &self.sampling_bindgroup_layout, view));
+        self.composite_bindgroups = inputs.each_ref().map(|view| Self::create_composite_bindgroup(device, &self.sampler, &self.composite_bindgroup_layout, view, &self.mipchain_views[0]));
+        self.compute_prefilter_bindgroups = inputs.each_ref().map(|view| Self::create_compute_downsample_bindgroup(device, &self.compute_downsample_layout, view, &self.mipchain_views[0]));
+        (self.compute_downsample_bindgroups, self.compute_upsample_bindgroups) = Self::create_compute_bindgroups(device, &self.sampler, &self.compute_downsample_layout, &self.compute_upsample_layout, &self.mipchain_views, &self.upchain_views);
     }
 
-    pub fn render(&self, encoder: &mut wgpu::CommandEncoder, queue: &wgpu::Queue) {
-        encoder.push_debug_group("Bloom");
-        //Downsampling
-        for i in 0..(MIP_LEVELS-1) {
+    // Copied into the cleared scene, stepping one scene pixel (half a mip 0 texel) every 8 frames
+    pub fn write_hot_pixel(&self, encoder: &mut wgpu::CommandEncoder, scene: &Texture) {
+        let step = (self.hot_pixel_frame / 8) % 4;
+
+        encoder.copy_buffer_to_texture(
+            ImageCopyBuffer {
+                buffer: &self.hot_pixel_buffer,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: None,
+                    rows_per_image: None,
+                }
+            },
+            ImageCopyTexture {
+                texture: scene,
+                mip_level: 0,
+                origin: Origin3d {
+                    x: scene.width() / 2 + step,
+                    y: scene.height() / 2,
+                    z: 0
+                },
+                aspect: TextureAspect::All,
+            },
+            Extent3d {
+                width: 1,
+                height: 1,
+                depth_or_array_layers: 1
+            },
+        );
+    }
+
+    fn mip_scale(&self, i: usize) -> [f32; 4] {
+        let [r, g, b] = self.mip_tints[i];
+        let weight = self.mip_weights[i];
+        [r * weight, g * weight, b * weight, 1.0]
+    }
+
+    // Level 0 is the prefilter straight from the input, every other level downsamples the one above it
+    fn render_raster_downsample(&self, encoder: &mut wgpu::CommandEncoder, timestamp_writes: Option<RenderPassTimestampWrites>, input: usize, i: usize) {
+        let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some(&format!("Bloom Downsample Renderpass {}", i)),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &self.mipchain_views[i],
+                resolve_target: None,
+                ops: wgpu::Operations {
+                    load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                    store: wgpu::StoreOp::Store,
+                }
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes,
+        });
+
+        if i == 0 {
+            render_pass.push_debug_group("Prefilter Input -> 0");
+            render_pass.set_pipeline(&self.prefilter_pipeline);
+            render_pass.set_bind_group(0, &self.input_sampling_bindgroups[input], &[]);
+        } else {
+            render_pass.push_debug_group(&format!("Downsample {} -> {}", i - 1, i));
+            render_pass.set_pipeline(&self.downsample_pipeline);
+            render_pass.set_bind_group(0, &self.sampling_bindgroups[i - 1], &[]);
+        }
+        render_pass.set_bind_group(1, &self.settings_bindgroup, &[]);
+        render_pass.draw(0..6, 0..1);  
+        render_pass.pop_debug_group(); 
+    }
+
+    fn render_raster(&self, encoder: &mut wgpu::CommandEncoder, timestamps: Option<TimestampScope>, input: usize) {
+        //Downsampling, input -> 0 (prefiltered), then i -> i + 1
+        for i in 0..self.mip_count {
+            self.render_raster_downsample(encoder, timestamps.filter(|_| i == 0).map(|scope| scope.render_writes(true, false)), input, i);
+        }
+
+        //Upsampling
+        for i in (1..self.mip_count).rev() {
+            // Update Render Target, Create Render Pass, Draw
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some(&format!("Bloom Downsample Renderpass {} -> {}", i, i + 1)),
+                label: Some(&format!("Bloom Upsample Renderpass {} -> {}", i, i - 1)),
                 color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &self.mipchain_views[i+1],
+                    view: &self.mipchain_views[i-1],
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        load: wgpu::LoadOp::Load,
                         store: wgpu::StoreOp::Store,
                     }
                 })],
@@ -292,41 +843,369 @@ impl Bloom {
                 timestamp_writes: None
             });
 
-            render_pass.push_debug_group(&format!("Downsample {} -> {}",i,i+1));
-            render_pass.set_pipeline(&self.downsample_pipeline);
+            render_pass.push_debug_group(&format!("Upsample {} -> {}", i, i - 1));
+            render_pass.set_pipeline(&self.upsample_pipeline);
             render_pass.set_bind_group(0, &self.sampling_bindgroups[i], &[]);
-            render_pass.draw(0..6, 0..1);  
-            render_pass.pop_debug_group(); 
+            render_pass.set_bind_group(1, &self.settings_bindgroup, &[]);
+            render_pass.set_bind_group(2, &self.mip_settings_bindgroup, &[(i as u64 * self.mip_settings_stride) as u32]);
+            let [r, g, b, a] = self.mip_scale(i - 1);
+            render_pass.set_blend_constant(Color { r: r as f64, g: g as f64, b: b as f64, a: a as f64 });
+            render_pass.draw(0..6, 0..1);   
+            render_pass.pop_debug_group();
         }
+    }
 
-        //Upsampling
-        let buffer_data_bytes = [self.filter_size.to_le_bytes()].concat();
-        queue.write_buffer(&self.upsample_settings, 0, &buffer_data_bytes);
-        for i in (1..MIP_LEVELS).rev() {
-            // Update Settings Buffer, Update Render Target, Create Render Pass, Draw
+    fn render_compute(&self, encoder: &mut wgpu::CommandEncoder, timestamps: Option<TimestampScope>, input: usize) {
+        let mip_size = |i: usize| {
+            let size = self.mipchain.size().mip_level_size(i as u32, TextureDimension::D2);
+            (size.width.div_ceil(COMPUTE_TILE), size.height.div_ceil(COMPUTE_TILE))
+        };
+
+        {
+            let mut compute_pass = encoder.begin_compute_pass(&ComputePassDescriptor {
+                label: Some("Bloom Compute Pass"),
+                timestamp_writes: timestamps.map(|scope| scope.compute_writes(true, false))
+            });
+            compute_pass.set_bind_group(1, &self.settings_bindgroup, &[]);
+
+            //Downsampling, input -> 0 (prefiltered), then i -> i + 1
+            for i in 0..self.mip_count {
+                if i == 0 {
+                    compute_pass.set_pipeline(&self.compute_prefilter_pipeline);
+                    compute_pass.set_bind_group(0, &self.compute_prefilter_bindgroups[input], &[]);
+                } else {
+                    compute_pass.set_pipeline(&self.compute_downsample_pipeline);
+                    compute_pass.set_bind_group(0, &self.compute_downsample_bindgroups[i - 1], &[]);
+                }
+                let (x, y) = mip_size(i);
+                compute_pass.dispatch_workgroups(x, y, 1);
+            }
+
+            //Upsampling into the up chain
+            compute_pass.set_pipeline(&self.compute_upsample_pipeline);
+            for i in (1..self.mip_count).rev() {
+                compute_pass.set_bind_group(0, &self.compute_upsample_bindgroups[i - 1], &[]);
+                compute_pass.set_bind_group(2, &self.mip_settings_bindgroup, &[(i as u64 * self.mip_settings_stride) as u32]);
+                let (x, y) = mip_size(i - 1);
+                compute_pass.dispatch_workgroups(x, y, 1);
+            }
+        }
+
+        // Up chain mip 0 back into the mipchain, which the composite and lens dirt read
+        if self.mip_count > 1 {
+            encoder.copy_texture_to_texture(
+                ImageCopyTexture {
+                    texture: &self.upchain,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                ImageCopyTexture {
+                    texture: &self.mipchain,
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                self.mipchain.size().mip_level_size(0, TextureDimension::D2),
+            );
+        }
+    }
+}
+
+impl PostEffect for Bloom {
+    fn kind(&self) -> PostEffectKind {
+        PostEffectKind::Bloom
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn resize(&mut self, ctx: &EffectContext) {
+        self.recreate_mipchain(ctx.device, ctx.inputs, &ctx.size);
+    }
+
+    fn update(&mut self, ctx: &EffectContext, _dt: f32) {
+        if self.hot_pixel_test {
+            self.hot_pixel_frame = self.hot_pixel_frame.wrapping_add(1);
+        }
+
+        ctx.queue.write_buffer(&self.settings, 0, bytemuck::cast_slice(&[BloomUniform {
+            filter_size: self.filter_size,
+            threshold: self.threshold,
+            soft_knee: self.soft_knee,
+            intensity: self.intensity,
+            karis_average: self.karis_average as u32,
+            _padding: [0; 3],
+        }]));
+
+        // Upsample source scale, 1.0 except for the deepest mip
+        for i in 1..self.mip_count {
+            let scale = if i == self.mip_count - 1 { self.mip_scale(i) } else { [1.0; 4] };
+            ctx.queue.write_buffer(&self.mip_settings, i as u64 * self.mip_settings_stride, bytemuck::cast_slice(&[MipUniform {
+                scale,
+                target_scale: self.mip_scale(i - 1),
+            }]));
+        }
+    }
+
+    // The scope brackets the whole chain including the composite, so both modes can be compared
+    fn render(&self, encoder: &mut CommandEncoder, _ctx: &EffectContext, timestamps: Option<TimestampScope>, input: usize, output: &TextureView) {
+        encoder.push_debug_group("Bloom");
+        match self.mode {
+            BloomMode::Raster => self.render_raster(encoder, timestamps, input),
+            BloomMode::Compute => self.render_compute(encoder, timestamps, input),
+        }
+        //At this point bloom output is in miplevel 0 
+
+        //Composite
+        {
             let mut render_pass = encoder.begin_render_pass(&RenderPassDescriptor {
-                label: Some(&format!("Bloom Upsample Renderpass {} -> {}", i, i - 1)),
+                label: Some("Bloom Composite Renderpass"),
                 color_attachments: &[Some(RenderPassColorAttachment {
-                    view: &self.mipchain_views[i-1],
+                    view: output,
                     resolve_target: None,
                     ops: wgpu::Operations {
-                        load: wgpu::LoadOp::Load,
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
                         store: wgpu::StoreOp::Store,
                     }
                 })],
                 depth_stencil_attachment: None,
                 occlusion_query_set: None,
-                timestamp_writes: None
+                timestamp_writes: timestamps.map(|scope| scope.render_writes(false, true))
             });
 
-            render_pass.push_debug_group(&format!("Upsample {} -> {}", i, i - 1));
-            render_pass.set_pipeline(&self.upsample_pipeline);
-            render_pass.set_bind_group(0, &self.sampling_bindgroups[i], &[]);
-            render_pass.set_bind_group(1, &self.upsample_settings_bindgroup, &[]);
-            render_pass.draw(0..6, 0..1);   
+            render_pass.push_debug_group("Composite");
+            render_pass.set_pipeline(&self.composite_pipeline);
+            render_pass.set_bind_group(0, &self.composite_bindgroups[input], &[]);
+            render_pass.set_bind_group(1, &self.settings_bindgroup, &[]);
+            render_pass.draw(0..6, 0..1);
             render_pass.pop_debug_group();
         }
-        //At this point bloom output is in miplevel 0 
         encoder.pop_debug_group();
     }
-}
\ No newline at end of file
+
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &EffectContext) {
+        egui::ComboBox::from_label("Mode")
+            .selected_text(self.mode.name())
+            .show_ui(ui, |ui| {
+                for mode in BloomMode::ALL {
+                    ui.selectable_value(&mut self.mode, mode, mode.name());
+                }
+            });
+        ui.add(egui::Slider::new(&mut self.filter_size,0.0..=0.003).text("Filter Size"));
+        ui.add(egui::Slider::new(&mut self.threshold, 0.0..=10.0).text("Threshold"));
+        ui.add(egui::Slider::new(&mut self.soft_knee, 0.0..=1.0).text("Soft Knee"));
+        ui.add(egui::Slider::new(&mut self.intensity, 0.0..=1.0).text("Intensity"));
+        ui.checkbox(&mut self.karis_average, "Firefly Suppression (Karis Average)");
+        ui.checkbox(&mut self.hot_pixel_test, "Hot Pixel Test Pattern");
+
+        let previous_levels = (self.mip_levels, self.min_mip_size);
+        ui.add(egui::Slider::new(&mut self.mip_levels, 0..=MAX_MIP_LEVELS as u32).text("Mip Levels (0 = auto, at least 2)"));
+        ui.add(egui::Slider::new(&mut self.min_mip_size, 1..=64).text("Min Mip Size"));
+        if previous_levels != (self.mip_levels, self.min_mip_size) {
+            self.recreate_mipchain(ctx.device, ctx.inputs, &ctx.size);
+        }
+
+        ui.collapsing(format!("Mip Weights ({} mips)", self.mip_count()), |ui| {
+            for i in 0..self.mip_count() {
+                ui.horizontal(|ui| {
+                    ui.add(egui::Slider::new(&mut self.mip_weights[i], 0.0..=2.0).text(format!("Mip {}", i)));
+                    ui.color_edit_button_rgb(&mut self.mip_tints[i]);
+                });
+            }
+        });
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::app::render::HDR_FORMAT;
+
+    // Mip 0 is 32 texels of Rgba16Float wide, exactly the 256 byte row alignment of buffer copies
+    const SIZE: u32 = 64;
+    // Relative to the brightest texel, blending and the storage writes round through f16 in different places
+    const TOLERANCE: f32 = 0.01;
+
+    fn f16_to_f32(bits: u16) -> f32 {
+        let sign = if bits & 0x8000 != 0 { -1.0 } else { 1.0 };
+        let exponent = ((bits >> 10) & 0x1F) as i32;
+        let mantissa = (bits & 0x3FF) as f32;
+        sign * match exponent {
+            0 => mantissa * 2f32.powi(-24),
+            0x1F => f32::INFINITY,
+            _ => (1.0 + mantissa / 1024.0) * 2f32.powi(exponent - 15),
+        }
+    }
+
+    // Hot texel values, normal range of f16 only
+    fn f32_to_f16(value: f32) -> u16 {
+        let bits = value.to_bits();
+        let sign = ((bits >> 16) & 0x8000) as u16;
+        let exponent = ((bits >> 23) & 0xFF) as i32 - 127 + 15;
+        sign | ((exponent as u16) << 10) | ((bits >> 13) & 0x3FF) as u16
+    }
+
+    // Submits the encoder with a copy of one mipchain level appended and reads it back
+    fn read_mip(device: &Device, queue: &Queue, mut encoder: CommandEncoder, bloom: &Bloom, level: u32) -> Vec<f32> {
+        let mip_size = bloom.mipchain.size().mip_level_size(level, TextureDimension::D2);
+        let bytes_per_row = mip_size.width * 8;
+        let readback = device.create_buffer(&BufferDescriptor {
+            label: Some("Bloom Mip Readback"),
+            size: (bytes_per_row * mip_size.height) as u64,
+            usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ,
+            mapped_at_creation: false,
+        });
+
+        encoder.copy_texture_to_buffer(
+            ImageCopyTexture {
+                texture: &bloom.mipchain,
+                mip_level: level,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            ImageCopyBuffer {
+                buffer: &readback,
+                layout: ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(bytes_per_row),
+                    rows_per_image: None,
+                },
+            },
+            mip_size,
+        );
+        queue.submit(std::iter::once(encoder.finish()));
+
+        readback.slice(..).map_async(MapMode::Read, |result| result.unwrap());
+        device.poll(Maintain::Wait);
+        let data = readback.slice(..).get_mapped_range();
+        bytemuck::cast_slice::<u8, u16>(&data).iter().map(|bits| f16_to_f32(*bits)).collect()
+    }
+
+    // Runs the hot pixel pattern through one mode and reads mip 0 back
+    fn render_mip0(device: &Device, queue: &Queue, bloom: &mut Bloom, scene: &Texture, mode: BloomMode) -> Vec<f32> {
+        bloom.mode = mode;
+        let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+        bloom.write_hot_pixel(&mut encoder, scene);
+        match mode {
+            BloomMode::Raster => bloom.render_raster(&mut encoder, None, 0),
+            BloomMode::Compute => bloom.render_compute(&mut encoder, None, 0),
+        }
+        read_mip(device, queue, encoder, bloom, 0)
+    }
+
+    fn create_scenes(device: &Device) -> [Texture; 2] {
+        [0, 1].map(|_| device.create_texture(&TextureDescriptor {
+            label: Some("Test Scene"),
+            size: Extent3d { width: SIZE, height: SIZE, depth_or_array_layers: 1 },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: HDR_FORMAT,
+            usage: TextureUsages::RENDER_ATTACHMENT | TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[],
+        }))
+    }
+
+    #[test]
+    fn karis_bounds_hot_texel_spread() {
+        // The prefilter only samples the scene, so GL can run it too
+        let instance = Instance::new(InstanceDescriptor::default());
+        let Some(adapter) = pollster::block_on(instance.request_adapter(&RequestAdapterOptions::default())) else {
+            eprintln!("No adapter, skipping the Karis average check");
+            return;
+        };
+        let (device, queue) = pollster::block_on(adapter.request_device(&DeviceDescriptor::default(), None)).unwrap();
+
+        let config: Config = toml::from_str(include_str!("../../../config/test.toml")).unwrap();
+        let size = PhysicalSize::new(SIZE, SIZE);
+        let scenes = create_scenes(&device);
+        let views = [0, 1].map(|i| scenes[i].create_view(&TextureViewDescriptor::default()));
+        let mut bloom = Bloom::new(&device, &config, HDR_FORMAT, &size, &views);
+        // A zero threshold passes the downsample through the prefilter unchanged
+        bloom.threshold = 0.0;
+
+        // The first downsample of the scene, the only pass Karis weights
+        let mut downsample = |karis: bool, hot: f32, (x, y): (u32, u32)| {
+            bloom.karis_average = karis;
+            bloom.update(&EffectContext { device: &device, queue: &queue, size, inputs: &views }, 0.0);
+
+            // Grey, so the luma Karis weighs by is the value itself
+            let mut texels = vec![0u16; (SIZE * SIZE * 4) as usize];
+            let hot_texel = ((y * SIZE + x) * 4) as usize;
+            texels[hot_texel..hot_texel + 4].copy_from_slice(&[f32_to_f16(hot), f32_to_f16(hot), f32_to_f16(hot), f32_to_f16(1.0)]);
+            queue.write_texture(
+                ImageCopyTexture {
+                    texture: &scenes[0],
+                    mip_level: 0,
+                    origin: Origin3d::ZERO,
+                    aspect: TextureAspect::All,
+                },
+                bytemuck::cast_slice(&texels),
+                ImageDataLayout {
+                    offset: 0,
+                    bytes_per_row: Some(SIZE * 8),
+                    rows_per_image: None,
+                },
+                scenes[0].size(),
+            );
+
+            let mut encoder = device.create_command_encoder(&CommandEncoderDescriptor { label: None });
+            bloom.render_raster_downsample(&mut encoder, None, 0, 0);
+            let mut red: Vec<f32> = read_mip(&device, &queue, encoder, &bloom, 0).into_iter().step_by(4).collect();
+            red.sort_by(|a, b| b.total_cmp(a));
+            red
+        };
+
+        for hot in [1024.0, 32768.0] {
+            // Every sub-pixel step of the hot pixel pattern
+            for position in [(31, 31), (32, 31), (31, 32), (32, 32)] {
+                let plain = downsample(false, hot, position);
+                let karis = downsample(true, hot, position);
+
+                // The plain average smears a fixed fraction of the texel into its neighbours
+                assert!(plain[1] > hot / 32.0, "plain neighbour {} for hot texel {}", plain[1], hot);
+
+                // Karis keeps the texel where all 5 groups see it, everywhere else at least one group is dark and
+                // weighs 1, against weights summing to at most 1, so the spread can't pass 1 / 0.125 however hot it is
+                assert!(karis[1] < 8.0, "karis neighbour {} for hot texel {}", karis[1], hot);
+                assert!(karis.iter().sum::<f32>() < plain.iter().sum::<f32>());
+            }
+        }
+    }
+
+    #[test]
+    fn compute_matches_raster() {
+        // Same backends as the app, GL can't sample one mip of a texture while rendering into another
+        let instance = Instance::new(InstanceDescriptor {
+            backends: Backends::PRIMARY,
+            ..Default::default()
+        });
+        let Some(adapter) = pollster::block_on(instance.request_adapter(&RequestAdapterOptions::default())) else {
+            eprintln!("No Vulkan / Metal / DX12 adapter, skipping the bloom mode comparison");
+            return;
+        };
+        let (device, queue) = pollster::block_on(adapter.request_device(&DeviceDescriptor::default(), None)).unwrap();
+
+        let config: Config = toml::from_str(include_str!("../../../config/test.toml")).unwrap();
+        let size = PhysicalSize::new(SIZE, SIZE);
+        let scenes = create_scenes(&device);
+        let views = [0, 1].map(|i| scenes[i].create_view(&TextureViewDescriptor::default()));
+
+        let mut bloom = Bloom::new(&device, &config, HDR_FORMAT, &size, &views);
+        bloom.update(&EffectContext { device: &device, queue: &queue, size, inputs: &views }, 0.0);
+
+        let raster = render_mip0(&device, &queue, &mut bloom, &scenes[0], BloomMode::Raster);
+        let compute = render_mip0(&device, &queue, &mut bloom, &scenes[0], BloomMode::Compute);
+
+        let brightest = raster.iter().fold(0.0f32, |max, v| max.max(v.abs()));
+        let error = raster.iter().zip(&compute).fold(0.0f32, |max, (r, c)| max.max((r - c).abs()));
+        assert!(brightest > 0.0, "raster bloom left mip 0 black");
+        assert!(error <= TOLERANCE * brightest, "max abs error {} against brightest texel {}", error, brightest);
+    }
+}
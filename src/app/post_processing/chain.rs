@@ -0,0 +1,390 @@
+use std::any::Any;
+
+use serde::Deserialize;
+use wgpu::*;
+use winit::dpi::PhysicalSize;
+
+use crate::config::Config;
+use crate::app::render::HDR_FORMAT;
+use crate::app::timestamps::TimestampScope;
+use super::bloom::Bloom;
+use super::auto_exposure::AutoExposure;
+use super::present::Present;
+use super::color_grading::ColorGrading;
+use super::lens::Lens;
+use super::lens_dirt::LensDirt;
+use super::film_grain::FilmGrain;
+
+/*
+    Ordered post processing chain over two full resolution ping-pong textures owned by the render graph,
+    the scene is rendered into the first one and every enabled effect reads one and writes the other.
+    Effects that only measure their input (auto exposure) or draw onto it (lens dirt) don't swap them.
+    A final blit copies whichever texture holds the result into the surface, so any order is valid.
+    Effects keep one bindgroup per ping-pong texture and rebuild them in resize.
+    Effects that depend on another one running first (tonemapping on auto exposure, lens dirt on bloom's mip 0)
+    are told every update whether it does in the current order.
+    The chain runs as a single graph pass, every effect is timed in the scope named after its kind.
+*/
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum PostEffectKind {
+    Bloom,
+    LensDirt,
+    AutoExposure,
+    Tonemap,
+    ColorGrading,
+    Lens,
+    FilmGrain,
+}
+
+impl PostEffectKind {
+    pub const ALL: [PostEffectKind; 7] = [
+        PostEffectKind::Bloom,
+        PostEffectKind::LensDirt,
+        PostEffectKind::AutoExposure,
+        PostEffectKind::Tonemap,
+        PostEffectKind::ColorGrading,
+        PostEffectKind::Lens,
+        PostEffectKind::FilmGrain,
+    ];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            PostEffectKind::Bloom => "Bloom",
+            PostEffectKind::LensDirt => "Lens Dirt",
+            PostEffectKind::AutoExposure => "Auto Exposure",
+            PostEffectKind::Tonemap => "Tonemapping",
+            PostEffectKind::ColorGrading => "Color Grading",
+            PostEffectKind::Lens => "Lens",
+            PostEffectKind::FilmGrain => "Film Grain",
+        }
+    }
+
+    // Position in ALL, independent of the declaration order
+    pub fn index(&self) -> usize {
+        PostEffectKind::ALL.iter().position(|kind| kind == self).unwrap()
+    }
+}
+
+// What effects get to (re)create resources, inputs are the chain's ping-pong views
+pub struct EffectContext<'a> {
+    pub device: &'a Device,
+    pub queue: &'a Queue,
+    pub size: PhysicalSize<u32>,
+    pub inputs: &'a [TextureView; 2],
+}
+
+pub trait AsAny {
+    fn as_any(&self) -> &dyn Any;
+    fn as_any_mut(&mut self) -> &mut dyn Any;
+}
+
+impl<T: Any> AsAny for T {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+
+    fn as_any_mut(&mut self) -> &mut dyn Any {
+        self
+    }
+}
+
+pub trait PostEffect: AsAny {
+    fn kind(&self) -> PostEffectKind;
+    fn enabled(&self) -> bool;
+    fn set_enabled(&mut self, enabled: bool);
+
+    // False for effects that only read their input or draw onto it, the chain then keeps the current texture
+    fn writes_output(&self) -> bool {
+        true
+    }
+
+    // The ping-pong textures were recreated, anything bound to ctx.inputs is stale
+    fn resize(&mut self, ctx: &EffectContext);
+
+    // Once per frame, also while disabled, dt in seconds
+    fn update(&mut self, _ctx: &EffectContext, _dt: f32) {}
+
+    // Reads ctx.inputs[input], writes output, timestamps is the effect's own scope
+    fn render(&self, encoder: &mut CommandEncoder, ctx: &EffectContext, timestamps: Option<TimestampScope>, input: usize, output: &TextureView);
+
+    // After the frame was submitted, for readbacks
+    fn after_submit(&mut self) {}
+
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &EffectContext);
+}
+
+// Single pass helper for the fullscreen effects, clears the output
+pub fn fullscreen_pass<'a>(encoder: &'a mut CommandEncoder, label: &str, output: &'a TextureView, timestamp_writes: Option<RenderPassTimestampWrites<'a>>) -> RenderPass<'a> {
+    encoder.begin_render_pass(&RenderPassDescriptor {
+        label: Some(label),
+        color_attachments: &[Some(RenderPassColorAttachment {
+            view: output,
+            resolve_target: None,
+            ops: Operations {
+                load: LoadOp::Clear(Color::BLACK),
+                store: StoreOp::Store,
+            }
+        })],
+        depth_stencil_attachment: None,
+        occlusion_query_set: None,
+        timestamp_writes,
+    })
+}
+
+pub struct PostChain {
+    views: [TextureView; 2],
+    size: PhysicalSize<u32>,
+
+    effects: Vec<Box<dyn PostEffect>>,
+
+    output_bindgroup_layout: BindGroupLayout,
+    output_bindgroups: [BindGroup; 2],
+    output_pipeline: RenderPipeline,
+}
+
+impl PostChain {
+    pub fn new(device: &Device, queue: &Queue, config: &Config, surface_format: TextureFormat, size: &PhysicalSize<u32>, textures: [&Texture; 2]) -> Self {
+        let views = textures.map(|texture| texture.create_view(&TextureViewDescriptor::default()));
+
+        let output_bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Post Chain Output Bindgroup Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                }
+            ]
+        });
+
+        let output_bindgroups = Self::create_output_bindgroups(device, &output_bindgroup_layout, &views);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Post Chain Output Pipeline Layout"),
+            bind_group_layouts: &[
+                &output_bindgroup_layout
+            ],
+            push_constant_ranges: &[]
+        });
+
+        let shader = device.create_shader_module(include_wgsl!("../shaders/chain_output.wgsl"));
+
+        let output_pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Post Chain Output Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs",
+                targets: &[
+                    Some(ColorTargetState {
+                        format: surface_format,
+                        blend: Some(BlendState::REPLACE),
+                        write_mask: ColorWrites::ALL
+                    })
+                ]
+            }),
+            multisample: MultisampleState::default(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multiview: None
+        });
+
+        // Tonemapping reads the exposure auto exposure adapts on the GPU
+        let auto_exposure = AutoExposure::new(device, queue, config, &views);
+        let present = Present::new(device, config, HDR_FORMAT, &views, &auto_exposure.exposure_buffer);
+        // Lens dirt samples bloom's mip 0
+        let bloom = Bloom::new(device, config, HDR_FORMAT, size, &views);
+        let lens_dirt = LensDirt::new(device, queue, config, &bloom.mipchain_views[0]);
+
+        let mut available: Vec<Box<dyn PostEffect>> = vec![
+            Box::new(bloom),
+            Box::new(lens_dirt),
+            Box::new(auto_exposure),
+            Box::new(present),
+            Box::new(ColorGrading::new(device, queue, config, &views)),
+            Box::new(Lens::new(device, config, &views)),
+            Box::new(FilmGrain::new(device, config, &views)),
+        ];
+
+        // Configured order first, anything left out is appended disabled so it can still be enabled at runtime
+        let mut effects = Vec::with_capacity(available.len());
+        for kind in &config.post_chain_config.order {
+            match available.iter().position(|effect| effect.kind() == *kind) {
+                Some(index) => effects.push(available.remove(index)),
+                None => log::warn!("Post effect {:?} is listed more than once, ignoring the duplicate", kind),
+            }
+        }
+        for mut effect in available {
+            effect.set_enabled(false);
+            effects.push(effect);
+        }
+
+        Self {
+            views,
+            size: *size,
+
+            effects,
+
+            output_bindgroup_layout,
+            output_bindgroups,
+            output_pipeline,
+        }
+    }
+
+    fn create_output_bindgroups(device: &Device, layout: &BindGroupLayout, views: &[TextureView; 2]) -> [BindGroup; 2] {
+        views.each_ref().map(|view| device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Post Chain Output Bindgroup"),
+            layout: layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(view),
+                }
+            ]
+        }))
+    }
+
+    pub fn get<T: PostEffect + 'static>(&self) -> Option<&T> {
+        // Through the box, Box<dyn PostEffect> is Any itself and would never downcast
+        self.effects.iter().find_map(|effect| effect.as_ref().as_any().downcast_ref::<T>())
+    }
+
+    pub fn get_mut<T: PostEffect + 'static>(&mut self) -> Option<&mut T> {
+        self.effects.iter_mut().find_map(|effect| effect.as_mut().as_any_mut().downcast_mut::<T>())
+    }
+
+    // Swaps the effect at index with the one before it
+    pub fn move_up(&mut self, index: usize) {
+        if index > 0 && index < self.effects.len() {
+            self.effects.swap(index - 1, index);
+        }
+    }
+
+    // Both enabled, with first ahead of second in the current order
+    fn runs_before(&self, first: PostEffectKind, second: PostEffectKind) -> bool {
+        let position = |kind| self.effects.iter().position(|effect| effect.enabled() && effect.kind() == kind);
+        matches!((position(first), position(second)), (Some(first), Some(second)) if first < second)
+    }
+
+    // Rebinds lens dirt once bloom recreated its mip chain (resize, mip levels) or the dirt texture changed
+    fn bind_lens_dirt(&mut self, device: &Device) {
+        let position = |kind| self.effects.iter().position(|effect| effect.kind() == kind);
+        let (Some(bloom), Some(lens_dirt)) = (position(PostEffectKind::Bloom), position(PostEffectKind::LensDirt)) else {
+            return;
+        };
+
+        // Split so bloom can be borrowed next to lens dirt
+        let (bloom, lens_dirt) = if bloom < lens_dirt {
+            let (front, back) = self.effects.split_at_mut(lens_dirt);
+            (&front[bloom], &mut back[0])
+        } else {
+            let (front, back) = self.effects.split_at_mut(bloom);
+            (&back[0], &mut front[lens_dirt])
+        };
+
+        if let (Some(bloom), Some(lens_dirt)) = (bloom.as_ref().as_any().downcast_ref::<Bloom>(), lens_dirt.as_mut().as_any_mut().downcast_mut::<LensDirt>()) {
+            let bloom_view = &bloom.mipchain_views[0];
+            if !lens_dirt.is_bound_to(bloom_view) {
+                lens_dirt.recreate_bindgroup(device, bloom_view);
+            }
+        }
+    }
+
+    // The graph recreated the ping-pong textures
+    pub fn resize(&mut self, device: &Device, queue: &Queue, size: &PhysicalSize<u32>, textures: [&Texture; 2]) {
+        self.views = textures.map(|texture| texture.create_view(&TextureViewDescriptor::default()));
+        self.output_bindgroups = Self::create_output_bindgroups(device, &self.output_bindgroup_layout, &self.views);
+        self.size = *size;
+
+        let ctx = EffectContext { device, queue, size: self.size, inputs: &self.views };
+        for effect in &mut self.effects {
+            effect.resize(&ctx);
+        }
+        self.bind_lens_dirt(device);
+    }
+
+    pub fn update(&mut self, device: &Device, queue: &Queue, dt: f32) {
+        // Tonemapping only applies the adapted exposure when auto exposure measured this frame before it
+        let auto_exposure = self.runs_before(PostEffectKind::AutoExposure, PostEffectKind::Tonemap);
+        if let Some(present) = self.get_mut::<Present>() {
+            present.auto_exposure = auto_exposure;
+        }
+        let bloom = self.runs_before(PostEffectKind::Bloom, PostEffectKind::LensDirt);
+        if let Some(lens_dirt) = self.get_mut::<LensDirt>() {
+            lens_dirt.bloom_ready = bloom;
+        }
+        self.bind_lens_dirt(device);
+
+        let ctx = EffectContext { device, queue, size: self.size, inputs: &self.views };
+        for effect in &mut self.effects {
+            effect.update(&ctx, dt);
+        }
+    }
+
+    // timestamps holds one scope per PostEffectKind, in ALL order
+    pub fn render(&self, encoder: &mut CommandEncoder, device: &Device, queue: &Queue, timestamps: &[Option<TimestampScope>; 7], target: &TextureView) {
+        let ctx = EffectContext { device, queue, size: self.size, inputs: &self.views };
+
+        let mut current = 0;
+        for effect in self.effects.iter().filter(|effect| effect.enabled()) {
+            effect.render(encoder, &ctx, timestamps[effect.kind().index()], current, &self.views[1 - current]);
+            if effect.writes_output() {
+                current = 1 - current;
+            }
+        }
+
+        let mut rpass = fullscreen_pass(encoder, "Post Chain Output Pass", target, None);
+        rpass.push_debug_group("Post Chain Output");
+        rpass.set_pipeline(&self.output_pipeline);
+        rpass.set_bind_group(0, &self.output_bindgroups[current], &[]);
+        rpass.draw(0..6, 0..1);
+        rpass.pop_debug_group();
+    }
+
+    pub fn after_submit(&mut self) {
+        for effect in &mut self.effects {
+            effect.after_submit();
+        }
+    }
+
+    // One collapsible section per effect, in chain order, the header enables and reorders it
+    pub fn ui(&mut self, ui: &mut egui::Ui, device: &Device, queue: &Queue) {
+        let ctx = EffectContext { device, queue, size: self.size, inputs: &self.views };
+        let count = self.effects.len();
+        let mut move_up = None;
+
+        for (index, effect) in self.effects.iter_mut().enumerate() {
+            let id = ui.make_persistent_id(("post_effect", effect.kind().name()));
+            egui::collapsing_header::CollapsingState::load_with_default_open(ui.ctx(), id, false)
+                .show_header(ui, |ui| {
+                    let mut enabled = effect.enabled();
+                    if ui.checkbox(&mut enabled, effect.kind().name()).changed() {
+                        effect.set_enabled(enabled);
+                    }
+                    if ui.add_enabled(index > 0, egui::Button::new("Up").small()).clicked() {
+                        move_up = Some(index);
+                    }
+                    if ui.add_enabled(index + 1 < count, egui::Button::new("Down").small()).clicked() {
+                        move_up = Some(index + 1);
+                    }
+                })
+                .body(|ui| effect.ui(ui, &ctx));
+        }
+
+        if let Some(index) = move_up {
+            self.move_up(index);
+        }
+    }
+}
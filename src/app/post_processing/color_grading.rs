@@ -0,0 +1,550 @@
+use std::fmt;
+use std::time;
+
+use wgpu::*;
+use bytemuck::{Pod, Zeroable};
+
+use crate::config::Config;
+use crate::app::timestamps::TimestampScope;
+use crate::app::render::HDR_FORMAT;
+use super::chain::{self, EffectContext, PostEffect, PostEffectKind};
+
+/*
+    Color grading after tonemapping, lift / gamma / gain, saturation and an Adobe / Resolve .cube 3D LUT.
+    Expects tonemapped input, so it follows Present in the default chain order.
+    The LUT file is polled for changes every RELOAD_INTERVAL, a file that fails to parse keeps the previous LUT.
+    LUT entries are stored as Rgb10a2Unorm, which is filterable everywhere and precise enough for grading.
+*/
+
+const RELOAD_INTERVAL: f32 = 0.5; // seconds
+const MAX_LUT_SIZE: u32 = 256;
+
+#[derive(Debug)]
+pub enum CubeError {
+    Io(String),
+    InvalidNumber { line: usize, token: String },
+    MissingValue { line: usize },
+    TrailingToken { line: usize, token: String },
+    UnknownKeyword { line: usize, keyword: String },
+    Unsupported1D { line: usize },
+    MissingSize,
+    SizeOutOfRange(u32),
+    InvalidDomain,
+    EntryCount { expected: usize, found: usize },
+}
+
+impl fmt::Display for CubeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CubeError::Io(err) => write!(f, "{}", err),
+            CubeError::InvalidNumber { line, token } => write!(f, "line {}: expected a number, found {}", line, token),
+            CubeError::MissingValue { line } => write!(f, "line {}: expected a number, found the end of the line", line),
+            CubeError::TrailingToken { line, token } => write!(f, "line {}: unexpected {} after the values", line, token),
+            CubeError::UnknownKeyword { line, keyword } => write!(f, "line {}: unknown keyword {}", line, keyword),
+            CubeError::Unsupported1D { line } => write!(f, "line {}: 1D LUTs aren't supported", line),
+            CubeError::MissingSize => write!(f, "missing LUT_3D_SIZE"),
+            CubeError::SizeOutOfRange(size) => write!(f, "LUT_3D_SIZE {} outside 2..={}", size, MAX_LUT_SIZE),
+            CubeError::InvalidDomain => write!(f, "DOMAIN_MAX has to be above DOMAIN_MIN"),
+            CubeError::EntryCount { expected, found } => write!(f, "expected {} entries, found {}", expected, found),
+        }
+    }
+}
+
+// Red varies fastest, matching the texel order of a 3D texture
+pub struct CubeLut {
+    pub size: u32,
+    pub domain_min: [f32; 3],
+    pub domain_max: [f32; 3],
+    pub entries: Vec<[f32; 3]>,
+}
+
+impl CubeLut {
+    fn identity() -> Self {
+        Self {
+            size: 2,
+            domain_min: [0.0; 3],
+            domain_max: [1.0; 3],
+            entries: (0..8).map(|i| [(i & 1) as f32, ((i >> 1) & 1) as f32, ((i >> 2) & 1) as f32]).collect(),
+        }
+    }
+
+    pub fn load(path: &str) -> Result<Self, CubeError> {
+        let text = std::fs::read_to_string(path).map_err(|e| CubeError::Io(e.to_string()))?;
+        Self::parse(&text)
+    }
+
+    pub fn parse(text: &str) -> Result<Self, CubeError> {
+        let mut size = None;
+        let mut domain_min = [0.0; 3];
+        let mut domain_max = [1.0; 3];
+        let mut entries = Vec::new();
+
+        for (index, line) in text.lines().enumerate() {
+            let line_number = index + 1;
+            let line = line.split('#').next().unwrap_or("").trim();
+            if line.is_empty() {
+                continue;
+            }
+
+            let mut tokens = line.split_whitespace();
+            let first = tokens.next().unwrap_or("");
+            let missing = || CubeError::MissingValue { line: line_number };
+            let end = |tokens: &mut std::str::SplitWhitespace| match tokens.next() {
+                Some(token) => Err(CubeError::TrailingToken { line: line_number, token: token.to_string() }),
+                None => Ok(()),
+            };
+            let number = |token: &str| token.parse::<f32>().map_err(|_| CubeError::InvalidNumber { line: line_number, token: token.to_string() });
+            // Exactly three values, for the domain and the entries
+            let triple = |tokens: &mut std::str::SplitWhitespace| -> Result<[f32; 3], CubeError> {
+                let mut value = [0.0; 3];
+                for v in value.iter_mut() {
+                    *v = number(tokens.next().ok_or_else(missing)?)?;
+                }
+                end(tokens)?;
+                Ok(value)
+            };
+
+            match first {
+                "TITLE" => {},
+                "LUT_3D_SIZE" => {
+                    let token = tokens.next().ok_or_else(missing)?;
+                    let n = token.parse::<u32>().map_err(|_| CubeError::InvalidNumber { line: line_number, token: token.to_string() })?;
+                    end(&mut tokens)?;
+                    if !(2..=MAX_LUT_SIZE).contains(&n) {
+                        return Err(CubeError::SizeOutOfRange(n));
+                    }
+                    size = Some(n);
+                },
+                "LUT_1D_SIZE" => return Err(CubeError::Unsupported1D { line: line_number }),
+                "DOMAIN_MIN" => domain_min = triple(&mut tokens)?,
+                "DOMAIN_MAX" => domain_max = triple(&mut tokens)?,
+                // Resolve writes this one, the input range is already given by the domain
+                "LUT_3D_INPUT_RANGE" => {},
+                keyword if keyword.starts_with(|c: char| c.is_ascii_alphabetic()) => {
+                    return Err(CubeError::UnknownKeyword { line: line_number, keyword: keyword.to_string() });
+                },
+                _ => {
+                    let mut tokens = line.split_whitespace();
+                    entries.push(triple(&mut tokens)?);
+                }
+            }
+        }
+
+        let size = size.ok_or(CubeError::MissingSize)?;
+        if (0..3).any(|i| domain_max[i] <= domain_min[i]) {
+            return Err(CubeError::InvalidDomain);
+        }
+        let expected = (size * size * size) as usize;
+        if entries.len() != expected {
+            return Err(CubeError::EntryCount { expected, found: entries.len() });
+        }
+
+        Ok(Self { size, domain_min, domain_max, entries })
+    }
+
+    // 10 bits per channel, packed as r | g << 10 | b << 20 | a << 30
+    fn pack(&self) -> Vec<u32> {
+        let channel = |c: f32| (c.clamp(0.0, 1.0) * 1023.0).round() as u32;
+        self.entries.iter().map(|[r, g, b]| channel(*r) | channel(*g) << 10 | channel(*b) << 20 | 3 << 30).collect()
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GradingUniform {
+    lift: [f32; 4],
+    gamma: [f32; 4],
+    gain: [f32; 4],
+    domain_min: [f32; 4],
+    domain_max: [f32; 4],
+    saturation: f32,
+    lut_intensity: f32,
+    lut_size: f32,
+    _padding: u32,
+}
+
+pub struct ColorGrading {
+    lut_texture: Texture,
+    lut_view: TextureView,
+    lut_size: u32,
+    domain: ([f32; 3], [f32; 3]),
+    lut_sampler: Sampler,
+    bindgroup_layout: BindGroupLayout,
+    bindgroups: [BindGroup; 2],
+    pipeline: RenderPipeline,
+    settings_buffer: Buffer,
+
+    lut_modified: Option<time::SystemTime>,
+    last_poll: time::Instant,
+    pub lut_error: Option<String>,
+
+    // Settings
+    pub enabled: bool,
+    pub lut_path: String, // Empty for no LUT
+    pub lut_intensity: f32,
+    pub lift: [f32; 3],
+    pub gamma: [f32; 3],
+    pub gain: [f32; 3],
+    pub saturation: f32,
+}
+
+impl ColorGrading {
+    pub fn new(device: &Device, queue: &Queue, config: &Config, inputs: &[TextureView; 2]) -> Self {
+        let grading_config = &config.color_grading_config;
+
+        let identity = CubeLut::identity();
+        let (lut_texture, lut_view) = Self::create_lut_texture(device, queue, &identity);
+
+        let lut_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Color Grading LUT Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Color Grading Bindgroup Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D3,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None
+                }
+            ]
+        });
+
+        let settings_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Color Grading Settings Uniform"),
+            size: std::mem::size_of::<GradingUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let bindgroups = Self::create_bind_groups(device, &bindgroup_layout, inputs, &lut_view, &lut_sampler, &settings_buffer);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Color Grading Pipeline Layout"),
+            bind_group_layouts: &[
+                &bindgroup_layout
+            ],
+            push_constant_ranges: &[]
+        });
+
+        let shader = device.create_shader_module(include_wgsl!("../shaders/color_grading.wgsl"));
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Color Grading Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs",
+                targets: &[
+                    Some(ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend: Some(BlendState::REPLACE),
+                        write_mask: ColorWrites::ALL
+                    })
+                ]
+            }),
+            multisample: MultisampleState::default(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multiview: None
+        });
+
+        let mut grading = Self {
+            lut_texture,
+            lut_view,
+            lut_size: identity.size,
+            domain: (identity.domain_min, identity.domain_max),
+            lut_sampler,
+            bindgroup_layout,
+            bindgroups,
+            pipeline,
+            settings_buffer,
+
+            lut_modified: None,
+            last_poll: time::Instant::now(),
+            lut_error: None,
+
+            enabled: grading_config.enabled,
+            lut_path: grading_config.lut.clone(),
+            lut_intensity: grading_config.lut_intensity,
+            lift: grading_config.lift,
+            gamma: grading_config.gamma,
+            gain: grading_config.gain,
+            saturation: grading_config.saturation,
+        };
+        grading.reload_lut(device, queue, inputs);
+        grading
+    }
+
+    fn create_lut_texture(device: &Device, queue: &Queue, lut: &CubeLut) -> (Texture, TextureView) {
+        let size = Extent3d {
+            width: lut.size,
+            height: lut.size,
+            depth_or_array_layers: lut.size
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Color Grading LUT"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D3,
+            format: TextureFormat::Rgb10a2Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[]
+        });
+
+        queue.write_texture(
+            ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            bytemuck::cast_slice(&lut.pack()),
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * lut.size),
+                rows_per_image: Some(lut.size),
+            },
+            size,
+        );
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        (texture, view)
+    }
+
+    fn create_bind_groups(device: &Device, layout: &BindGroupLayout, inputs: &[TextureView; 2], lut_view: &TextureView, lut_sampler: &Sampler, settings_buffer: &Buffer) -> [BindGroup; 2] {
+        inputs.each_ref().map(|input_view| device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Color Grading Bindgroup"),
+            layout: layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(input_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(lut_view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(lut_sampler),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: settings_buffer.as_entire_binding(),
+                }
+            ]
+        }))
+    }
+
+    // Loads lut_path, an empty path goes back to the identity LUT, errors keep the current one
+    // The modification time is only recorded once a load succeeds, so a half written file gets retried
+    pub fn reload_lut(&mut self, device: &Device, queue: &Queue, inputs: &[TextureView; 2]) {
+        let modified = std::fs::metadata(&self.lut_path).and_then(|m| m.modified()).ok();
+
+        let lut = if self.lut_path.is_empty() {
+            CubeLut::identity()
+        } else {
+            match CubeLut::load(&self.lut_path) {
+                Ok(lut) => lut,
+                Err(err) => {
+                    // Retried on every poll, only warn when the error changes
+                    let err = err.to_string();
+                    if self.lut_error.as_ref() != Some(&err) {
+                        log::warn!("Couldn't load LUT {}: {}", self.lut_path, err);
+                    }
+                    self.lut_error = Some(err);
+                    return;
+                }
+            }
+        };
+        self.lut_error = None;
+        self.lut_modified = modified;
+
+        // Dropped rather than destroyed, its upload or this frame's commands may still be pending
+        (self.lut_texture, self.lut_view) = Self::create_lut_texture(device, queue, &lut);
+        self.lut_size = lut.size;
+        self.domain = (lut.domain_min, lut.domain_max);
+        self.bindgroups = Self::create_bind_groups(device, &self.bindgroup_layout, inputs, &self.lut_view, &self.lut_sampler, &self.settings_buffer);
+    }
+}
+
+impl PostEffect for ColorGrading {
+    fn kind(&self) -> PostEffectKind {
+        PostEffectKind::ColorGrading
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn resize(&mut self, ctx: &EffectContext) {
+        self.bindgroups = Self::create_bind_groups(ctx.device, &self.bindgroup_layout, ctx.inputs, &self.lut_view, &self.lut_sampler, &self.settings_buffer);
+    }
+
+    fn update(&mut self, ctx: &EffectContext, _dt: f32) {
+        if !self.lut_path.is_empty() && self.last_poll.elapsed().as_secs_f32() >= RELOAD_INTERVAL {
+            self.last_poll = time::Instant::now();
+            let modified = std::fs::metadata(&self.lut_path).and_then(|m| m.modified()).ok();
+            if modified.is_some() && modified != self.lut_modified {
+                self.reload_lut(ctx.device, ctx.queue, ctx.inputs);
+            }
+        }
+
+        let extend = |v: [f32; 3]| [v[0], v[1], v[2], 0.0];
+        ctx.queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[GradingUniform {
+            lift: extend(self.lift),
+            gamma: extend(self.gamma),
+            gain: extend(self.gain),
+            domain_min: extend(self.domain.0),
+            domain_max: extend(self.domain.1),
+            saturation: self.saturation,
+            lut_intensity: self.lut_intensity,
+            lut_size: self.lut_size as f32,
+            _padding: 0,
+        }]));
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, _ctx: &EffectContext, timestamps: Option<TimestampScope>, input: usize, output: &TextureView) {
+        let mut rpass = chain::fullscreen_pass(encoder, "Color Grading Pass", output, timestamps.map(|scope| scope.render_writes(true, true)));
+        rpass.push_debug_group("Color Grading Render Pass");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bindgroups[input], &[]);
+        rpass.draw(0..6, 0..1);
+        rpass.pop_debug_group();
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &EffectContext) {
+        for (values, name, range) in [(&mut self.lift, "Lift", -0.5..=0.5), (&mut self.gamma, "Gamma", 0.2..=3.0), (&mut self.gain, "Gain", 0.0..=2.0)] {
+            ui.horizontal(|ui| {
+                ui.label(name);
+                for (value, channel) in values.iter_mut().zip(["R", "G", "B"]) {
+                    ui.add(egui::DragValue::new(value).speed(0.005).clamp_range(range.clone()).prefix(format!("{}: ", channel)));
+                }
+            });
+        }
+        ui.add(egui::Slider::new(&mut self.saturation, 0.0..=2.0).text("Saturation"));
+        let mut reload = false;
+        ui.horizontal(|ui| {
+            ui.label("LUT (.cube)");
+            ui.text_edit_singleline(&mut self.lut_path);
+            reload = ui.button("Load").clicked();
+        });
+        if reload {
+            self.reload_lut(ctx.device, ctx.queue, ctx.inputs);
+        }
+        ui.add(egui::Slider::new(&mut self.lut_intensity, 0.0..=1.0).text("LUT Intensity"));
+        if let Some(err) = &self.lut_error {
+            ui.colored_label(egui::Color32::LIGHT_RED, err);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Size 2 header followed by the given data lines
+    fn cube(entries: &[&str]) -> String {
+        format!("TITLE \"test\"\nLUT_3D_SIZE 2\n{}\n", entries.join("\n"))
+    }
+
+    const IDENTITY: [&str; 8] = ["0 0 0", "1 0 0", "0 1 0", "1 1 0", "0 0 1", "1 0 1", "0 1 1", "1 1 1"];
+
+    #[test]
+    fn parses_identity() {
+        let lut = CubeLut::parse(&cube(&IDENTITY)).unwrap();
+        assert_eq!(lut.size, 2);
+        assert_eq!(lut.entries, CubeLut::identity().entries);
+    }
+
+    #[test]
+    fn rejects_bad_size() {
+        let with_size = |size: &str| CubeLut::parse(&format!("LUT_3D_SIZE{}\n{}\n", size, IDENTITY.join("\n")));
+        assert!(matches!(with_size(" 1"), Err(CubeError::SizeOutOfRange(1))));
+        assert!(matches!(with_size(" 257"), Err(CubeError::SizeOutOfRange(257))));
+        assert!(matches!(with_size(" two"), Err(CubeError::InvalidNumber { line: 1, token }) if token == "two"));
+        assert!(matches!(with_size(" -2"), Err(CubeError::InvalidNumber { line: 1, .. })));
+        assert!(matches!(with_size(""), Err(CubeError::MissingValue { line: 1 })));
+        assert!(matches!(with_size(" 2 2"), Err(CubeError::TrailingToken { line: 1, token }) if token == "2"));
+        assert!(matches!(CubeLut::parse(&IDENTITY.join("\n")), Err(CubeError::MissingSize)));
+    }
+
+    #[test]
+    fn rejects_wrong_entry_count() {
+        assert!(matches!(CubeLut::parse(&cube(&IDENTITY[..7])), Err(CubeError::EntryCount { expected: 8, found: 7 })));
+
+        let mut long = IDENTITY.to_vec();
+        long.push("0.5 0.5 0.5");
+        assert!(matches!(CubeLut::parse(&cube(&long)), Err(CubeError::EntryCount { expected: 8, found: 9 })));
+    }
+
+    #[test]
+    fn rejects_non_numeric_tokens() {
+        let mut entries = IDENTITY;
+        entries[3] = "1 one 0";
+        assert!(matches!(CubeLut::parse(&cube(&entries)), Err(CubeError::InvalidNumber { line: 6, token }) if token == "one"));
+
+        assert!(matches!(CubeLut::parse("LUT_3D_SIZE 2\nDOMAIN_MIN 0 0 zero\n"), Err(CubeError::InvalidNumber { line: 2, .. })));
+    }
+
+    #[test]
+    fn rejects_wrong_value_count() {
+        let mut entries = IDENTITY;
+        entries[2] = "0 1";
+        assert!(matches!(CubeLut::parse(&cube(&entries)), Err(CubeError::MissingValue { line: 5 })));
+
+        entries[2] = "0 1 0 1";
+        assert!(matches!(CubeLut::parse(&cube(&entries)), Err(CubeError::TrailingToken { line: 5, token }) if token == "1"));
+
+        assert!(matches!(CubeLut::parse("LUT_3D_SIZE 2\nDOMAIN_MAX 1 1\n"), Err(CubeError::MissingValue { line: 2 })));
+    }
+}
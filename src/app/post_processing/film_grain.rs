@@ -0,0 +1,209 @@
+use wgpu::*;
+use bytemuck::{Pod, Zeroable};
+
+use crate::config::Config;
+use crate::app::timestamps::TimestampScope;
+use crate::app::render::HDR_FORMAT;
+use super::chain::{self, EffectContext, PostEffect, PostEffectKind};
+
+/*
+    Physically based film grain, applied to the tonemapped image.
+    The input is tonemapped but still linear, the shader converts to display space itself.
+*/
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct GrainUniform {
+    grain_radius: f32,
+    intensity: f32,
+    color_variation: f32,
+    filter_sigma: f32,
+    seed: u32,
+    samples: u32,
+    _padding: [u32; 2],
+}
+
+pub struct FilmGrain {
+    bindgroup_layout: BindGroupLayout,
+    bindgroups: [BindGroup; 2],
+    pipeline: RenderPipeline,
+    settings_buffer: Buffer,
+    frame: u32,
+
+    // Settings
+    pub enabled: bool,
+    pub grain_radius: f32,
+    pub intensity: f32,
+    pub color_variation: f32,
+    pub filter_sigma: f32,
+    pub samples: u32,
+    pub animated: bool,
+    // Fixed seed and no animation, for golden-image comparisons on the same GPU
+    pub deterministic: bool,
+    pub seed: u32,
+}
+
+impl FilmGrain {
+    pub fn new(device: &Device, config: &Config, inputs: &[TextureView; 2]) -> Self {
+        let bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Film Grain Bindgroup Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: false },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None
+                }
+            ]
+        });
+
+        let settings_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Film Grain Settings Uniform"),
+            size: std::mem::size_of::<GrainUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let bindgroups = Self::create_bind_groups(device, &bindgroup_layout, inputs, &settings_buffer);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Film Grain Pipeline Layout"),
+            bind_group_layouts: &[
+                &bindgroup_layout
+            ],
+            push_constant_ranges: &[]
+        });
+
+        let shader = device.create_shader_module(include_wgsl!("../shaders/film_grain.wgsl"));
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Film Grain Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs",
+                targets: &[
+                    Some(ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend: Some(BlendState::REPLACE),
+                        write_mask: ColorWrites::ALL
+                    })
+                ]
+            }),
+            multisample: MultisampleState::default(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multiview: None
+        });
+
+        let grain_config = &config.film_grain_config;
+
+        Self {
+            bindgroup_layout,
+            bindgroups,
+            pipeline,
+            settings_buffer,
+            frame: 0,
+
+            enabled: grain_config.enabled,
+            grain_radius: grain_config.grain_radius,
+            intensity: grain_config.intensity,
+            color_variation: grain_config.color_variation,
+            filter_sigma: grain_config.filter_sigma,
+            samples: grain_config.samples,
+            animated: grain_config.animated,
+            deterministic: grain_config.deterministic,
+            seed: grain_config.seed,
+        }
+    }
+
+    fn create_bind_groups(device: &Device, layout: &BindGroupLayout, inputs: &[TextureView; 2], settings_buffer: &Buffer) -> [BindGroup; 2] {
+        inputs.each_ref().map(|input_view| device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Film Grain Bindgroup"),
+            layout: layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(input_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: settings_buffer.as_entire_binding(),
+                }
+            ]
+        }))
+    }
+}
+
+impl PostEffect for FilmGrain {
+    fn kind(&self) -> PostEffectKind {
+        PostEffectKind::FilmGrain
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn resize(&mut self, ctx: &EffectContext) {
+        self.bindgroups = Self::create_bind_groups(ctx.device, &self.bindgroup_layout, ctx.inputs, &self.settings_buffer);
+    }
+
+    fn update(&mut self, ctx: &EffectContext, _dt: f32) {
+        if self.animated && !self.deterministic {
+            self.frame = self.frame.wrapping_add(1);
+        }
+        let seed = if self.deterministic { self.seed } else { self.seed.wrapping_add(self.frame) };
+
+        ctx.queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[GrainUniform {
+            grain_radius: self.grain_radius.max(0.05),
+            intensity: self.intensity,
+            color_variation: self.color_variation,
+            filter_sigma: self.filter_sigma,
+            seed,
+            samples: self.samples,
+            _padding: [0; 2],
+        }]));
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, _ctx: &EffectContext, timestamps: Option<TimestampScope>, input: usize, output: &TextureView) {
+        let mut rpass = chain::fullscreen_pass(encoder, "Film Grain Pass", output, timestamps.map(|scope| scope.render_writes(true, true)));
+        rpass.push_debug_group("Film Grain Render Pass");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bindgroups[input], &[]);
+        rpass.draw(0..6, 0..1);
+        rpass.pop_debug_group();
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, _ctx: &EffectContext) {
+        ui.add(egui::Slider::new(&mut self.grain_radius, 0.05..=3.0).text("Grain Radius (px)"));
+        ui.add(egui::Slider::new(&mut self.intensity, 0.0..=1.0).text("Intensity"));
+        ui.add(egui::Slider::new(&mut self.color_variation, 0.0..=1.0).text("Color Variation"));
+        ui.add(egui::Slider::new(&mut self.filter_sigma, 0.1..=2.0).text("Filter Sigma (px)"));
+        ui.add(egui::Slider::new(&mut self.samples, 1..=64).text("Samples"));
+        ui.checkbox(&mut self.animated, "Animated");
+        ui.checkbox(&mut self.deterministic, "Deterministic");
+    }
+}
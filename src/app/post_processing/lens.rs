@@ -0,0 +1,211 @@
+use wgpu::*;
+use bytemuck::{Pod, Zeroable};
+
+use crate::config::Config;
+use crate::app::timestamps::TimestampScope;
+use crate::app::render::HDR_FORMAT;
+use super::chain::{self, EffectContext, PostEffect, PostEffectKind};
+
+/*
+    Lens stage: radial chromatic aberration, vignette and barrel / pincushion distortion.
+    Sits between ColorGrading and FilmGrain in the default chain order.
+*/
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct LensUniform {
+    distortion: f32,
+    chromatic_aberration: f32,
+    vignette: f32,
+    vignette_falloff: f32,
+    aspect_ratio: f32,
+    _padding: [u32; 3],
+}
+
+pub struct Lens {
+    sampler: Sampler,
+    bindgroup_layout: BindGroupLayout,
+    bindgroups: [BindGroup; 2],
+    pipeline: RenderPipeline,
+    settings_buffer: Buffer,
+
+    // Settings
+    pub enabled: bool,
+    pub distortion: f32,
+    pub chromatic_aberration: f32,
+    pub vignette: f32,
+    pub vignette_falloff: f32,
+}
+
+impl Lens {
+    pub fn new(device: &Device, config: &Config, inputs: &[TextureView; 2]) -> Self {
+        let sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Lens Sampler"),
+            address_mode_u: AddressMode::ClampToEdge,
+            address_mode_v: AddressMode::ClampToEdge,
+            address_mode_w: AddressMode::ClampToEdge,
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            mipmap_filter: FilterMode::Nearest,
+            ..Default::default()
+        });
+
+        let bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Lens Bindgroup Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None
+                }
+            ]
+        });
+
+        let settings_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Lens Settings Uniform"),
+            size: std::mem::size_of::<LensUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let bindgroups = Self::create_bind_groups(device, &bindgroup_layout, inputs, &sampler, &settings_buffer);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Lens Pipeline Layout"),
+            bind_group_layouts: &[
+                &bindgroup_layout
+            ],
+            push_constant_ranges: &[]
+        });
+
+        let shader = device.create_shader_module(include_wgsl!("../shaders/lens.wgsl"));
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Lens Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs",
+                targets: &[
+                    Some(ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend: Some(BlendState::REPLACE),
+                        write_mask: ColorWrites::ALL
+                    })
+                ]
+            }),
+            multisample: MultisampleState::default(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multiview: None
+        });
+
+        let lens_config = &config.lens_config;
+
+        Self {
+            sampler,
+            bindgroup_layout,
+            bindgroups,
+            pipeline,
+            settings_buffer,
+
+            enabled: lens_config.enabled,
+            distortion: lens_config.distortion,
+            chromatic_aberration: lens_config.chromatic_aberration,
+            vignette: lens_config.vignette,
+            vignette_falloff: lens_config.vignette_falloff,
+        }
+    }
+
+    fn create_bind_groups(device: &Device, layout: &BindGroupLayout, inputs: &[TextureView; 2], sampler: &Sampler, settings_buffer: &Buffer) -> [BindGroup; 2] {
+        inputs.each_ref().map(|input_view| device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Lens Bindgroup"),
+            layout: layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(input_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::Sampler(sampler),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: settings_buffer.as_entire_binding(),
+                }
+            ]
+        }))
+    }
+}
+
+impl PostEffect for Lens {
+    fn kind(&self) -> PostEffectKind {
+        PostEffectKind::Lens
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn resize(&mut self, ctx: &EffectContext) {
+        self.bindgroups = Self::create_bind_groups(ctx.device, &self.bindgroup_layout, ctx.inputs, &self.sampler, &self.settings_buffer);
+    }
+
+    fn update(&mut self, ctx: &EffectContext, _dt: f32) {
+        ctx.queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[LensUniform {
+            distortion: self.distortion,
+            chromatic_aberration: self.chromatic_aberration,
+            vignette: self.vignette,
+            vignette_falloff: self.vignette_falloff,
+            aspect_ratio: ctx.size.width as f32 / ctx.size.height.max(1) as f32,
+            _padding: [0; 3],
+        }]));
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, _ctx: &EffectContext, timestamps: Option<TimestampScope>, input: usize, output: &TextureView) {
+        let mut rpass = chain::fullscreen_pass(encoder, "Lens Pass", output, timestamps.map(|scope| scope.render_writes(true, true)));
+        rpass.push_debug_group("Lens Render Pass");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bindgroups[input], &[]);
+        rpass.draw(0..6, 0..1);
+        rpass.pop_debug_group();
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, _ctx: &EffectContext) {
+        ui.add(egui::Slider::new(&mut self.distortion, -0.5..=0.5).text("Distortion (Barrel / Pincushion)"));
+        ui.add(egui::Slider::new(&mut self.chromatic_aberration, 0.0..=0.05).text("Chromatic Aberration"));
+        ui.add(egui::Slider::new(&mut self.vignette, 0.0..=1.0).text("Vignette"));
+        ui.add(egui::Slider::new(&mut self.vignette_falloff, 0.0..=2.0).text("Vignette Falloff"));
+    }
+}
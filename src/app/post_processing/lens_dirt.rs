@@ -0,0 +1,495 @@
+use rand::{Rng, SeedableRng};
+use rand::rngs::StdRng;
+use wgpu::*;
+use bytemuck::{Pod, Zeroable};
+
+use crate::config::Config;
+use crate::app::timestamps::TimestampScope;
+use crate::app::render::HDR_FORMAT;
+use super::chain::{EffectContext, PostEffect, PostEffectKind};
+
+/*
+    Screen / lens dirt, the dirt texture is multiplied by bloom's (un-composited) mip 0
+    and added onto the chain image, so smudges only appear around bright regions.
+    It draws additively into its input instead of ping-ponging, like auto exposure it doesn't swap the textures.
+    Mip 0 belongs to bloom, the chain binds it here whenever bloom recreates its mip chain, and only lets
+    the dirt draw while bloom runs earlier in the same frame.
+    The dirt is either loaded from a Netpbm file (.ppm / .pgm) or generated procedurally.
+*/
+
+const PROCEDURAL_SIZE: u32 = 512;
+// Largest side accepted from a dirt file, the default wgpu texture limit
+const MAX_IMAGE_SIZE: u32 = 8192;
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct LensDirtUniform {
+    intensity: f32,
+    _padding: [u32; 3],
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum DirtSource {
+    Procedural,
+    File,
+}
+
+// RGBA8 pixels
+struct DirtImage {
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+}
+
+pub struct LensDirt {
+    dirt_texture: Texture,
+    dirt_view: TextureView,
+    dirt_sampler: Sampler,
+    file_image: Option<DirtImage>,
+
+    bindgroup_layout: BindGroupLayout,
+    bindgroup: BindGroup,
+    pipeline: RenderPipeline,
+    settings_buffer: Buffer,
+
+    // Bloom mip 0 the bindgroup samples, None once the dirt texture changed
+    bound_view: Option<Id<TextureView>>,
+    // Set by the chain, bloom ran before this effect and mip 0 is this frame's
+    pub bloom_ready: bool,
+
+    // Settings
+    pub enabled: bool,
+    pub intensity: f32,
+    pub source: DirtSource,
+    pub seed: u64,
+}
+
+impl LensDirt {
+    pub fn new(device: &Device, queue: &Queue, config: &Config, bloom_view: &TextureView) -> Self {
+        let dirt_config = &config.lens_dirt_config;
+
+        let file_image = if dirt_config.texture.is_empty() {
+            None
+        } else {
+            match load_netpbm(&dirt_config.texture) {
+                Ok(image) => Some(image),
+                Err(err) => {
+                    log::warn!("Couldn't load lens dirt texture {}: {}, using procedural dirt", dirt_config.texture, err);
+                    None
+                }
+            }
+        };
+        let source = if file_image.is_some() { DirtSource::File } else { DirtSource::Procedural };
+
+        let procedural;
+        let image = match &file_image {
+            Some(image) => image,
+            None => {
+                procedural = generate_dirt(dirt_config.seed);
+                &procedural
+            }
+        };
+        let (dirt_texture, dirt_view) = Self::create_dirt_texture(device, queue, image);
+
+        let dirt_sampler = device.create_sampler(&SamplerDescriptor {
+            label: Some("Lens Dirt Sampler"),
+            mag_filter: FilterMode::Linear,
+            min_filter: FilterMode::Linear,
+            ..Default::default()
+        });
+
+        let bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Lens Dirt Bindgroup Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture {
+                        sample_type: TextureSampleType::Float { filterable: true },
+                        view_dimension: TextureViewDimension::D2,
+                        multisampled: false
+                    },
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Sampler(SamplerBindingType::Filtering),
+                    count: None
+                },
+                BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None
+                }
+            ]
+        });
+
+        let settings_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Lens Dirt Settings Uniform"),
+            size: std::mem::size_of::<LensDirtUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false
+        });
+
+        let bindgroup = Self::create_bind_group(device, &bindgroup_layout, bloom_view, &dirt_view, &dirt_sampler, &settings_buffer);
+
+        let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
+            label: Some("Lens Dirt Pipeline Layout"),
+            bind_group_layouts: &[
+                &bindgroup_layout
+            ],
+            push_constant_ranges: &[]
+        });
+
+        let shader = device.create_shader_module(include_wgsl!("../shaders/lens_dirt.wgsl"));
+
+        let pipeline = device.create_render_pipeline(&RenderPipelineDescriptor {
+            label: Some("Lens Dirt Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: VertexState {
+                module: &shader,
+                entry_point: "vs",
+                buffers: &[],
+            },
+            fragment: Some(FragmentState {
+                module: &shader,
+                entry_point: "fs",
+                targets: &[
+                    Some(ColorTargetState {
+                        format: HDR_FORMAT,
+                        blend: Some(BlendState {
+                            color: BlendComponent {
+                                operation: BlendOperation::Add,
+                                src_factor: BlendFactor::One,
+                                dst_factor: BlendFactor::One
+                            },
+                            alpha: BlendComponent::OVER
+                        }),
+                        write_mask: ColorWrites::ALL
+                    })
+                ]
+            }),
+            multisample: MultisampleState::default(),
+            primitive: PrimitiveState::default(),
+            depth_stencil: None,
+            multiview: None
+        });
+
+        Self {
+            dirt_texture,
+            dirt_view,
+            dirt_sampler,
+            file_image,
+
+            bindgroup_layout,
+            bindgroup,
+            pipeline,
+            settings_buffer,
+
+            bound_view: Some(bloom_view.global_id()),
+            bloom_ready: false,
+
+            enabled: dirt_config.enabled,
+            intensity: dirt_config.intensity,
+            source,
+            seed: dirt_config.seed,
+        }
+    }
+
+    fn create_dirt_texture(device: &Device, queue: &Queue, image: &DirtImage) -> (Texture, TextureView) {
+        let size = Extent3d {
+            width: image.width,
+            height: image.height,
+            depth_or_array_layers: 1
+        };
+        let texture = device.create_texture(&TextureDescriptor {
+            label: Some("Lens Dirt Texture"),
+            size,
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: TextureDimension::D2,
+            format: TextureFormat::Rgba8Unorm,
+            usage: TextureUsages::TEXTURE_BINDING | TextureUsages::COPY_DST,
+            view_formats: &[]
+        });
+
+        queue.write_texture(
+            ImageCopyTexture {
+                texture: &texture,
+                mip_level: 0,
+                origin: Origin3d::ZERO,
+                aspect: TextureAspect::All,
+            },
+            &image.pixels,
+            ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * image.width),
+                rows_per_image: Some(image.height),
+            },
+            size,
+        );
+        let view = texture.create_view(&TextureViewDescriptor::default());
+
+        (texture, view)
+    }
+
+    fn create_bind_group(device: &Device, layout: &BindGroupLayout, bloom_view: &TextureView, dirt_view: &TextureView, dirt_sampler: &Sampler, settings_buffer: &Buffer) -> BindGroup {
+        device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Lens Dirt Bindgroup"),
+            layout: layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(bloom_view),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: BindingResource::TextureView(dirt_view),
+                },
+                BindGroupEntry {
+                    binding: 2,
+                    resource: BindingResource::Sampler(dirt_sampler),
+                },
+                BindGroupEntry {
+                    binding: 3,
+                    resource: settings_buffer.as_entire_binding(),
+                }
+            ]
+        })
+    }
+
+    pub fn has_file(&self) -> bool {
+        self.file_image.is_some()
+    }
+
+    // Re-uploads the dirt texture after the source or seed changed, the chain rebinds it before the next frame.
+    // The old texture isn't destroyed, this frame's commands may still sample it
+    fn recreate_dirt_texture(&mut self, device: &Device, queue: &Queue) {
+        let procedural;
+        let image = match (&self.file_image, self.source) {
+            (Some(image), DirtSource::File) => image,
+            _ => {
+                procedural = generate_dirt(self.seed);
+                &procedural
+            }
+        };
+
+        (self.dirt_texture, self.dirt_view) = Self::create_dirt_texture(device, queue, image);
+        self.bound_view = None;
+    }
+
+    pub fn is_bound_to(&self, bloom_view: &TextureView) -> bool {
+        self.bound_view == Some(bloom_view.global_id())
+    }
+
+    pub fn recreate_bindgroup(&mut self, device: &Device, bloom_view: &TextureView) {
+        self.bindgroup = Self::create_bind_group(device, &self.bindgroup_layout, bloom_view, &self.dirt_view, &self.dirt_sampler, &self.settings_buffer);
+        self.bound_view = Some(bloom_view.global_id());
+    }
+}
+
+impl PostEffect for LensDirt {
+    fn kind(&self) -> PostEffectKind {
+        PostEffectKind::LensDirt
+    }
+
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    // Draws onto its input
+    fn writes_output(&self) -> bool {
+        false
+    }
+
+    // Only samples bloom's mip 0, which the chain rebinds
+    fn resize(&mut self, _ctx: &EffectContext) {}
+
+    fn update(&mut self, ctx: &EffectContext, _dt: f32) {
+        ctx.queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[LensDirtUniform {
+            intensity: self.intensity,
+            _padding: [0; 3],
+        }]));
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, ctx: &EffectContext, timestamps: Option<TimestampScope>, input: usize, _output: &TextureView) {
+        // Mip 0 would be a stale frame
+        if !self.bloom_ready {
+            return;
+        }
+
+        let mut rpass = encoder.begin_render_pass(&RenderPassDescriptor {
+            label: Some("Lens Dirt Pass"),
+            color_attachments: &[Some(RenderPassColorAttachment {
+                view: &ctx.inputs[input],
+                resolve_target: None,
+                ops: Operations {
+                    load: LoadOp::Load,
+                    store: StoreOp::Store,
+                }
+            })],
+            depth_stencil_attachment: None,
+            occlusion_query_set: None,
+            timestamp_writes: timestamps.map(|scope| scope.render_writes(true, true)),
+        });
+        rpass.push_debug_group("Lens Dirt Render Pass");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bindgroup, &[]);
+        rpass.draw(0..6, 0..1);
+        rpass.pop_debug_group();
+    }
+
+    fn ui(&mut self, ui: &mut egui::Ui, ctx: &EffectContext) {
+        if !self.bloom_ready {
+            ui.colored_label(egui::Color32::LIGHT_RED, "Needs Bloom enabled and ahead of it in the chain");
+        }
+        ui.add(egui::Slider::new(&mut self.intensity, 0.0..=10.0).text("Intensity"));
+
+        let previous = (self.source, self.seed);
+        ui.horizontal(|ui| {
+            ui.selectable_value(&mut self.source, DirtSource::Procedural, "Procedural");
+            if self.has_file() {
+                ui.selectable_value(&mut self.source, DirtSource::File, "File");
+            }
+        });
+        if self.source == DirtSource::Procedural {
+            ui.add(egui::DragValue::new(&mut self.seed).prefix("Seed: "));
+        }
+        if previous != (self.source, self.seed) {
+            self.recreate_dirt_texture(ctx.device, ctx.queue);
+        }
+    }
+}
+
+// Soft smudges of varying size plus a sprinkling of small specks
+fn generate_dirt(seed: u64) -> DirtImage {
+    let mut rng = StdRng::seed_from_u64(seed);
+    let size = PROCEDURAL_SIZE as usize;
+    let mut intensity = vec![0.0f32; size * size];
+
+    let splat = |intensity: &mut Vec<f32>, cx: f32, cy: f32, radius: f32, strength: f32| {
+        let r = (radius * 2.0).ceil() as i32;
+        for y in (cy as i32 - r).max(0)..(cy as i32 + r).min(size as i32) {
+            for x in (cx as i32 - r).max(0)..(cx as i32 + r).min(size as i32) {
+                let d2 = (x as f32 - cx).powi(2) + (y as f32 - cy).powi(2);
+                intensity[y as usize * size + x as usize] += strength * (-d2 / (2.0 * radius * radius)).exp();
+            }
+        }
+    };
+
+    for _ in 0..40 {
+        let (cx, cy) = (rng.gen::<f32>() * size as f32, rng.gen::<f32>() * size as f32);
+        splat(&mut intensity, cx, cy, rng.gen_range(10.0..60.0), rng.gen_range(0.1..0.4));
+    }
+    for _ in 0..400 {
+        let (cx, cy) = (rng.gen::<f32>() * size as f32, rng.gen::<f32>() * size as f32);
+        splat(&mut intensity, cx, cy, rng.gen_range(0.5..3.0), rng.gen_range(0.2..1.0));
+    }
+
+    let pixels = intensity.iter().flat_map(|i| {
+        let v = (i.min(1.0) * 255.0) as u8;
+        [v, v, v, 255]
+    }).collect();
+
+    DirtImage {
+        width: PROCEDURAL_SIZE,
+        height: PROCEDURAL_SIZE,
+        pixels,
+    }
+}
+
+// Netpbm greyscale / RGB images, ASCII (P2, P3) or binary (P5, P6), 8 bit
+fn load_netpbm(path: &str) -> Result<DirtImage, String> {
+    let bytes = std::fs::read(path).map_err(|e| e.to_string())?;
+
+    // Header is whitespace separated tokens with # comments, followed by a single whitespace byte
+    let mut cursor = 0;
+    let next_token = |cursor: &mut usize| -> Result<String, String> {
+        loop {
+            while *cursor < bytes.len() && bytes[*cursor].is_ascii_whitespace() {
+                *cursor += 1;
+            }
+            if *cursor < bytes.len() && bytes[*cursor] == b'#' {
+                while *cursor < bytes.len() && bytes[*cursor] != b'\n' {
+                    *cursor += 1;
+                }
+                continue;
+            }
+            break;
+        }
+        let start = *cursor;
+        while *cursor < bytes.len() && !bytes[*cursor].is_ascii_whitespace() {
+            *cursor += 1;
+        }
+        if start == *cursor {
+            return Err("unexpected end of file".to_string());
+        }
+        Ok(String::from_utf8_lossy(&bytes[start..*cursor]).into_owned())
+    };
+    let parse = |token: String| token.parse::<u32>().map_err(|_| format!("expected a number, found {}", token));
+
+    let magic = next_token(&mut cursor)?;
+    let width = parse(next_token(&mut cursor)?)?;
+    let height = parse(next_token(&mut cursor)?)?;
+    let max_value = parse(next_token(&mut cursor)?)?;
+    if max_value == 0 || max_value > 255 {
+        return Err(format!("unsupported max value {}", max_value));
+    }
+    if width == 0 || height == 0 || width > MAX_IMAGE_SIZE || height > MAX_IMAGE_SIZE {
+        return Err(format!("unsupported size {}x{}, at most {} per side", width, height, MAX_IMAGE_SIZE));
+    }
+
+    let channels = match magic.as_str() {
+        "P2" | "P5" => 1,
+        "P3" | "P6" => 3,
+        _ => return Err(format!("unsupported format {}", magic)),
+    };
+    let count = (width as usize).checked_mul(height as usize)
+        .and_then(|pixels| pixels.checked_mul(channels))
+        .ok_or("image is too large")?;
+
+    let samples: Vec<u32> = if magic == "P5" || magic == "P6" {
+        let data = bytes.get(cursor + 1..cursor + 1 + count).ok_or("pixel data is truncated")?;
+        data.iter().map(|b| *b as u32).collect()
+    } else {
+        (0..count).map(|_| next_token(&mut cursor).and_then(parse)).collect::<Result<_, _>>()?
+    };
+    if let Some(sample) = samples.iter().find(|sample| **sample > max_value) {
+        return Err(format!("sample {} is above the max value {}", sample, max_value));
+    }
+
+    let pixels = samples.chunks(channels).flat_map(|px| {
+        let c = |v: u32| (v * 255 / max_value) as u8;
+        if channels == 1 {
+            [c(px[0]), c(px[0]), c(px[0]), 255]
+        } else {
+            [c(px[0]), c(px[1]), c(px[2]), 255]
+        }
+    }).collect();
+
+    Ok(DirtImage {
+        width,
+        height,
+        pixels,
+    })
+}
@@ -1,42 +1,145 @@
+use serde::Deserialize;
 use wgpu::*;
-use winit::dpi::PhysicalSize;
+use bytemuck::{Pod, Zeroable};
 
 use crate::config::Config;
+use crate::app::timestamps::TimestampScope;
+use super::chain::{self, EffectContext, PostEffect, PostEffectKind};
 
 /*
-    Simply renders a texture to the output screen texture,
-    will most likely not be needed
+    Tonemaps the HDR chain input, with the default chain order everything after this stage is display referred.
+    Applies the exposure adapted by AutoExposure while that runs, the buffer is bound once at creation
 */
 
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Tonemapper {
+    Linear,
+    Reinhard,
+    Uncharted2,
+    Aces,
+    Agx,
+}
+
+impl Tonemapper {
+    pub const ALL: [Tonemapper; 5] = [Tonemapper::Linear, Tonemapper::Reinhard, Tonemapper::Uncharted2, Tonemapper::Aces, Tonemapper::Agx];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Tonemapper::Linear => "Linear (Clamp)",
+            Tonemapper::Reinhard => "Reinhard",
+            Tonemapper::Uncharted2 => "Uncharted 2",
+            Tonemapper::Aces => "ACES",
+            Tonemapper::Agx => "AgX",
+        }
+    }
+
+    // Only the Reinhard and Uncharted 2 curves are parameterized by a white point
+    pub fn uses_white_point(&self) -> bool {
+        matches!(self, Tonemapper::Reinhard | Tonemapper::Uncharted2)
+    }
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct TonemapUniform {
+    tonemapper: u32, // matches the Tonemapper discriminant
+    exposure: f32, // linear scale, 2^EV
+    white_point: f32,
+    auto_exposure: u32, // scale by the adapted exposure in the exposure buffer
+}
 
 pub struct Present {
-    bindgroup_layout: BindGroupLayout,
-    bindgroup: BindGroup,
+    input_bindgroup_layout: BindGroupLayout,
+    input_bindgroups: [BindGroup; 2],
+    settings_bindgroup: BindGroup,
     pipeline: RenderPipeline,
+    settings_buffer: Buffer,
+
+    // Set by the chain, whether auto exposure ran this frame
+    pub auto_exposure: bool,
+
+    // Settings
+    pub enabled: bool,
+    pub operator: Tonemapper,
+    pub exposure: f32, // EV
+    pub white_point: f32,
 }
 
 impl Present {
-    pub fn new(device: &Device, _config: &Config, format: TextureFormat,  size: &PhysicalSize<u32>, texture_view: &TextureView) -> Self {
-        let bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
-            label: Some("Presentation Bindgroup Layout"),
-            entries: &[BindGroupLayoutEntry {
-                binding: 0,
-                visibility: ShaderStages::FRAGMENT,
-                ty: BindingType::Texture { 
-                    sample_type: TextureSampleType::Float { filterable: false }, 
-                    view_dimension: TextureViewDimension::D2, 
-                    multisampled: false 
+    pub fn new(device: &Device, config: &Config, format: TextureFormat, inputs: &[TextureView; 2], exposure_buffer: &Buffer) -> Self {
+        let input_bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Presentation Input Bindgroup Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Texture { 
+                        sample_type: TextureSampleType::Float { filterable: false }, 
+                        view_dimension: TextureViewDimension::D2, 
+                        multisampled: false 
+                    },
+                    count: None
+                }
+            ]
+        });
+
+        // Doesn't depend on the size, so it's created once
+        let settings_bindgroup_layout = device.create_bind_group_layout(&BindGroupLayoutDescriptor {
+            label: Some("Presentation Settings Bindgroup Layout"),
+            entries: &[
+                BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None
                 },
-                count: None
-            }]
+                BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: ShaderStages::FRAGMENT,
+                    ty: BindingType::Buffer {
+                        ty: BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None
+                }
+            ]
+        });
+
+        let settings_buffer = device.create_buffer(&BufferDescriptor {
+            label: Some("Tonemap Settings Uniform"),
+            size: std::mem::size_of::<TonemapUniform>() as u64,
+            usage: BufferUsages::UNIFORM | BufferUsages::COPY_DST,
+            mapped_at_creation: false
         });
 
-        let bindgroup = Present::create_bind_group(device, &bindgroup_layout, texture_view);
+        let input_bindgroups = Present::create_input_bind_groups(device, &input_bindgroup_layout, inputs);
+
+        let settings_bindgroup = device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Present Settings Bindgroup"),
+            layout: &settings_bindgroup_layout,
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                BindGroupEntry {
+                    binding: 1,
+                    resource: exposure_buffer.as_entire_binding(),
+                }
+            ]
+        });
 
         let pipeline_layout = device.create_pipeline_layout(&PipelineLayoutDescriptor {
             label: Some("Presentation Pipeline Layout"),
             bind_group_layouts: &[
-                &bindgroup_layout
+                &input_bindgroup_layout,
+                &settings_bindgroup_layout
             ],
             push_constant_ranges: &[]
         });
@@ -68,33 +171,83 @@ impl Present {
             multiview: None
         });
 
+        let tonemap_config = &config.tonemap_config;
+
         return Self {
-            bindgroup_layout: bindgroup_layout,
-            bindgroup: bindgroup,
-            pipeline: pipeline
+            input_bindgroup_layout: input_bindgroup_layout,
+            input_bindgroups: input_bindgroups,
+            settings_bindgroup: settings_bindgroup,
+            pipeline: pipeline,
+            settings_buffer: settings_buffer,
+
+            auto_exposure: false,
+
+            enabled: true,
+            operator: tonemap_config.operator,
+            exposure: tonemap_config.exposure,
+            white_point: tonemap_config.white_point,
         }
     }
 
-    pub fn create_bind_group(device: &Device, layout: &BindGroupLayout, texture_view: &TextureView) -> BindGroup {
-        device.create_bind_group(&BindGroupDescriptor {
-            label: Some("Present Shader Bindgroup"),
+    pub fn create_input_bind_groups(device: &Device, layout: &BindGroupLayout, inputs: &[TextureView; 2]) -> [BindGroup; 2] {
+        inputs.each_ref().map(|texture_view| device.create_bind_group(&BindGroupDescriptor {
+            label: Some("Present Input Bindgroup"),
             layout: layout,
-            entries: &[BindGroupEntry {
-                binding: 0,
-                resource: BindingResource::TextureView(texture_view),
-            }]
-        })
+            entries: &[
+                BindGroupEntry {
+                    binding: 0,
+                    resource: BindingResource::TextureView(texture_view),
+                }
+            ]
+        }))
+    }
+}
+
+impl PostEffect for Present {
+    fn kind(&self) -> PostEffectKind {
+        PostEffectKind::Tonemap
     }
 
-    pub fn render<'rpass>(&'rpass self, rpass: &mut wgpu::RenderPass<'rpass>) {
+    fn enabled(&self) -> bool {
+        self.enabled
+    }
+
+    fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+    }
+
+    fn resize(&mut self, ctx: &EffectContext) {
+        self.input_bindgroups = Present::create_input_bind_groups(ctx.device, &self.input_bindgroup_layout, ctx.inputs);
+    }
+
+    fn update(&mut self, ctx: &EffectContext, _dt: f32) {
+        ctx.queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[TonemapUniform {
+            tonemapper: self.operator as u32,
+            exposure: self.exposure.exp2(),
+            white_point: self.white_point,
+            auto_exposure: self.auto_exposure as u32,
+        }]));
+    }
+
+    fn render(&self, encoder: &mut CommandEncoder, _ctx: &EffectContext, timestamps: Option<TimestampScope>, input: usize, output: &TextureView) {
+        let mut rpass = chain::fullscreen_pass(encoder, "Present Pass", output, timestamps.map(|scope| scope.render_writes(true, true)));
         rpass.push_debug_group("Present Render Pass");
         rpass.set_pipeline(&self.pipeline);
-        rpass.set_bind_group(0, &self.bindgroup, &[]);
+        rpass.set_bind_group(0, &self.input_bindgroups[input], &[]);
+        rpass.set_bind_group(1, &self.settings_bindgroup, &[]);
         rpass.draw(0..6, 0..1);
         rpass.pop_debug_group();
     }
 
-    pub fn recreate_bindgroup(&mut self, device: &Device, texture_view: &TextureView) {
-        self.bindgroup = Present::create_bind_group(device, &self.bindgroup_layout, texture_view);
+    fn ui(&mut self, ui: &mut egui::Ui, _ctx: &EffectContext) {
+        egui::ComboBox::from_label("Operator")
+            .selected_text(self.operator.name())
+            .show_ui(ui, |ui| {
+                for operator in Tonemapper::ALL {
+                    ui.selectable_value(&mut self.operator, operator, operator.name());
+                }
+            });
+        ui.add(egui::Slider::new(&mut self.exposure, -10.0..=10.0).text("Exposure (EV)"));
+        ui.add_enabled(self.operator.uses_white_point(), egui::Slider::new(&mut self.white_point, 1.0..=20.0).text("White Point"));
     }
-}
\ No newline at end of file
+}
@@ -1,19 +1,189 @@
 use super::camera::{Camera, Projection};
 use super::galaxy::{Galaxy, Star};
+use super::timestamps::TimestampScope;
 
 use crate::config::Config;
 use crate::wgpu_state::WgpuState;
 use wgpu::*;
 use winit::dpi::PhysicalSize;
+use bytemuck::{Pod, Zeroable};
+use serde::Deserialize;
+
+pub mod black_body;
+pub mod colormap;
+pub mod density_splat;
+pub mod dust;
+pub mod octree_overlay;
+pub mod trails;
+pub mod visualization;
+use density_splat::DensitySplat;
+use dust::Dust;
+use octree_overlay::OctreeOverlay;
+use trails::Trails;
+use visualization::Visualization;
+
+// Scene and bloom are rendered in floating point, Present tonemaps them down
+pub const HDR_FORMAT: TextureFormat = TextureFormat::Rgba16Float;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum RenderMode {
+    Sprites,
+    DensitySplat, // See density_splat.rs
+}
+
+impl RenderMode {
+    pub const ALL: [RenderMode; 2] = [RenderMode::Sprites, RenderMode::DensitySplat];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            RenderMode::Sprites => "Star Sprites",
+            RenderMode::DensitySplat => "Density Splat",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StarProfile {
+    Gaussian,
+    Airy,
+}
+
+impl StarProfile {
+    pub const ALL: [StarProfile; 2] = [StarProfile::Gaussian, StarProfile::Airy];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StarProfile::Gaussian => "Gaussian",
+            StarProfile::Airy => "Airy Disk",
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum ColorMode {
+    Temperature,
+    Velocity,
+    Density,
+    Property, // Analysis, see visualization.rs
+}
+
+impl ColorMode {
+    pub const ALL: [ColorMode; 4] = [ColorMode::Temperature, ColorMode::Velocity, ColorMode::Density, ColorMode::Property];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            ColorMode::Temperature => "Temperature",
+            ColorMode::Velocity => "Velocity",
+            ColorMode::Density => "Density",
+            ColorMode::Property => "Property (Colormap)",
+        }
+    }
+}
+
+// Overlay line vertex, drawn with lines.wgsl
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+pub struct LineVertex {
+    pub position: [f32; 3],
+    pub color: [f32; 4], // rgb, intensity
+}
+
+impl LineVertex {
+    const ATTRIBS: [wgpu::VertexAttribute; 2] = wgpu::vertex_attr_array![0 => Float32x3, 1 => Float32x4];
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<LineVertex>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Vertex,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+}
+
+// Cells per side of the density grid used by ColorMode::Density
+const DENSITY_GRID: usize = 64;
+
+// Mass per cell of a cube grid fitted around the stars, as (origin, cell size, grid)
+fn density_grid(stars: &[Star]) -> ([f32; 3], f32, Vec<f32>) {
+    let mut min = [f32::MAX; 3];
+    let mut max = [f32::MIN; 3];
+    for star in stars {
+        for i in 0..3 {
+            min[i] = min[i].min(star.position[i]);
+            max[i] = max[i].max(star.position[i]);
+        }
+    }
+    let extent = (0..3).map(|i| max[i] - min[i]).fold(0.0f32, f32::max).max(1e-3);
+    let cell_size = extent * 1.0001 / DENSITY_GRID as f32; // Keeps the max star inside the last cell
+
+    let mut grid = vec![0.0f32; DENSITY_GRID * DENSITY_GRID * DENSITY_GRID];
+    for star in stars {
+        grid[density_cell(&min, cell_size, star)] += star.mass;
+    }
+    (min, cell_size, grid)
+}
+
+fn density_cell(origin: &[f32; 3], cell_size: f32, star: &Star) -> usize {
+    let cell = |i: usize| (((star.position[i] - origin[i]) / cell_size) as usize).min(DENSITY_GRID - 1);
+    (cell(2) * DENSITY_GRID + cell(1)) * DENSITY_GRID + cell(0)
+}
 
 pub struct Renderer {
     pub render_pipeline: wgpu::RenderPipeline,
     pub bindgroup: wgpu::BindGroup,
 
+    settings_buffer: wgpu::Buffer,
+    settings_bindgroup: wgpu::BindGroup,
+
+    density_texture: wgpu::Texture,
+    grid_origin: [f32; 3],
+    cell_size: f32,
+    max_density: f32,
+
+    pub visualization: Visualization,
+    pub density_splat: DensitySplat,
+    pub dust: Dust,
+    pub octree_overlay: OctreeOverlay,
+    pub trails: Trails,
+
+    // Settings
+    pub mode: RenderMode,
+    pub luminosity_scale: f32,
+    pub luminosity_exponent: f32,
+    pub star_size: f32,
+    pub min_star_pixels: f32,
+    pub max_star_pixels: f32,
+    pub profile: StarProfile,
+    pub color_mode: ColorMode,
+    pub velocity_range: f32,
+}
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct RenderSettingsUniform {
+    time: f32, // Myr
+    luminosity_scale: f32,
+    luminosity_exponent: f32,
+    star_size: f32,
+    min_star_pixels: f32,
+    max_star_pixels: f32,
+    profile: u32, // matches the StarProfile discriminant
+    color_mode: u32, // matches the ColorMode discriminant
+    viewport: [f32; 2],
+    velocity_range: f32,
+    max_density: f32,
+    grid_origin: [f32; 3],
+    cell_size: f32,
+    range: [f32; 2],
+    log_scale: u32,
+    _padding: u32,
 }
 
 impl Renderer {
-    pub fn new<T: Projection + Default>(device: &Device, _config: &Config, format: TextureFormat, camera: &Camera<T>) -> Self {
+    pub fn new<T: Projection + Default>(device: &Device, config: &Config, format: TextureFormat, size: &PhysicalSize<u32>, scene_view: &TextureView, camera: &Camera<T>, galaxy: &Galaxy) -> Self {
         let shader = device.create_shader_module(include_wgsl!("./shaders/render.wgsl"));
 
         let bindgroup_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
@@ -31,10 +201,120 @@ impl Renderer {
             ],
         });
 
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Render Settings Uniform"),
+            size: std::mem::size_of::<RenderSettingsUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let settings_bindgroup_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Render Settings Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::VERTEX_FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::VERTEX,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D3,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        // Never rewritten, the settings bindgroup keeps it alive
+        let lut = black_body::generate_lut();
+        let black_body_lut = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Black Body LUT"),
+            size: std::mem::size_of_val(lut.as_slice()) as u64,
+            usage: wgpu::BufferUsages::UNIFORM,
+            mapped_at_creation: true,
+        });
+        black_body_lut.slice(..).get_mapped_range_mut().copy_from_slice(bytemuck::cast_slice(&lut));
+        black_body_lut.unmap();
+
+        let visualization = Visualization::new(device, config);
+        let density_splat = DensitySplat::new(device, config, format, size, camera);
+        let dust = Dust::new(device, config, format, galaxy);
+        let octree_overlay = OctreeOverlay::new(device, config, format, camera);
+        let trails = Trails::new(device, config, format, size, scene_view, camera, galaxy);
+
+        let density_texture = device.create_texture(&wgpu::TextureDescriptor {
+            label: Some("Star Density Grid"),
+            size: wgpu::Extent3d {
+                width: DENSITY_GRID as u32,
+                height: DENSITY_GRID as u32,
+                depth_or_array_layers: DENSITY_GRID as u32,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D3,
+            format: wgpu::TextureFormat::R32Float,
+            usage: wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_DST,
+            view_formats: &[],
+        });
+        let density_view = density_texture.create_view(&wgpu::TextureViewDescriptor::default());
+
+        let settings_bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Render Settings Bind Group"),
+            layout: &settings_bindgroup_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: black_body_lut.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: wgpu::BindingResource::TextureView(&density_view),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: visualization.colormap_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
         let render_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
             label: Some("Render Pipeline Layout"),
             bind_group_layouts: &[
                 &bindgroup_layout,
+                &settings_bindgroup_layout,
             ],
             push_constant_ranges: &[],
         });
@@ -47,6 +327,7 @@ impl Renderer {
                 entry_point: "vs_main",
                 buffers: &[
                     Star::desc(),
+                    Visualization::desc(),
                 ],
             },
             fragment: Some(wgpu::FragmentState {
@@ -55,7 +336,19 @@ impl Renderer {
                 targets: &[
                     Some(wgpu::ColorTargetState {
                         format,
-                        blend: Some(wgpu::BlendState::REPLACE),
+                        // Additive, overlapping stars accumulate
+                        blend: Some(wgpu::BlendState {
+                            color: wgpu::BlendComponent {
+                                operation: wgpu::BlendOperation::Add,
+                                src_factor: wgpu::BlendFactor::One,
+                                dst_factor: wgpu::BlendFactor::One
+                            },
+                            alpha: wgpu::BlendComponent {
+                                operation: wgpu::BlendOperation::Add,
+                                src_factor: wgpu::BlendFactor::One,
+                                dst_factor: wgpu::BlendFactor::One
+                            }
+                        }),
                         write_mask: wgpu::ColorWrites::ALL,
                     })
                 ],
@@ -67,7 +360,7 @@ impl Renderer {
                 alpha_to_coverage_enabled: false,
             },
             primitive: wgpu::PrimitiveState {
-                topology: wgpu::PrimitiveTopology::PointList,
+                topology: wgpu::PrimitiveTopology::TriangleList,
                 strip_index_format: None,
                 front_face: wgpu::FrontFace::Ccw,
                 cull_mode: None,
@@ -88,17 +381,127 @@ impl Renderer {
 
         Self {
             render_pipeline,
-            bindgroup
+            bindgroup,
+            settings_buffer,
+            settings_bindgroup,
+            density_texture,
+            grid_origin: [0.0; 3],
+            cell_size: 1.0,
+            max_density: 1.0,
+            visualization,
+            density_splat,
+            dust,
+            octree_overlay,
+            trails,
+            mode: config.render_config.render_mode,
+            luminosity_scale: config.render_config.luminosity_scale,
+            luminosity_exponent: config.render_config.luminosity_exponent,
+            star_size: config.render_config.star_size,
+            min_star_pixels: config.render_config.min_star_pixels,
+            max_star_pixels: config.render_config.max_star_pixels,
+            profile: config.render_config.star_profile,
+            color_mode: config.render_config.color_mode,
+            velocity_range: config.render_config.velocity_range,
+        }
+    }
+
+    pub fn update<T: Projection + Default>(&mut self, queue: &wgpu::Queue, galaxy: &Galaxy, camera: &Camera<T>, (width, height): (u32, u32), dt: f32) {
+        self.dust.update(queue, galaxy, camera, (width, height));
+        self.octree_overlay.update(queue, galaxy);
+        self.trails.update(queue, galaxy, dt);
+
+        if self.mode == RenderMode::DensitySplat {
+            self.density_splat.update(queue, galaxy, (width, height));
+            return;
+        }
+
+        match self.color_mode {
+            ColorMode::Density => self.update_density(queue, galaxy),
+            ColorMode::Property => self.visualization.update(queue, galaxy),
+            _ => {}
+        }
+
+        queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[RenderSettingsUniform {
+            time: galaxy.time,
+            luminosity_scale: self.luminosity_scale,
+            luminosity_exponent: self.luminosity_exponent,
+            star_size: self.star_size,
+            min_star_pixels: self.min_star_pixels,
+            max_star_pixels: self.max_star_pixels,
+            profile: self.profile as u32,
+            color_mode: self.color_mode as u32,
+            viewport: [width as f32, height as f32],
+            velocity_range: self.velocity_range,
+            max_density: self.max_density,
+            grid_origin: self.grid_origin,
+            cell_size: self.cell_size,
+            range: self.visualization.range,
+            log_scale: self.visualization.log_scale as u32,
+            _padding: 0,
+        }]));
+    }
+
+    // Rebuilt every frame while coloring by density
+    fn update_density(&mut self, queue: &wgpu::Queue, galaxy: &Galaxy) {
+        let (origin, cell_size, grid) = density_grid(&galaxy.stars);
+        self.grid_origin = origin;
+        self.cell_size = cell_size;
+        self.max_density = grid.iter().cloned().fold(1e-6, f32::max);
+
+        queue.write_texture(
+            wgpu::ImageCopyTexture {
+                texture: &self.density_texture,
+                mip_level: 0,
+                origin: wgpu::Origin3d::ZERO,
+                aspect: wgpu::TextureAspect::All,
+            },
+            bytemuck::cast_slice(&grid),
+            wgpu::ImageDataLayout {
+                offset: 0,
+                bytes_per_row: Some(4 * DENSITY_GRID as u32),
+                rows_per_image: Some(DENSITY_GRID as u32),
+            },
+            self.density_texture.size(),
+        );
+    }
+
+    pub fn resize(&mut self, device: &Device, size: &PhysicalSize<u32>, scene_view: &TextureView) {
+        self.density_splat.resize(device, size);
+        self.trails.recreate_textures_and_bindgroups(device, HDR_FORMAT, size, scene_view);
+    }
+
+    // Work outside the scene pass, only the density splat has any
+    pub fn prepare<T: Projection + Default>(&self, encoder: &mut CommandEncoder, device: &Device, galaxy: &Galaxy, camera: &Camera<T>, timestamps: Option<TimestampScope>) {
+        if self.mode == RenderMode::DensitySplat {
+            self.density_splat.splat(encoder, device, galaxy, camera, timestamps);
         }
     }
 
     pub fn render<'rpass>(&'rpass self, rpass: &mut wgpu::RenderPass<'rpass>, galaxy: &'rpass Galaxy) {
-        rpass.push_debug_group("Main Render Pass");
-        rpass.set_pipeline(&self.render_pipeline);
-        rpass.set_bind_group(0, &self.bindgroup, &[]);
-        rpass.set_vertex_buffer(0, galaxy.stars_buffer.slice(..));
-        rpass.draw(0..galaxy.stars.len() as u32, 0..1);
-        rpass.pop_debug_group();
+        match self.mode {
+            RenderMode::Sprites => {
+                rpass.push_debug_group("Main Render Pass");
+                rpass.set_pipeline(&self.render_pipeline);
+                rpass.set_bind_group(0, &self.bindgroup, &[]);
+                rpass.set_bind_group(1, &self.settings_bindgroup, &[]);
+                rpass.set_vertex_buffer(0, galaxy.stars_buffer.slice(..));
+                rpass.set_vertex_buffer(1, self.visualization.attribute_buffer.slice(..));
+                rpass.draw(0..6, 0..galaxy.stars.len() as u32);
+                rpass.pop_debug_group();
+            },
+            RenderMode::DensitySplat => self.density_splat.render(rpass),
+        }
+
+        self.trails.render_history(rpass);
+
+        // Absorbs whatever was drawn above, before bloom
+        if self.dust.enabled {
+            self.dust.render(rpass);
+        }
+
+        if self.octree_overlay.enabled {
+            self.octree_overlay.render(rpass);
+        }
     }
 }
 
@@ -0,0 +1,61 @@
+/*
+    Black-body colors, integrated once on the CPU and looked up in render.wgsl.
+    Planck's law is integrated against the CIE 1931 color matching functions,
+    (multi-lobe fit of Wyman, Sloan and Shirley 2013), then converted XYZ -> linear sRGB and normalized to max 1.
+    Entries are spaced logarithmically between MIN_TEMPERATURE and MAX_TEMPERATURE.
+*/
+
+pub const LUT_SIZE: usize = 256;
+pub const MIN_TEMPERATURE: f32 = 1000.0; // Kelvin
+pub const MAX_TEMPERATURE: f32 = 40000.0;
+
+// Piecewise gaussian, different widths either side of the peak
+fn lobe(wavelength: f64, peak: f64, below: f64, above: f64) -> f64 {
+    let width = if wavelength < peak { below } else { above };
+    (-0.5 * ((wavelength - peak) / width).powi(2)).exp()
+}
+
+fn color_matching(wavelength: f64) -> [f64; 3] {
+    let x = 1.056 * lobe(wavelength, 599.8, 37.9, 31.0) + 0.362 * lobe(wavelength, 442.0, 16.0, 26.7) - 0.065 * lobe(wavelength, 501.1, 20.4, 26.2);
+    let y = 0.821 * lobe(wavelength, 568.8, 46.9, 40.5) + 0.286 * lobe(wavelength, 530.9, 16.3, 31.1);
+    let z = 1.217 * lobe(wavelength, 437.0, 11.8, 36.0) + 0.681 * lobe(wavelength, 459.0, 26.0, 13.8);
+    [x, y, z]
+}
+
+// Spectral radiance, wavelength in nm, scale doesn't matter since the result is normalized
+fn planck(wavelength: f64, temperature: f64) -> f64 {
+    const C2: f64 = 1.4387769e7; // hc / k in nm K
+    let l = wavelength * 1e-3; // um keeps the numbers in range
+    1.0 / (l.powi(5) * ((C2 / (wavelength * temperature)).exp() - 1.0))
+}
+
+pub fn temperature_to_rgb(temperature: f32) -> [f32; 3] {
+    let mut xyz = [0.0f64; 3];
+    for wavelength in (380..=780).step_by(5) {
+        let wavelength = wavelength as f64;
+        let radiance = planck(wavelength, temperature as f64);
+        let cmf = color_matching(wavelength);
+        for i in 0..3 {
+            xyz[i] += radiance * cmf[i];
+        }
+    }
+
+    let [x, y, z] = xyz;
+    let rgb = [
+        (3.2406 * x - 1.5372 * y - 0.4986 * z).max(0.0),
+        (-0.9689 * x + 1.8758 * y + 0.0415 * z).max(0.0),
+        (0.0557 * x - 0.2040 * y + 1.0570 * z).max(0.0),
+    ];
+    let max = rgb[0].max(rgb[1]).max(rgb[2]).max(1e-12);
+    rgb.map(|c| (c / max) as f32)
+}
+
+// vec4 entries to match the uniform array stride
+pub fn generate_lut() -> Vec<[f32; 4]> {
+    (0..LUT_SIZE).map(|i| {
+        let t = i as f32 / (LUT_SIZE - 1) as f32;
+        let temperature = MIN_TEMPERATURE * (MAX_TEMPERATURE / MIN_TEMPERATURE).powf(t);
+        let [r, g, b] = temperature_to_rgb(temperature);
+        [r, g, b, 1.0]
+    }).collect()
+}
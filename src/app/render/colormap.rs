@@ -0,0 +1,108 @@
+use serde::Deserialize;
+
+/*
+    Perceptual colormaps for the property visualization.
+    Viridis, magma and inferno use the polynomial fits by Matt Zucker, turbo the one published with it by Google.
+    Both return sRGB, the LUT handed to render.wgsl is converted to linear since the scene is rendered in HDR.
+*/
+
+pub const LUT_SIZE: usize = 256;
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Colormap {
+    Viridis,
+    Magma,
+    Inferno,
+    Turbo,
+}
+
+type Coefficients = [[f32; 3]; 7];
+
+const VIRIDIS: Coefficients = [
+    [0.27772734, 0.005407345, 0.3340998],
+    [0.10509304, 1.4046135, 1.3845901],
+    [-0.33086184, 0.21484756, 0.095095165],
+    [-4.6342306, -5.799101, -19.332441],
+    [6.22827, 14.179934, 56.69055],
+    [4.776385, -13.745146, -65.353035],
+    [-5.435456, 4.6458526, 26.312435],
+];
+
+const MAGMA: Coefficients = [
+    [-0.002136485, -0.000749655, -0.005386128],
+    [0.25166056, 0.67752326, 2.4940267],
+    [8.353717, -3.5777194, 0.3144679],
+    [-27.668734, 14.26473, -13.649213],
+    [52.17614, -27.943605, 12.944169],
+    [-50.768524, 29.046583, 4.234153],
+    [18.655704, -11.489774, -5.6019616],
+];
+
+const INFERNO: Coefficients = [
+    [0.00021894, 0.001651005, -0.019480897],
+    [0.10651342, 0.56395644, 3.9327123],
+    [11.602493, -3.972854, -15.942394],
+    [-41.703995, 17.4364, 44.354145],
+    [77.16293, -33.40236, -81.80731],
+    [-71.31943, 32.626064, 73.20952],
+    [25.131126, -12.242669, -23.070326],
+];
+
+const TURBO: Coefficients = [
+    [0.13572139, 0.09140261, 0.1066733],
+    [4.6153927, 2.1941884, 12.641946],
+    [-42.660324, 4.8429666, -60.582047],
+    [132.13109, -14.185034, 110.36277],
+    [-152.9424, 4.2772985, -89.90311],
+    [59.28638, 2.829566, 27.34825],
+    [0.0, 0.0, 0.0],
+];
+
+impl Colormap {
+    pub const ALL: [Colormap; 4] = [Colormap::Viridis, Colormap::Magma, Colormap::Inferno, Colormap::Turbo];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            Colormap::Viridis => "Viridis",
+            Colormap::Magma => "Magma",
+            Colormap::Inferno => "Inferno",
+            Colormap::Turbo => "Turbo",
+        }
+    }
+
+    fn coefficients(&self) -> &'static Coefficients {
+        match self {
+            Colormap::Viridis => &VIRIDIS,
+            Colormap::Magma => &MAGMA,
+            Colormap::Inferno => &INFERNO,
+            Colormap::Turbo => &TURBO,
+        }
+    }
+
+    // sRGB, t in [0, 1]
+    pub fn sample(&self, t: f32) -> [f32; 3] {
+        let t = t.clamp(0.0, 1.0);
+        let c = self.coefficients();
+        [0, 1, 2].map(|i| {
+            let value = c.iter().rev().fold(0.0, |acc, coefficient| acc * t + coefficient[i]);
+            value.clamp(0.0, 1.0)
+        })
+    }
+
+    // vec4 entries to match the uniform array stride
+    pub fn generate_lut(&self) -> Vec<[f32; 4]> {
+        (0..LUT_SIZE).map(|i| {
+            let [r, g, b] = self.sample(i as f32 / (LUT_SIZE - 1) as f32).map(srgb_to_linear);
+            [r, g, b, 1.0]
+        }).collect()
+    }
+}
+
+pub fn srgb_to_linear(c: f32) -> f32 {
+    if c <= 0.04045 {
+        c / 12.92
+    } else {
+        ((c + 0.055) / 1.055).powf(2.4)
+    }
+}
@@ -0,0 +1,322 @@
+use bytemuck::{Pod, Zeroable};
+use winit::dpi::PhysicalSize;
+
+use crate::app::camera::{Camera, Projection};
+use crate::app::galaxy::{Galaxy, Star};
+use crate::app::timestamps::TimestampScope;
+use crate::config::Config;
+
+use super::colormap::{self, Colormap};
+
+/*
+    RenderMode::DensitySplat, an alternative to the star sprites.
+    A compute pass projects every star and adds its mass to a screen-space histogram (one u32 per pixel, in fixed point
+    since there are no float atomics), the resolve pass smooths it with an optional Gaussian kernel
+    and maps log10 mass per pixel through a colormap.
+*/
+
+// Fixed point scale of the histogram, 1 / MASS_SCALE solar masses is the smallest resolvable mass
+const MASS_SCALE: f32 = 256.0;
+const WORKGROUP_SIZE: u32 = 256;
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct SplatSettingsUniform {
+    viewport: [f32; 2],
+    mass_scale: f32,
+    kernel_radius: u32,
+    log_min: f32,
+    log_max: f32,
+    brightness: f32,
+    star_count: u32,
+}
+
+pub struct DensitySplat {
+    histogram: wgpu::Buffer,
+    settings_buffer: wgpu::Buffer,
+    colormap_buffer: wgpu::Buffer,
+    uploaded_colormap: Option<Colormap>,
+    max_stars: usize, // The star binding can't exceed the storage binding limit, stars past it are left out
+    star_count: u32,
+
+    splat_bindgroup_layout: wgpu::BindGroupLayout,
+    splat_pipeline: wgpu::ComputePipeline,
+    resolve_bindgroup_layout: wgpu::BindGroupLayout,
+    resolve_bindgroup: wgpu::BindGroup,
+    resolve_pipeline: wgpu::RenderPipeline,
+
+    // Settings
+    pub kernel_radius: u32, // pixels, 0 shows the raw histogram
+    pub log_range: [f32; 2], // log10 solar masses per pixel
+    pub brightness: f32,
+    pub colormap: Colormap,
+}
+
+impl DensitySplat {
+    pub fn new<T: Projection + Default>(device: &wgpu::Device, config: &Config, format: wgpu::TextureFormat, size: &PhysicalSize<u32>, camera: &Camera<T>) -> Self {
+        let density_splat_config = &config.density_splat_config;
+
+        let histogram = Self::create_histogram(device, size);
+
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Density Splat Settings Uniform"),
+            size: std::mem::size_of::<SplatSettingsUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let colormap_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Density Splat Colormap LUT"),
+            size: (std::mem::size_of::<[f32; 4]>() * colormap::LUT_SIZE) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // Splat
+        let splat_shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/density_splat.wgsl"));
+
+        let splat_bindgroup_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Density Splat Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ..camera.get_bindgroup_layout_entry()
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: false },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 3,
+                    visibility: wgpu::ShaderStages::COMPUTE,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let splat_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Density Splat Pipeline Layout"),
+            bind_group_layouts: &[&splat_bindgroup_layout],
+            push_constant_ranges: &[],
+        });
+
+        let splat_pipeline = device.create_compute_pipeline(&wgpu::ComputePipelineDescriptor {
+            label: Some("Density Splat Pipeline"),
+            layout: Some(&splat_pipeline_layout),
+            module: &splat_shader,
+            entry_point: "splat",
+        });
+
+        // Resolve
+        let resolve_shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/density_resolve.wgsl"));
+
+        let resolve_bindgroup_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Density Resolve Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let resolve_bindgroup = Self::create_resolve_bindgroup(device, &resolve_bindgroup_layout, &histogram, &settings_buffer, &colormap_buffer);
+
+        let resolve_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Density Resolve Pipeline Layout"),
+            bind_group_layouts: &[&resolve_bindgroup_layout],
+            push_constant_ranges: &[],
+        });
+
+        let resolve_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Density Resolve Pipeline"),
+            layout: Some(&resolve_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &resolve_shader,
+                entry_point: "vs",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &resolve_shader,
+                entry_point: "fs",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            histogram,
+            settings_buffer,
+            colormap_buffer,
+            uploaded_colormap: None,
+            max_stars: device.limits().max_storage_buffer_binding_size as usize / std::mem::size_of::<Star>(),
+            star_count: 0,
+            splat_bindgroup_layout,
+            splat_pipeline,
+            resolve_bindgroup_layout,
+            resolve_bindgroup,
+            resolve_pipeline,
+            kernel_radius: density_splat_config.kernel_radius,
+            log_range: [density_splat_config.log_min, density_splat_config.log_max],
+            brightness: density_splat_config.brightness,
+            colormap: density_splat_config.colormap,
+        }
+    }
+
+    fn create_histogram(device: &wgpu::Device, size: &PhysicalSize<u32>) -> wgpu::Buffer {
+        device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Density Splat Histogram"),
+            size: (std::mem::size_of::<u32>() as u32 * size.width.max(1) * size.height.max(1)) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        })
+    }
+
+    fn create_resolve_bindgroup(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, histogram: &wgpu::Buffer, settings_buffer: &wgpu::Buffer, colormap_buffer: &wgpu::Buffer) -> wgpu::BindGroup {
+        device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Density Resolve Bind Group"),
+            layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: histogram.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: colormap_buffer.as_entire_binding(),
+                },
+            ],
+        })
+    }
+
+    pub fn resize(&mut self, device: &wgpu::Device, size: &PhysicalSize<u32>) {
+        self.histogram = Self::create_histogram(device, size);
+        self.resolve_bindgroup = Self::create_resolve_bindgroup(device, &self.resolve_bindgroup_layout, &self.histogram, &self.settings_buffer, &self.colormap_buffer);
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, galaxy: &Galaxy, (width, height): (u32, u32)) {
+        self.star_count = galaxy.stars.len().min(self.max_stars) as u32;
+
+        if self.uploaded_colormap != Some(self.colormap) {
+            queue.write_buffer(&self.colormap_buffer, 0, bytemuck::cast_slice(&self.colormap.generate_lut()));
+            self.uploaded_colormap = Some(self.colormap);
+        }
+
+        queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[SplatSettingsUniform {
+            viewport: [width as f32, height as f32],
+            mass_scale: MASS_SCALE,
+            kernel_radius: self.kernel_radius,
+            log_min: self.log_range[0],
+            log_max: self.log_range[1].max(self.log_range[0] + 1e-3),
+            brightness: self.brightness,
+            star_count: self.star_count,
+        }]));
+    }
+
+    // Clears and fills the histogram, has to run before the resolve pass
+    pub fn splat<T: Projection + Default>(&self, encoder: &mut wgpu::CommandEncoder, device: &wgpu::Device, galaxy: &Galaxy, camera: &Camera<T>, timestamps: Option<TimestampScope>) {
+        encoder.clear_buffer(&self.histogram, 0, None);
+        if self.star_count == 0 {
+            return;
+        }
+
+        // Rebuilt every frame, the binding only covers the live stars
+        let bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Density Splat Bind Group"),
+            layout: &self.splat_bindgroup_layout,
+            entries: &[
+                camera.get_bindgroup_entry(),
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: wgpu::BindingResource::Buffer(wgpu::BufferBinding {
+                        buffer: &galaxy.stars_buffer,
+                        offset: 0,
+                        size: wgpu::BufferSize::new((std::mem::size_of::<Star>() * self.star_count as usize) as u64),
+                    }),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 2,
+                    resource: self.histogram.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 3,
+                    resource: self.settings_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let mut cpass = encoder.begin_compute_pass(&wgpu::ComputePassDescriptor {
+            label: Some("Density Splat Pass"),
+            timestamp_writes: timestamps.map(|scope| scope.compute_writes(true, true)),
+        });
+        cpass.set_pipeline(&self.splat_pipeline);
+        cpass.set_bind_group(0, &bindgroup, &[]);
+        cpass.dispatch_workgroups(self.star_count.div_ceil(WORKGROUP_SIZE), 1, 1);
+    }
+
+    pub fn render<'rpass>(&'rpass self, rpass: &mut wgpu::RenderPass<'rpass>) {
+        rpass.push_debug_group("Density Resolve Pass");
+        rpass.set_pipeline(&self.resolve_pipeline);
+        rpass.set_bind_group(0, &self.resolve_bindgroup, &[]);
+        rpass.draw(0..6, 0..1);
+        rpass.pop_debug_group();
+    }
+}
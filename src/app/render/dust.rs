@@ -0,0 +1,200 @@
+use bytemuck::{Pod, Zeroable};
+
+use crate::app::camera::{Camera, Projection};
+use crate::app::galaxy::Galaxy;
+use crate::config::Config;
+
+/*
+    Dust lanes, derived from the star formation gas grid (dust follows the gas).
+    A fullscreen pass after the stars raymarches each pixel through the gas surface density, spread vertically
+    with a Gaussian of scale_height, and multiplies the scene by exp(-tau) before bloom.
+    Extinction is the V band optical depth through the densest face-on column, reddening is the exponent of
+    tau ~ wavelength^-reddening, so 0 is gray dust and ~1 is close to the Milky Way extinction curve.
+    The scene has no depth buffer, so the march stops where the ray crosses the midplane, as if every star
+    sat in the disk. Stars well above it (halo, satellites, tidal tails) in front of the disk get dimmed
+    by dust that is actually behind them.
+*/
+
+// Effective wavelengths of the R, G and B channels, nm
+const CHANNEL_WAVELENGTHS: [f32; 3] = [610.0, 550.0, 465.0];
+const V_BAND: f32 = 550.0;
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct DustUniform {
+    inverse_view_projection: [[f32; 4]; 4],
+    camera_position: [f32; 3],
+    extent: f32,
+    extinction: [f32; 3],
+    scale_height: f32,
+    viewport: [f32; 2],
+    resolution: u32,
+    steps: u32,
+}
+
+pub struct Dust {
+    pipeline: wgpu::RenderPipeline,
+    bindgroup: wgpu::BindGroup,
+    settings_buffer: wgpu::Buffer,
+    gas_buffer: wgpu::Buffer,
+    gas: Vec<f32>,
+
+    // Settings
+    pub enabled: bool,
+    pub extinction: f32,
+    pub reddening: f32,
+    pub scale_height: f32,
+    pub steps: u32,
+}
+
+impl Dust {
+    pub fn new(device: &wgpu::Device, config: &Config, format: wgpu::TextureFormat, galaxy: &Galaxy) -> Self {
+        let dust_config = &config.dust_config;
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/dust.wgsl"));
+
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Dust Settings Uniform"),
+            size: std::mem::size_of::<DustUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        // The grid resolution is fixed for the lifetime of the galaxy
+        let cells = galaxy.star_formation.density.len().max(1);
+        let gas_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Dust Gas Buffer"),
+            size: (std::mem::size_of::<f32>() * cells) as u64,
+            usage: wgpu::BufferUsages::STORAGE | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let bindgroup_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Dust Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Storage { read_only: true },
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Dust Bind Group"),
+            layout: &bindgroup_layout,
+            entries: &[
+                wgpu::BindGroupEntry {
+                    binding: 0,
+                    resource: settings_buffer.as_entire_binding(),
+                },
+                wgpu::BindGroupEntry {
+                    binding: 1,
+                    resource: gas_buffer.as_entire_binding(),
+                },
+            ],
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Dust Pipeline Layout"),
+            bind_group_layouts: &[&bindgroup_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Dust Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    // Multiplies the scene by the transmission
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            operation: wgpu::BlendOperation::Add,
+                            src_factor: wgpu::BlendFactor::Zero,
+                            dst_factor: wgpu::BlendFactor::Src,
+                        },
+                        alpha: wgpu::BlendComponent::OVER,
+                    }),
+                    write_mask: wgpu::ColorWrites::COLOR,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bindgroup,
+            settings_buffer,
+            gas_buffer,
+            gas: vec![0.0; cells],
+            enabled: dust_config.enabled,
+            extinction: dust_config.extinction,
+            reddening: dust_config.reddening,
+            scale_height: dust_config.scale_height,
+            steps: dust_config.steps,
+        }
+    }
+
+    pub fn update<T: Projection + Default>(&mut self, queue: &wgpu::Queue, galaxy: &Galaxy, camera: &Camera<T>, (width, height): (u32, u32)) {
+        if !self.enabled {
+            return;
+        }
+
+        // Gas changes as stars form, so it's re-uploaded every frame
+        let star_formation = &galaxy.star_formation;
+        let reference = star_formation.reference_density.max(1e-12);
+        for (dust, gas) in self.gas.iter_mut().zip(&star_formation.density) {
+            *dust = gas / reference;
+        }
+        queue.write_buffer(&self.gas_buffer, 0, bytemuck::cast_slice(&self.gas));
+
+        let view_projection = camera.projection.get_projection_matrix() * camera.get_view_matrix();
+        let inverse_view_projection = view_projection.try_inverse().unwrap_or_default();
+        let camera_position = camera.spherical_position.to_cartesian();
+        let extinction = CHANNEL_WAVELENGTHS.map(|wavelength| self.extinction * (V_BAND / wavelength).powf(self.reddening));
+
+        queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[DustUniform {
+            inverse_view_projection: inverse_view_projection.into(),
+            camera_position: camera_position.coords.into(),
+            extent: star_formation.extent,
+            extinction,
+            scale_height: self.scale_height.max(1e-3),
+            viewport: [width as f32, height as f32],
+            resolution: star_formation.resolution as u32,
+            steps: self.steps.max(1),
+        }]));
+    }
+
+    pub fn render<'rpass>(&'rpass self, rpass: &mut wgpu::RenderPass<'rpass>) {
+        rpass.push_debug_group("Dust Pass");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bindgroup, &[]);
+        rpass.draw(0..6, 0..1);
+        rpass.pop_debug_group();
+    }
+}
@@ -0,0 +1,280 @@
+use std::collections::VecDeque;
+use std::time;
+
+use nalgebra::Vector3;
+use serde::Deserialize;
+
+use crate::app::camera::{Camera, Projection};
+use crate::app::galaxy::bhot::{BHOTNode, BHOT};
+use crate::app::galaxy::Galaxy;
+use crate::config::Config;
+
+use super::colormap::{self, Colormap};
+use super::LineVertex;
+
+/*
+    Debug overlay of the Barnes-Hut octree, node bounds drawn as lines on top of the scene.
+    Nodes are walked breadth first down to max_depth and colored by mass or star count (log scaled through a colormap),
+    the nodes opened while computing the force on the selected star are drawn in HIGHLIGHT_COLOR at any depth.
+    Like Visualization the tree is rebuilt every update_interval seconds.
+*/
+
+// Caps the vertex buffer, the deepest drawn level is cut short past this
+pub const MAX_NODES: usize = 32768;
+const VERTICES_PER_NODE: usize = 24;
+const HIGHLIGHT_COLOR: [f32; 3] = [1.0, 0.35, 0.1];
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum NodeColoring {
+    Mass,
+    Occupancy,
+}
+
+impl NodeColoring {
+    pub const ALL: [NodeColoring; 2] = [NodeColoring::Mass, NodeColoring::Occupancy];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            NodeColoring::Mass => "Mass",
+            NodeColoring::Occupancy => "Occupancy",
+        }
+    }
+}
+
+// Changing any of these rebuilds the lines straight away
+type Settings = (u32, NodeColoring, Colormap, Option<usize>, f32, f32, f32);
+
+pub struct OctreeOverlay {
+    pipeline: wgpu::RenderPipeline,
+    bindgroup: wgpu::BindGroup,
+    vertex_buffer: wgpu::Buffer,
+    vertex_count: u32,
+    last_update: Option<time::Instant>,
+    uploaded: Option<Settings>,
+
+    pub node_count: usize, // Total nodes in the last tree
+    pub drawn_nodes: usize,
+    pub opened_nodes: usize,
+
+    // Settings
+    pub enabled: bool,
+    pub max_depth: u32,
+    pub coloring: NodeColoring,
+    pub colormap: Colormap,
+    pub intensity: f32,
+    pub highlight_opened: bool,
+    pub selected_star: usize,
+    pub theta: f32,
+    pub softening: f32,
+    pub update_interval: f32, // seconds
+}
+
+impl OctreeOverlay {
+    pub fn new<T: Projection + Default>(device: &wgpu::Device, config: &Config, format: wgpu::TextureFormat, camera: &Camera<T>) -> Self {
+        let overlay_config = &config.octree_overlay_config;
+        let shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/lines.wgsl"));
+
+        let bindgroup_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Octree Overlay Bind Group Layout"),
+            entries: &[
+                camera.get_bindgroup_layout_entry(),
+            ],
+        });
+
+        let bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Octree Overlay Bind Group"),
+            layout: &bindgroup_layout,
+            entries: &[
+                camera.get_bindgroup_entry(),
+            ],
+        });
+
+        let vertex_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Octree Overlay Vertex Buffer"),
+            size: (std::mem::size_of::<LineVertex>() * VERTICES_PER_NODE * MAX_NODES) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Octree Overlay Pipeline Layout"),
+            bind_group_layouts: &[&bindgroup_layout],
+            push_constant_ranges: &[],
+        });
+
+        let pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Octree Overlay Pipeline"),
+            layout: Some(&pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &shader,
+                entry_point: "vs",
+                buffers: &[LineVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &shader,
+                entry_point: "fs",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    // Additive like the stars
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            operation: wgpu::BlendOperation::Add,
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            operation: wgpu::BlendOperation::Add,
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        Self {
+            pipeline,
+            bindgroup,
+            vertex_buffer,
+            vertex_count: 0,
+            last_update: None,
+            uploaded: None,
+            node_count: 0,
+            drawn_nodes: 0,
+            opened_nodes: 0,
+            enabled: overlay_config.enabled,
+            max_depth: overlay_config.max_depth,
+            coloring: overlay_config.coloring,
+            colormap: overlay_config.colormap,
+            intensity: overlay_config.intensity,
+            highlight_opened: overlay_config.highlight_opened,
+            selected_star: overlay_config.selected_star,
+            theta: overlay_config.theta,
+            softening: overlay_config.softening,
+            update_interval: overlay_config.update_interval,
+        }
+    }
+
+    // Rebuilds the tree and the lines when the interval passed or a setting changed
+    pub fn update(&mut self, queue: &wgpu::Queue, galaxy: &Galaxy) {
+        if !self.enabled {
+            return;
+        }
+
+        let selected = (self.highlight_opened && self.selected_star < galaxy.stars.len()).then_some(self.selected_star);
+        let settings = (self.max_depth, self.coloring, self.colormap, selected, self.theta, self.softening, self.intensity);
+        let due = self.last_update.is_none_or(|last| last.elapsed().as_secs_f32() >= self.update_interval);
+        if !due && self.uploaded == Some(settings) {
+            return;
+        }
+        self.last_update = Some(time::Instant::now());
+        self.uploaded = Some(settings);
+
+        let stars = &galaxy.stars;
+        let tree = BHOT::build(stars);
+        self.node_count = tree.nodes.len();
+
+        let mut vertices = Vec::new();
+
+        // Highlighted nodes go first so the cap never drops them
+        let mut opened = Vec::new();
+        let mut reserved = 0;
+        if let Some(star) = selected {
+            let position = Vector3::from(stars[star].position);
+            tree.field(stars, position, Some(star), self.theta, self.softening, |index| opened.push(index));
+
+            let color = [HIGHLIGHT_COLOR[0], HIGHLIGHT_COLOR[1], HIGHLIGHT_COLOR[2], self.intensity * 4.0];
+            for &index in opened.iter().take(MAX_NODES - 1) {
+                push_box(&mut vertices, &tree.nodes[index], color);
+            }
+            push_marker(&mut vertices, position, tree.nodes[0].half_size * 0.01, color);
+            reserved = opened.len().min(MAX_NODES - 1) + 1;
+        }
+        self.opened_nodes = opened.len();
+
+        // Breadth first, so running into MAX_NODES only cuts the deepest level
+        let mut drawn = Vec::new();
+        let mut queue_nodes = VecDeque::from([0usize]);
+        while let Some(index) = queue_nodes.pop_front() {
+            if drawn.len() + reserved >= MAX_NODES {
+                break;
+            }
+            let node = match tree.nodes.get(index) {
+                Some(node) if node.count > 0 => node,
+                _ => continue,
+            };
+            drawn.push(index);
+            if node.depth < self.max_depth {
+                queue_nodes.extend(node.children());
+            }
+        }
+        self.drawn_nodes = drawn.len();
+
+        let value = |node: &BHOTNode| match self.coloring {
+            NodeColoring::Mass => node.total_mass.max(1e-12).ln(),
+            NodeColoring::Occupancy => (node.count as f32).ln(),
+        };
+        let (min, max) = drawn.iter()
+            .map(|&index| value(&tree.nodes[index]))
+            .fold((f32::MAX, f32::MIN), |(min, max), v| (min.min(v), max.max(v)));
+
+        for &index in &drawn {
+            let node = &tree.nodes[index];
+            let t = (value(node) - min) / (max - min).max(1e-6);
+            let [r, g, b] = self.colormap.sample(t).map(colormap::srgb_to_linear);
+            push_box(&mut vertices, node, [r, g, b, self.intensity]);
+        }
+
+        self.vertex_count = vertices.len() as u32;
+        queue.write_buffer(&self.vertex_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+
+    pub fn render<'rpass>(&'rpass self, rpass: &mut wgpu::RenderPass<'rpass>) {
+        if self.vertex_count == 0 {
+            return;
+        }
+
+        rpass.push_debug_group("Octree Overlay Pass");
+        rpass.set_pipeline(&self.pipeline);
+        rpass.set_bind_group(0, &self.bindgroup, &[]);
+        rpass.set_vertex_buffer(0, self.vertex_buffer.slice(..));
+        rpass.draw(0..self.vertex_count, 0..1);
+        rpass.pop_debug_group();
+    }
+}
+
+// The 12 edges of the node's cube
+fn push_box(vertices: &mut Vec<LineVertex>, node: &BHOTNode, color: [f32; 4]) {
+    let corner = |i: usize| {
+        let sign = |bit: usize| if i & bit != 0 { node.half_size } else { -node.half_size };
+        (node.center + Vector3::new(sign(1), sign(2), sign(4))).into()
+    };
+
+    for i in 0..8 {
+        for bit in [1, 2, 4] {
+            if i & bit == 0 {
+                vertices.push(LineVertex { position: corner(i), color });
+                vertices.push(LineVertex { position: corner(i | bit), color });
+            }
+        }
+    }
+}
+
+// Axis cross on the selected star, takes up one node's worth of vertices at most
+fn push_marker(vertices: &mut Vec<LineVertex>, position: Vector3<f32>, size: f32, color: [f32; 4]) {
+    for axis in 0..3 {
+        let mut offset = Vector3::zeros();
+        offset[axis] = size;
+        vertices.push(LineVertex { position: (position - offset).into(), color });
+        vertices.push(LineVertex { position: (position + offset).into(), color });
+    }
+}
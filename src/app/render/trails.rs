@@ -0,0 +1,414 @@
+use std::time;
+
+use bytemuck::{Pod, Zeroable};
+use winit::dpi::PhysicalSize;
+
+use crate::app::camera::{Camera, Projection};
+use crate::app::galaxy::Galaxy;
+use crate::app::timestamps::TimestampScope;
+use crate::config::Config;
+
+use super::LineVertex;
+
+/*
+    Star trails, two independent variants both drawn before bloom.
+    Accumulation: the scene is folded into a decaying HDR buffer each frame (ping-ponged, see trails.wgsl)
+    and copied back over the scene, so everything moving on screen smears, camera motion included.
+    History: a ring of past positions for the stars inside Galaxy::selection when they were tracked,
+    drawn as fading line segments.
+*/
+
+pub const MAX_TRACKED_STARS: usize = 256;
+pub const MAX_HISTORY_LENGTH: u32 = 256;
+
+#[repr(C)]
+#[derive(Clone, Copy, Pod, Zeroable)]
+struct TrailUniform {
+    decay: f32,
+    _padding: [u32; 3],
+}
+
+pub struct Trails {
+    // Accumulation
+    accumulation_textures: [wgpu::Texture; 2],
+    accumulation_bindgroups: [wgpu::BindGroup; 2], // [i] reads accumulation i, renders into the other one
+    accumulation_bindgroup_layout: wgpu::BindGroupLayout,
+    accumulation_pipeline: wgpu::RenderPipeline,
+    settings_buffer: wgpu::Buffer,
+    current: usize, // Accumulation texture holding the latest frame
+    was_accumulating: bool,
+
+    // History
+    tracked: Vec<u32>,
+    history: Vec<[f32; 3]>, // MAX_HISTORY_LENGTH slots per tracked star
+    head: usize,
+    filled: usize,
+    last_sample: Option<time::Instant>,
+    generation: u32, // Galaxy::generation the tracked indices belong to
+    line_buffer: wgpu::Buffer,
+    line_count: u32,
+    line_pipeline: wgpu::RenderPipeline,
+    line_bindgroup: wgpu::BindGroup,
+
+    // Settings
+    pub accumulation: bool,
+    pub persistence: f32, // seconds for the trail to fall to 1 / e
+    pub history_enabled: bool,
+    pub history_length: u32, // samples
+    pub sample_interval: f32, // seconds
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+impl Trails {
+    pub fn new<T: Projection + Default>(device: &wgpu::Device, config: &Config, format: wgpu::TextureFormat, size: &PhysicalSize<u32>, scene_view: &wgpu::TextureView, camera: &Camera<T>, galaxy: &Galaxy) -> Self {
+        let trails_config = &config.trails_config;
+
+        // Accumulation
+        let accumulation_shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/trails.wgsl"));
+
+        let settings_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trail Settings Uniform"),
+            size: std::mem::size_of::<TrailUniform>() as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let accumulation_bindgroup_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Trail Accumulation Bind Group Layout"),
+            entries: &[
+                wgpu::BindGroupLayoutEntry {
+                    binding: 0,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 1,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Texture {
+                        sample_type: wgpu::TextureSampleType::Float { filterable: false },
+                        view_dimension: wgpu::TextureViewDimension::D2,
+                        multisampled: false,
+                    },
+                    count: None,
+                },
+                wgpu::BindGroupLayoutEntry {
+                    binding: 2,
+                    visibility: wgpu::ShaderStages::FRAGMENT,
+                    ty: wgpu::BindingType::Buffer {
+                        ty: wgpu::BufferBindingType::Uniform,
+                        has_dynamic_offset: false,
+                        min_binding_size: None,
+                    },
+                    count: None,
+                },
+            ],
+        });
+
+        let accumulation_textures = Self::create_accumulation_textures(device, format, size);
+        let accumulation_bindgroups = Self::create_accumulation_bindgroups(device, &accumulation_bindgroup_layout, &accumulation_textures, scene_view, &settings_buffer);
+
+        let accumulation_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Trail Accumulation Pipeline Layout"),
+            bind_group_layouts: &[&accumulation_bindgroup_layout],
+            push_constant_ranges: &[],
+        });
+
+        let accumulation_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Trail Accumulation Pipeline"),
+            layout: Some(&accumulation_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &accumulation_shader,
+                entry_point: "vs",
+                buffers: &[],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &accumulation_shader,
+                entry_point: "fs",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    blend: None,
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState::default(),
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        // History
+        let line_shader = device.create_shader_module(wgpu::include_wgsl!("../shaders/lines.wgsl"));
+
+        let line_bindgroup_layout = device.create_bind_group_layout(&wgpu::BindGroupLayoutDescriptor {
+            label: Some("Trail Line Bind Group Layout"),
+            entries: &[
+                camera.get_bindgroup_layout_entry(),
+            ],
+        });
+
+        let line_bindgroup = device.create_bind_group(&wgpu::BindGroupDescriptor {
+            label: Some("Trail Line Bind Group"),
+            layout: &line_bindgroup_layout,
+            entries: &[
+                camera.get_bindgroup_entry(),
+            ],
+        });
+
+        let line_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Trail Line Vertex Buffer"),
+            size: (std::mem::size_of::<LineVertex>() * 2 * MAX_TRACKED_STARS * MAX_HISTORY_LENGTH as usize) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let line_pipeline_layout = device.create_pipeline_layout(&wgpu::PipelineLayoutDescriptor {
+            label: Some("Trail Line Pipeline Layout"),
+            bind_group_layouts: &[&line_bindgroup_layout],
+            push_constant_ranges: &[],
+        });
+
+        let line_pipeline = device.create_render_pipeline(&wgpu::RenderPipelineDescriptor {
+            label: Some("Trail Line Pipeline"),
+            layout: Some(&line_pipeline_layout),
+            vertex: wgpu::VertexState {
+                module: &line_shader,
+                entry_point: "vs",
+                buffers: &[LineVertex::desc()],
+            },
+            fragment: Some(wgpu::FragmentState {
+                module: &line_shader,
+                entry_point: "fs",
+                targets: &[Some(wgpu::ColorTargetState {
+                    format,
+                    // Additive like the stars
+                    blend: Some(wgpu::BlendState {
+                        color: wgpu::BlendComponent {
+                            operation: wgpu::BlendOperation::Add,
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                        },
+                        alpha: wgpu::BlendComponent {
+                            operation: wgpu::BlendOperation::Add,
+                            src_factor: wgpu::BlendFactor::One,
+                            dst_factor: wgpu::BlendFactor::One,
+                        },
+                    }),
+                    write_mask: wgpu::ColorWrites::ALL,
+                })],
+            }),
+            primitive: wgpu::PrimitiveState {
+                topology: wgpu::PrimitiveTopology::LineList,
+                ..Default::default()
+            },
+            depth_stencil: None,
+            multisample: wgpu::MultisampleState::default(),
+            multiview: None,
+        });
+
+        let mut trails = Self {
+            accumulation_textures,
+            accumulation_bindgroups,
+            accumulation_bindgroup_layout,
+            accumulation_pipeline,
+            settings_buffer,
+            current: 0,
+            was_accumulating: false,
+            tracked: Vec::new(),
+            history: Vec::new(),
+            head: 0,
+            filled: 0,
+            last_sample: None,
+            generation: galaxy.generation,
+            line_buffer,
+            line_count: 0,
+            line_pipeline,
+            line_bindgroup,
+            accumulation: trails_config.accumulation,
+            persistence: trails_config.persistence,
+            history_enabled: trails_config.history,
+            history_length: trails_config.history_length.clamp(2, MAX_HISTORY_LENGTH),
+            sample_interval: trails_config.sample_interval,
+            color: trails_config.color,
+            intensity: trails_config.intensity,
+        };
+        trails.track_selection(galaxy);
+        trails
+    }
+
+    fn create_accumulation_textures(device: &wgpu::Device, format: wgpu::TextureFormat, size: &PhysicalSize<u32>) -> [wgpu::Texture; 2] {
+        [0, 1].map(|i| device.create_texture(&wgpu::TextureDescriptor {
+            label: Some(&format!("Trail Accumulation {}", i)),
+            size: wgpu::Extent3d {
+                width: size.width.max(1),
+                height: size.height.max(1),
+                depth_or_array_layers: 1,
+            },
+            mip_level_count: 1,
+            sample_count: 1,
+            dimension: wgpu::TextureDimension::D2,
+            format,
+            usage: wgpu::TextureUsages::RENDER_ATTACHMENT | wgpu::TextureUsages::TEXTURE_BINDING | wgpu::TextureUsages::COPY_SRC,
+            view_formats: &[],
+        }))
+    }
+
+    fn create_accumulation_bindgroups(device: &wgpu::Device, layout: &wgpu::BindGroupLayout, textures: &[wgpu::Texture; 2], scene_view: &wgpu::TextureView, settings_buffer: &wgpu::Buffer) -> [wgpu::BindGroup; 2] {
+        [0, 1].map(|i| {
+            let previous_view = textures[i].create_view(&wgpu::TextureViewDescriptor::default());
+            device.create_bind_group(&wgpu::BindGroupDescriptor {
+                label: Some(&format!("Trail Accumulation Bind Group {}", i)),
+                layout,
+                entries: &[
+                    wgpu::BindGroupEntry {
+                        binding: 0,
+                        resource: wgpu::BindingResource::TextureView(scene_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 1,
+                        resource: wgpu::BindingResource::TextureView(&previous_view),
+                    },
+                    wgpu::BindGroupEntry {
+                        binding: 2,
+                        resource: settings_buffer.as_entire_binding(),
+                    },
+                ],
+            })
+        })
+    }
+
+    // The accumulation starts over at the new size
+    pub fn recreate_textures_and_bindgroups(&mut self, device: &wgpu::Device, format: wgpu::TextureFormat, size: &PhysicalSize<u32>, scene_view: &wgpu::TextureView) {
+        self.accumulation_textures = Self::create_accumulation_textures(device, format, size);
+        self.accumulation_bindgroups = Self::create_accumulation_bindgroups(device, &self.accumulation_bindgroup_layout, &self.accumulation_textures, scene_view, &self.settings_buffer);
+        self.was_accumulating = false;
+    }
+
+    // Restarts the history with the stars currently inside the selection region
+    pub fn track_selection(&mut self, galaxy: &Galaxy) {
+        self.tracked = galaxy.stars.iter().enumerate()
+            .filter(|(_, star)| galaxy.selection.contains(&star.position))
+            .map(|(i, _)| i as u32)
+            .take(MAX_TRACKED_STARS)
+            .collect();
+        self.clear_history();
+    }
+
+    pub fn tracked_count(&self) -> usize {
+        self.tracked.len()
+    }
+
+    fn clear_history(&mut self) {
+        self.history = vec![[0.0; 3]; self.tracked.len() * MAX_HISTORY_LENGTH as usize];
+        self.head = 0;
+        self.filled = 0;
+        self.last_sample = None;
+        self.line_count = 0;
+    }
+
+    pub fn update(&mut self, queue: &wgpu::Queue, galaxy: &Galaxy, dt: f32) {
+        // Fresh accumulation starts from the current frame alone
+        let decay = if self.was_accumulating { (-dt / self.persistence.max(1e-3)).exp() } else { 0.0 };
+        self.was_accumulating = self.accumulation;
+        queue.write_buffer(&self.settings_buffer, 0, bytemuck::cast_slice(&[TrailUniform {
+            decay,
+            _padding: [0; 3],
+        }]));
+
+        if self.history_enabled {
+            self.update_history(queue, galaxy);
+        }
+    }
+
+    fn update_history(&mut self, queue: &wgpu::Queue, galaxy: &Galaxy) {
+        // Removal and reset move other stars into the tracked indices, start over rather than follow them
+        if galaxy.generation != self.generation {
+            self.generation = galaxy.generation;
+            self.tracked.clear();
+            self.clear_history();
+        }
+
+        let due = self.last_sample.is_none_or(|last| last.elapsed().as_secs_f32() >= self.sample_interval);
+        if !due || self.tracked.is_empty() {
+            return;
+        }
+        self.last_sample = Some(time::Instant::now());
+
+        let capacity = MAX_HISTORY_LENGTH as usize;
+        for (k, &i) in self.tracked.iter().enumerate() {
+            self.history[k * capacity + self.head] = galaxy.stars[i as usize].position;
+        }
+        self.head = (self.head + 1) % capacity;
+        self.filled = (self.filled + 1).min(capacity);
+
+        // Oldest to newest, fading out towards the tail
+        let length = self.filled.min(self.history_length as usize);
+        let [r, g, b] = self.color;
+        let color = |age: usize| [r, g, b, self.intensity * (1.0 - age as f32 / length as f32)];
+        let mut vertices = Vec::with_capacity(self.tracked.len() * 2 * length);
+        for k in 0..self.tracked.len() {
+            let sample = |age: usize| self.history[k * capacity + (self.head + capacity - 1 - age) % capacity];
+            for age in (1..length).rev() {
+                vertices.push(LineVertex { position: sample(age), color: color(age) });
+                vertices.push(LineVertex { position: sample(age - 1), color: color(age - 1) });
+            }
+        }
+
+        self.line_count = vertices.len() as u32;
+        queue.write_buffer(&self.line_buffer, 0, bytemuck::cast_slice(&vertices));
+    }
+
+    // After the scene pass, replaces the scene with the accumulated trails
+    pub fn accumulate(&mut self, encoder: &mut wgpu::CommandEncoder, scene: &wgpu::Texture, timestamps: Option<TimestampScope>) {
+        if !self.accumulation {
+            return;
+        }
+
+        let target = 1 - self.current;
+        let target_view = self.accumulation_textures[target].create_view(&wgpu::TextureViewDescriptor::default());
+        {
+            let mut rpass = encoder.begin_render_pass(&wgpu::RenderPassDescriptor {
+                label: Some("Trail Accumulation Pass"),
+                color_attachments: &[Some(wgpu::RenderPassColorAttachment {
+                    view: &target_view,
+                    resolve_target: None,
+                    ops: wgpu::Operations {
+                        load: wgpu::LoadOp::Clear(wgpu::Color::BLACK),
+                        store: wgpu::StoreOp::Store,
+                    },
+                })],
+                depth_stencil_attachment: None,
+                occlusion_query_set: None,
+                timestamp_writes: timestamps.map(|scope| scope.render_writes(true, true)),
+            });
+            rpass.set_pipeline(&self.accumulation_pipeline);
+            rpass.set_bind_group(0, &self.accumulation_bindgroups[self.current], &[]);
+            rpass.draw(0..6, 0..1);
+        }
+
+        encoder.copy_texture_to_texture(
+            self.accumulation_textures[target].as_image_copy(),
+            scene.as_image_copy(),
+            scene.size(),
+        );
+        self.current = target;
+    }
+
+    pub fn render_history<'rpass>(&'rpass self, rpass: &mut wgpu::RenderPass<'rpass>) {
+        if !self.history_enabled || self.line_count == 0 {
+            return;
+        }
+
+        rpass.push_debug_group("Trail History Pass");
+        rpass.set_pipeline(&self.line_pipeline);
+        rpass.set_bind_group(0, &self.line_bindgroup, &[]);
+        rpass.set_vertex_buffer(0, self.line_buffer.slice(..));
+        rpass.draw(0..self.line_count, 0..1);
+        rpass.pop_debug_group();
+    }
+}
@@ -0,0 +1,191 @@
+use std::time;
+
+use nalgebra::Vector3;
+use serde::Deserialize;
+
+use crate::app::galaxy::bhot::BHOT;
+use crate::app::galaxy::{Galaxy, Star, MAX_STARS};
+use crate::config::Config;
+
+use super::colormap::{self, Colormap};
+use super::{density_cell, density_grid};
+
+/*
+    Color-by-property analysis mode (ColorMode::Property).
+    One f32 attribute per star, uploaded as a second instance buffer next to Galaxy::stars_buffer,
+    mapped through a colormap LUT in render.wgsl.
+    Attributes are computed on the CPU, tree based ones from a Barnes-Hut pass, so they are refreshed
+    every update_interval seconds rather than every frame.
+*/
+
+#[derive(Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum StarProperty {
+    Speed,
+    Acceleration,
+    Density,
+    Potential,
+    Population,
+    TreeDepth,
+}
+
+impl StarProperty {
+    pub const ALL: [StarProperty; 6] = [StarProperty::Speed, StarProperty::Acceleration, StarProperty::Density, StarProperty::Potential, StarProperty::Population, StarProperty::TreeDepth];
+
+    pub fn name(&self) -> &'static str {
+        match self {
+            StarProperty::Speed => "Speed",
+            StarProperty::Acceleration => "Acceleration",
+            StarProperty::Density => "Local Density",
+            StarProperty::Potential => "Potential Energy",
+            StarProperty::Population => "Population",
+            StarProperty::TreeDepth => "Tree Depth",
+        }
+    }
+}
+
+pub struct Visualization {
+    pub attribute_buffer: wgpu::Buffer,
+    pub colormap_buffer: wgpu::Buffer,
+    attributes: Vec<f32>,
+    last_update: Option<time::Instant>,
+    uploaded: Option<(StarProperty, Colormap, bool)>,
+
+    // Settings
+    pub property: StarProperty,
+    pub colormap: Colormap,
+    pub auto_range: bool,
+    pub range: [f32; 2], // In log10 when log_scale is set
+    pub log_scale: bool,
+    pub theta: f32,
+    pub softening: f32,
+    pub update_interval: f32, // seconds
+}
+
+impl Visualization {
+    const ATTRIBS: [wgpu::VertexAttribute; 1] = wgpu::vertex_attr_array![5 => Float32];
+
+    pub fn desc() -> wgpu::VertexBufferLayout<'static> {
+        wgpu::VertexBufferLayout {
+            array_stride: std::mem::size_of::<f32>() as wgpu::BufferAddress,
+            step_mode: wgpu::VertexStepMode::Instance,
+            attributes: &Self::ATTRIBS,
+        }
+    }
+
+    pub fn new(device: &wgpu::Device, config: &Config) -> Self {
+        let visualization_config = &config.visualization_config;
+
+        let attribute_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Star Attribute Buffer"),
+            size: (std::mem::size_of::<f32>() * MAX_STARS) as u64,
+            usage: wgpu::BufferUsages::VERTEX | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        let colormap_buffer = device.create_buffer(&wgpu::BufferDescriptor {
+            label: Some("Colormap LUT"),
+            size: (std::mem::size_of::<[f32; 4]>() * colormap::LUT_SIZE) as u64,
+            usage: wgpu::BufferUsages::UNIFORM | wgpu::BufferUsages::COPY_DST,
+            mapped_at_creation: false,
+        });
+
+        Self {
+            attribute_buffer,
+            colormap_buffer,
+            attributes: Vec::new(),
+            last_update: None,
+            uploaded: None,
+
+            property: visualization_config.property,
+            colormap: visualization_config.colormap,
+            auto_range: visualization_config.auto_range,
+            range: [visualization_config.range_min, visualization_config.range_max],
+            log_scale: visualization_config.log_scale,
+            theta: visualization_config.theta,
+            softening: visualization_config.softening,
+            update_interval: visualization_config.update_interval,
+        }
+    }
+
+    // Recomputes the attributes when the interval passed, the property / colormap changed or stars were added or
+    // removed (removal swaps stars into other slots, so old attributes would land on the wrong ones)
+    pub fn update(&mut self, queue: &wgpu::Queue, galaxy: &Galaxy) {
+        let changed = self.uploaded.map(|(property, _, _)| property) != Some(self.property)
+            || self.attributes.len() != galaxy.stars.len();
+        let rescaled = self.uploaded.map(|(_, _, log_scale)| log_scale) != Some(self.log_scale);
+        let due = self.last_update.is_none_or(|last| last.elapsed().as_secs_f32() >= self.update_interval);
+
+        if self.uploaded.map(|(_, colormap, _)| colormap) != Some(self.colormap) {
+            queue.write_buffer(&self.colormap_buffer, 0, bytemuck::cast_slice(&self.colormap.generate_lut()));
+        }
+        self.uploaded = Some((self.property, self.colormap, self.log_scale));
+
+        if changed || due {
+            self.last_update = Some(time::Instant::now());
+            self.attributes = Self::compute(self.property, galaxy, self.theta, self.softening);
+            queue.write_buffer(&self.attribute_buffer, 0, bytemuck::cast_slice(&self.attributes));
+        }
+
+        if self.auto_range && (changed || due || rescaled) {
+            self.range = self.data_range();
+        }
+    }
+
+    fn compute(property: StarProperty, galaxy: &Galaxy, theta: f32, softening: f32) -> Vec<f32> {
+        let stars = &galaxy.stars;
+        match property {
+            StarProperty::Speed => stars.iter().map(|star| Vector3::from(star.velocity).norm()).collect(),
+            StarProperty::Population => stars.iter().map(|star| star.population as f32).collect(),
+            StarProperty::Density => Self::grid_density(stars),
+            StarProperty::Acceleration | StarProperty::Potential => {
+                let tree = BHOT::build(stars);
+                stars.iter().enumerate().map(|(i, star)| {
+                    let (acceleration, potential) = tree.field(stars, Vector3::from(star.position), Some(i), theta, softening, |_| {});
+                    match property {
+                        StarProperty::Acceleration => acceleration.norm(),
+                        _ => potential * star.mass,
+                    }
+                }).collect()
+            },
+            // Depth of the leaf holding the star
+            StarProperty::TreeDepth => {
+                let tree = BHOT::build(stars);
+                let mut attributes = vec![0.0; stars.len()];
+                for node in tree.nodes.iter().filter(|node| node.leaf) {
+                    for &i in tree.leaf_stars(node) {
+                        attributes[i as usize] = node.depth as f32;
+                    }
+                }
+                attributes
+            }
+        }
+    }
+
+    // Mass per volume of the cell holding the star, on the same grid ColorMode::Density uses
+    fn grid_density(stars: &[Star]) -> Vec<f32> {
+        let (origin, cell_size, grid) = density_grid(stars);
+        let volume = cell_size.powi(3);
+        stars.iter().map(|star| grid[density_cell(&origin, cell_size, star)] / volume).collect()
+    }
+
+    // Potential is negative, so the log scale works on magnitudes
+    pub fn transform(&self, value: f32) -> f32 {
+        if self.log_scale {
+            value.abs().max(1e-30).log10()
+        } else {
+            value
+        }
+    }
+
+    pub fn data_range(&self) -> [f32; 2] {
+        let (min, max) = self.attributes.iter()
+            .map(|&value| self.transform(value))
+            .fold((f32::MAX, f32::MIN), |(min, max), value| (min.min(value), max.max(value)));
+        if min > max {
+            [0.0, 1.0]
+        } else {
+            [min, max.max(min + 1e-6)]
+        }
+    }
+}
@@ -0,0 +1,36 @@
+var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+struct BloomSettings {
+    filter_size: f32,
+    threshold: f32,
+    soft_knee: f32,
+    intensity: f32,
+    karis_average: u32,
+}
+
+@group(0) @binding(0) var scene_texture: texture_2d<f32>;
+@group(0) @binding(1) var bloom_texture: texture_2d<f32>;
+@group(0) @binding(2) var samp: sampler;
+
+@group(1) @binding(0) var<uniform> bloom_settings: BloomSettings;
+
+@vertex fn vs(@builtin(vertex_index) index : u32) -> @builtin(position) vec4f {
+    return vec4<f32>(quad_verts[index], 0.0, 1.0);
+}
+
+// Scene plus the (lower resolution) bloom chain result
+@fragment fn fs(@builtin(position) pos: vec4f) -> @location(0) vec4f {
+    let scene = textureLoad(scene_texture, vec2u(pos.xy), 0).rgb;
+
+    let uv = pos.xy / vec2f(textureDimensions(scene_texture));
+    let bloom = textureSample(bloom_texture, samp, uv).rgb;
+
+    return vec4f(scene + bloom * bloom_settings.intensity, 1.0);
+}
@@ -0,0 +1,164 @@
+// Compute version of downsample.wgsl / upsample.wgsl, selected with BloomMode::Compute
+// Upsampling writes into a separate up chain, a storage write can't read the mip it writes to
+
+struct BloomSettings {
+    filter_size: f32,
+    threshold: f32,
+    soft_knee: f32,
+    intensity: f32,
+    karis_average: u32,
+}
+
+struct MipSettings {
+    scale: vec4f,
+    target_scale: vec4f,
+}
+
+@group(1) @binding(0) var<uniform> bloom_settings: BloomSettings;
+
+/*
+    Downsample
+    Every tap of the 13 tap filter lands on a texel corner, so each one is the box average of 4 source texels.
+    An 8x8 tile of outputs covers 20x20 source texels, which are loaded into shared memory once.
+*/
+
+@group(0) @binding(0) var down_input: texture_2d<f32>;
+@group(0) @binding(1) var down_output: texture_storage_2d<rgba16float, write>;
+
+const TILE: u32 = 8u;
+const CACHE: u32 = 20u; // 2 * TILE + 2 texel border on each side
+
+var<workgroup> cache: array<vec4f, 400>;
+
+fn box(corner: vec2u) -> vec4f {
+    let i = corner.y * CACHE + corner.x;
+    return (cache[i] + cache[i + 1u] + cache[i + CACHE] + cache[i + CACHE + 1u]) * 0.25;
+}
+
+fn load_tile(group: vec2u, local_index: u32) {
+    let input_dim = vec2i(textureDimensions(down_input));
+    let origin = vec2i(group * TILE * 2u) - 2;
+
+    for (var i = local_index; i < CACHE * CACHE; i += TILE * TILE) {
+        let texel = clamp(origin + vec2i(i32(i % CACHE), i32(i / CACHE)), vec2i(0), input_dim - 1);
+        cache[i] = textureLoad(down_input, texel, 0);
+    }
+    workgroupBarrier();
+}
+
+// karis_weight / karis_average are prepended from karis.wgsl
+
+// Same weights as downsample_13_tap, tap (dx, dy) is the box with its top left texel at 2 * local + 2 + (dx - 1, dy - 1)
+fn downsample_13_tap(local: vec2u, karis: bool) -> vec4f {
+    let base = local * 2u;
+
+    let a = box(base + vec2u(0u, 0u));
+    let b = box(base + vec2u(2u, 0u));
+    let c = box(base + vec2u(4u, 0u));
+
+    let d = box(base + vec2u(1u, 1u));
+    let e = box(base + vec2u(3u, 1u));
+
+    let f = box(base + vec2u(0u, 2u));
+    let g = box(base + vec2u(2u, 2u));
+    let h = box(base + vec2u(4u, 2u));
+
+    let i = box(base + vec2u(1u, 3u));
+    let j = box(base + vec2u(3u, 3u));
+
+    let k = box(base + vec2u(0u, 4u));
+    let l = box(base + vec2u(2u, 4u));
+    let m = box(base + vec2u(4u, 4u));
+
+    if (karis) {
+        return karis_average(a, b, c, d, e, f, g, h, i, j, k, l, m);
+    }
+
+    return (d + e + i + j) * 0.125 +
+           (g) * 0.125 +
+           (a + c + k + m) * 0.03125 +
+           (b + f + h + l) * 0.0625;
+}
+
+// Threshold with a quadratic soft knee, keeps hue by scaling the whole color
+fn prefilter(color: vec3f) -> vec3f {
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = bloom_settings.threshold * bloom_settings.soft_knee + 1e-5;
+
+    var soft = clamp(brightness - bloom_settings.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee);
+
+    let contribution = max(soft, brightness - bloom_settings.threshold) / max(brightness, 1e-5);
+    return color * contribution;
+}
+
+@compute @workgroup_size(8, 8) fn downsample(
+    @builtin(global_invocation_id) id: vec3u,
+    @builtin(local_invocation_id) local: vec3u,
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(workgroup_id) group: vec3u,
+) {
+    load_tile(group.xy, local_index);
+
+    if (all(id.xy < textureDimensions(down_output))) {
+        textureStore(down_output, id.xy, downsample_13_tap(local.xy, false));
+    }
+}
+
+// First downsample, straight from the scene
+@compute @workgroup_size(8, 8) fn downsample_prefilter(
+    @builtin(global_invocation_id) id: vec3u,
+    @builtin(local_invocation_id) local: vec3u,
+    @builtin(local_invocation_index) local_index: u32,
+    @builtin(workgroup_id) group: vec3u,
+) {
+    load_tile(group.xy, local_index);
+
+    if (all(id.xy < textureDimensions(down_output))) {
+        let color = downsample_13_tap(local.xy, bloom_settings.karis_average != 0u);
+        textureStore(down_output, id.xy, vec4f(prefilter(color.rgb), 1.0));
+    }
+}
+
+/*
+    Upsample
+    The tent filter radius is in uv space, so it samples bilinearly like the raster path.
+    up[i - 1] = tent(up[i]) * scale + down[i - 1] * target_scale
+*/
+
+@group(0) @binding(0) var up_input: texture_2d<f32>;
+@group(0) @binding(1) var up_sampler: sampler;
+@group(0) @binding(2) var up_target: texture_2d<f32>;
+@group(0) @binding(3) var up_output: texture_storage_2d<rgba16float, write>;
+
+@group(2) @binding(0) var<uniform> mip_settings: MipSettings;
+
+@compute @workgroup_size(8, 8) fn upsample(@builtin(global_invocation_id) id: vec3u) {
+    let output_dim = textureDimensions(up_output);
+    if (any(id.xy >= output_dim)) {
+        return;
+    }
+
+    let texcoord = (vec2f(id.xy) + 0.5) / vec2f(output_dim);
+    let filter_size = bloom_settings.filter_size;
+
+    let a = textureSampleLevel(up_input, up_sampler, texcoord + vec2(-filter_size, -filter_size), 0.0);
+    let b = textureSampleLevel(up_input, up_sampler, texcoord + vec2(0.0, -filter_size), 0.0);
+    let c = textureSampleLevel(up_input, up_sampler, texcoord + vec2(filter_size, -filter_size), 0.0);
+
+    let d = textureSampleLevel(up_input, up_sampler, texcoord + vec2(-filter_size, 0.0), 0.0);
+    let e = textureSampleLevel(up_input, up_sampler, texcoord, 0.0);
+    let f = textureSampleLevel(up_input, up_sampler, texcoord + vec2(filter_size, 0.0), 0.0);
+
+    let g = textureSampleLevel(up_input, up_sampler, texcoord + vec2(-filter_size, filter_size), 0.0);
+    let h = textureSampleLevel(up_input, up_sampler, texcoord + vec2(0.0, filter_size), 0.0);
+    let i = textureSampleLevel(up_input, up_sampler, texcoord + vec2(filter_size, filter_size), 0.0);
+
+    var weighted_average = e * 4.0;
+    weighted_average += (b + d + f + h) * 2.0;
+    weighted_average += (a + c + g + i);
+    weighted_average /= 16.0;
+
+    let down = textureLoad(up_target, id.xy, 0);
+    textureStore(up_output, id.xy, weighted_average * mip_settings.scale + down * mip_settings.target_scale);
+}
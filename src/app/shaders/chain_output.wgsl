@@ -0,0 +1,22 @@
+/*
+    Copies the result of the post chain into the surface, which encodes it to sRGB on write.
+*/
+
+var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+@group(0) @binding(0) var input_texture: texture_2d<f32>;
+
+@vertex fn vs(@builtin(vertex_index) index : u32) -> @builtin(position) vec4f {
+    return vec4<f32>(quad_verts[index], 0.0, 1.0);
+}
+
+@fragment fn fs(@builtin(position) pos: vec4f) -> @location(0) vec4f {
+    return vec4f(textureLoad(input_texture, vec2u(pos.xy), 0).rgb, 1.0);
+}
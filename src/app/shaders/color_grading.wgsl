@@ -0,0 +1,71 @@
+/*
+    Color grading after tonemapping: lift / gamma / gain and saturation, then a 3D LUT lookup.
+    The tonemapped image is display linear, grading works on sRGB encoded values since that's what .cube LUTs expect.
+*/
+
+var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+struct GradingSettings {
+    lift: vec4f,
+    gamma: vec4f,
+    gain: vec4f,
+    domain_min: vec4f,
+    domain_max: vec4f,
+    saturation: f32,
+    lut_intensity: f32,
+    lut_size: f32,
+}
+
+@group(0) @binding(0) var input_texture: texture_2d<f32>;
+@group(0) @binding(1) var lut_texture: texture_3d<f32>;
+@group(0) @binding(2) var lut_sampler: sampler;
+@group(0) @binding(3) var<uniform> settings: GradingSettings;
+
+@vertex fn vs(@builtin(vertex_index) index : u32) -> @builtin(position) vec4f {
+    return vec4<f32>(quad_verts[index], 0.0, 1.0);
+}
+
+fn linear_to_srgb(c: vec3f) -> vec3f {
+    let low = c * 12.92;
+    let high = 1.055 * pow(c, vec3f(1.0 / 2.4)) - 0.055;
+    return select(high, low, c <= vec3f(0.0031308));
+}
+
+fn srgb_to_linear(c: vec3f) -> vec3f {
+    let low = c / 12.92;
+    let high = pow((c + 0.055) / 1.055, vec3f(2.4));
+    return select(high, low, c <= vec3f(0.04045));
+}
+
+// Lift raises the blacks, gain scales the whites, gamma bends the midtones
+fn lift_gamma_gain(c: vec3f) -> vec3f {
+    let lifted = settings.gain.rgb * (c + settings.lift.rgb * (1.0 - c));
+    return pow(max(lifted, vec3f(0.0)), 1.0 / max(settings.gamma.rgb, vec3f(1e-3)));
+}
+
+// Texel centers, so the first and last entries land exactly on the domain bounds
+fn lut_lookup(c: vec3f) -> vec3f {
+    let normalized = clamp((c - settings.domain_min.rgb) / (settings.domain_max.rgb - settings.domain_min.rgb), vec3f(0.0), vec3f(1.0));
+    let coordinate = (normalized * (settings.lut_size - 1.0) + 0.5) / settings.lut_size;
+    return textureSampleLevel(lut_texture, lut_sampler, coordinate, 0.0).rgb;
+}
+
+@fragment fn fs(@builtin(position) pos: vec4f) -> @location(0) vec4f {
+    let color = textureLoad(input_texture, vec2u(pos.xy), 0).rgb;
+
+    var graded = lift_gamma_gain(linear_to_srgb(clamp(color, vec3f(0.0), vec3f(1.0))));
+
+    let luma = dot(graded, vec3f(0.2126, 0.7152, 0.0722));
+    graded = max(mix(vec3f(luma), graded, settings.saturation), vec3f(0.0));
+
+    graded = mix(graded, lut_lookup(graded), settings.lut_intensity);
+
+    return vec4f(srgb_to_linear(clamp(graded, vec3f(0.0), vec3f(1.0))), 1.0);
+}
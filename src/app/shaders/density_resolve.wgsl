@@ -0,0 +1,67 @@
+// Smooths the splatted histogram and maps log10 surface density through the colormap
+
+struct SplatSettings {
+    viewport: vec2<f32>,
+    mass_scale: f32,
+    kernel_radius: u32,
+    log_min: f32,
+    log_max: f32,
+    brightness: f32,
+    star_count: u32,
+}
+
+const LUT_SIZE: u32 = 256u;
+
+@group(0) @binding(0) var<storage, read> histogram: array<u32>;
+@group(0) @binding(1) var<uniform> settings: SplatSettings;
+@group(0) @binding(2) var<uniform> colormap_lut: array<vec4<f32>, LUT_SIZE>;
+
+var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+@vertex fn vs(@builtin(vertex_index) index : u32) -> @builtin(position) vec4f {
+    return vec4<f32>(quad_verts[index], 0.0, 1.0);
+}
+
+fn mass_at(pixel: vec2<i32>) -> f32 {
+    let size = vec2<i32>(settings.viewport);
+    let p = clamp(pixel, vec2<i32>(0), size - 1);
+    return f32(histogram[u32(p.y * size.x + p.x)]) / settings.mass_scale;
+}
+
+fn colormap_sample(t: f32) -> vec3<f32> {
+    let x = clamp(t, 0.0, 1.0) * f32(LUT_SIZE - 1u);
+    let i = min(u32(x), LUT_SIZE - 2u);
+    return mix(colormap_lut[i].rgb, colormap_lut[i + 1u].rgb, x - f32(i));
+}
+
+@fragment fn fs(@builtin(position) pos: vec4f) -> @location(0) vec4f {
+    let pixel = vec2<i32>(pos.xy);
+
+    // Normalized Gaussian, sigma = radius / 2, a radius of 0 is the raw histogram
+    var density = 0.0;
+    var total_weight = 0.0;
+    let radius = i32(settings.kernel_radius);
+    let sigma = max(f32(radius) * 0.5, 0.5);
+    for (var y = -radius; y <= radius; y++) {
+        for (var x = -radius; x <= radius; x++) {
+            let weight = exp(-f32(x * x + y * y) / (2.0 * sigma * sigma));
+            density += mass_at(pixel + vec2<i32>(x, y)) * weight;
+            total_weight += weight;
+        }
+    }
+    density /= total_weight;
+
+    if (density <= 0.0) {
+        return vec4<f32>(0.0, 0.0, 0.0, 1.0);
+    }
+
+    let t = (log(density) / log(10.0) - settings.log_min) / (settings.log_max - settings.log_min);
+    return vec4<f32>(colormap_sample(t) * settings.brightness, 1.0);
+}
@@ -0,0 +1,48 @@
+// Screen-space mass histogram, one invocation per star, see render/density_splat.rs
+
+struct CameraUniform {
+    view_matrix: mat4x4<f32>,
+    projection_matrix: mat4x4<f32>,
+}
+
+struct SplatSettings {
+    viewport: vec2<f32>,
+    mass_scale: f32, // fixed point scale, the histogram is accumulated as u32
+    kernel_radius: u32,
+    log_min: f32,
+    log_max: f32,
+    brightness: f32,
+    star_count: u32,
+}
+
+// Star is 9 tightly packed 32 bit values, which doesn't match WGSL struct alignment
+const STAR_STRIDE: u32 = 9u;
+
+@group(0) @binding(0) var<uniform> camera: CameraUniform;
+@group(0) @binding(1) var<storage, read> stars: array<f32>;
+@group(0) @binding(2) var<storage, read_write> histogram: array<atomic<u32>>;
+@group(0) @binding(3) var<uniform> settings: SplatSettings;
+
+@compute @workgroup_size(256) fn splat(@builtin(global_invocation_id) id: vec3u) {
+    if (id.x >= settings.star_count) {
+        return;
+    }
+
+    let base = id.x * STAR_STRIDE;
+    let position = vec3<f32>(stars[base], stars[base + 1u], stars[base + 2u]);
+    let mass = stars[base + 7u];
+
+    let clip = camera.projection_matrix * camera.view_matrix * vec4<f32>(position, 1.0);
+    if (clip.w <= 0.0) {
+        return;
+    }
+
+    let ndc = clip.xy / clip.w;
+    let pixel = vec2<i32>(floor((ndc * vec2<f32>(0.5, -0.5) + 0.5) * settings.viewport));
+    let size = vec2<i32>(settings.viewport);
+    if (any(pixel < vec2<i32>(0)) || any(pixel >= size)) {
+        return;
+    }
+
+    atomicAdd(&histogram[u32(pixel.y * size.x + pixel.x)], u32(mass * settings.mass_scale + 0.5));
+}
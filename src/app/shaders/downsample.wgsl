@@ -1,6 +1,16 @@
+struct BloomSettings {
+    filter_size: f32,
+    threshold: f32,
+    soft_knee: f32,
+    intensity: f32,
+    karis_average: u32,
+}
+
 @group(0) @binding(0) var input_mip: texture_2d<f32>;
 @group(0) @binding(1) var samp: sampler;
 
+@group(1) @binding(0) var<uniform> bloom_settings: BloomSettings;
+
 var<private> quad_verts: array<vec2<f32>,6> =  array<vec2<f32>, 6>(
     vec2<f32>(-1.0, 1.0),
     vec2<f32>(-1.0, -1.0),
@@ -20,22 +30,20 @@ struct VertexOutput {
     
     var vertexOutput: VertexOutput;
     vertexOutput.position = pos;
-    vertexOutput.texcoord = (pos.xy / 2.0) + 0.5;
+    vertexOutput.texcoord = pos.xy * vec2f(0.5, -0.5) + 0.5; // Texture space has y pointing down
     return vertexOutput;
 }
 
 // Note, you can use the vertex shader to get texcoords (0,1), 
 //  no need to pass the render target size
 
+// karis_weight / karis_average are prepended from karis.wgsl
 
-
-@fragment fn fs(input: VertexOutput) -> @location(0) vec4f {   
+fn downsample_13_tap(texcoord: vec2f, karis: bool) -> vec4f {
     //Use TextureDimension with miplevel instead..
     let input_dim = textureDimensions(input_mip);
     let pixel_size = 1.0 / vec2f(input_dim);
 
-    let texcoord = input.texcoord;
-
     let a = textureSample(input_mip, samp, texcoord + vec2(-2*pixel_size.x, -2*pixel_size.y));
     let b = textureSample(input_mip, samp, texcoord + vec2(0, -2*pixel_size.y));
     let c = textureSample(input_mip, samp, texcoord + vec2(2*pixel_size.x, -2*pixel_size.y));
@@ -51,8 +59,12 @@ struct VertexOutput {
     let j = textureSample(input_mip, samp, texcoord + vec2( 1*pixel_size.x, 1*pixel_size.y));
 
     let k = textureSample(input_mip, samp, texcoord + vec2(-2*pixel_size.x, 2*pixel_size.y));
-    let l = textureSample(input_mip, samp, texcoord + vec2(0, 2*pixel_size.x));
+    let l = textureSample(input_mip, samp, texcoord + vec2(0, 2*pixel_size.y));
     let m = textureSample(input_mip, samp, texcoord + vec2( 2*pixel_size.x, 2*pixel_size.y));
+
+    if (karis) {
+        return karis_average(a, b, c, d, e, f, g, h, i, j, k, l, m);
+    }
     
     let weighted_average = (d + e + i + j) * 0.125 + //red
                            (g) * 0.125 + // center (overlap of 4)
@@ -60,4 +72,26 @@ struct VertexOutput {
                            (b + f + h + l) * 0.0625; // edges (overlap of 2)
 
     return weighted_average;
-}
\ No newline at end of file
+}
+
+// Threshold with a quadratic soft knee, keeps hue by scaling the whole color
+fn prefilter(color: vec3f) -> vec3f {
+    let brightness = max(color.r, max(color.g, color.b));
+    let knee = bloom_settings.threshold * bloom_settings.soft_knee + 1e-5;
+
+    var soft = clamp(brightness - bloom_settings.threshold + knee, 0.0, 2.0 * knee);
+    soft = soft * soft / (4.0 * knee);
+
+    let contribution = max(soft, brightness - bloom_settings.threshold) / max(brightness, 1e-5);
+    return color * contribution;
+}
+
+@fragment fn fs(input: VertexOutput) -> @location(0) vec4f {   
+    return downsample_13_tap(input.texcoord, false);
+}
+
+// First downsample, straight from the scene
+@fragment fn fs_prefilter(input: VertexOutput) -> @location(0) vec4f {
+    let color = downsample_13_tap(input.texcoord, bloom_settings.karis_average != 0u);
+    return vec4f(prefilter(color.rgb), 1.0);
+}
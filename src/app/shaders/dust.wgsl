@@ -0,0 +1,88 @@
+// Dust absorption, multiplied onto the scene after the stars are drawn, see render/dust.rs
+
+struct DustSettings {
+    inverse_view_projection: mat4x4<f32>,
+    camera_position: vec3<f32>,
+    extent: f32, // grid spans [-extent, extent] on x and z
+    extinction: vec3<f32>, // optical depth per channel through the densest face-on column
+    scale_height: f32,
+    viewport: vec2<f32>,
+    resolution: u32,
+    steps: u32,
+}
+
+@group(0) @binding(0) var<uniform> settings: DustSettings;
+@group(0) @binding(1) var<storage, read> gas: array<f32>; // surface density relative to the densest initial cell
+
+var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+@vertex fn vs(@builtin(vertex_index) index : u32) -> @builtin(position) vec4f {
+    return vec4<f32>(quad_verts[index], 0.0, 1.0);
+}
+
+fn gas_cell(x: i32, z: i32) -> f32 {
+    let n = i32(settings.resolution);
+    if (x < 0 || z < 0 || x >= n || z >= n) {
+        return 0.0;
+    }
+    return gas[u32(z * n + x)];
+}
+
+// Bilinear between cell centers
+fn surface_density(p: vec2<f32>) -> f32 {
+    let cell = (p + settings.extent) / (2.0 * settings.extent) * f32(settings.resolution) - 0.5;
+    let base = vec2<i32>(floor(cell));
+    let f = cell - floor(cell);
+    let top = mix(gas_cell(base.x, base.y), gas_cell(base.x + 1, base.y), f.x);
+    let bottom = mix(gas_cell(base.x, base.y + 1), gas_cell(base.x + 1, base.y + 1), f.x);
+    return mix(top, bottom, f.y);
+}
+
+// Gaussian vertical profile, integrates to 1 through the disk
+fn dust_density(p: vec3<f32>) -> f32 {
+    let h = settings.scale_height;
+    return surface_density(p.xz) * exp(-0.5 * p.y * p.y / (h * h)) / (2.5066283 * h);
+}
+
+@fragment fn fs(@builtin(position) pos: vec4f) -> @location(0) vec4f {
+    let uv = pos.xy / settings.viewport;
+    let ndc = vec2<f32>(uv.x * 2.0 - 1.0, 1.0 - uv.y * 2.0);
+    let far = settings.inverse_view_projection * vec4<f32>(ndc, 1.0, 1.0);
+    let origin = settings.camera_position;
+    let direction = normalize(far.xyz / far.w - origin);
+
+    // Slab around the grid, 3 scale heights either side of the plane
+    let box_max = vec3<f32>(settings.extent, 3.0 * settings.scale_height, settings.extent);
+    let inverse = 1.0 / direction;
+    let t_a = (-box_max - origin) * inverse;
+    let t_b = (box_max - origin) * inverse;
+    let t_enter = max(max(max(min(t_a.x, t_b.x), min(t_a.y, t_b.y)), min(t_a.z, t_b.z)), 0.0);
+    var t_exit = min(min(max(t_a.x, t_b.x), max(t_a.y, t_b.y)), max(t_a.z, t_b.z));
+
+    // Assumes the stars sit in the plane (no depth buffer), only dust in front of the crossing absorbs.
+    // Wrong for stars off the plane in front of the disk, they are dimmed by dust behind them
+    let t_plane = -origin.y * inverse.y;
+    if (t_plane > t_enter && t_plane < t_exit) {
+        t_exit = t_plane;
+    }
+
+    if (t_exit <= t_enter) {
+        return vec4<f32>(1.0);
+    }
+
+    let step = (t_exit - t_enter) / f32(settings.steps);
+    var column = 0.0;
+    for (var i = 0u; i < settings.steps; i++) {
+        column += dust_density(origin + direction * (t_enter + (f32(i) + 0.5) * step));
+    }
+    column *= step;
+
+    return vec4<f32>(exp(-settings.extinction * column), 1.0);
+}
@@ -0,0 +1,148 @@
+/*
+    Realistic film grain (Newson, Delon & Galerne 2017).
+    The image is modelled as a Boolean model of disc shaped grains whose Poisson density is
+    chosen so a region of intensity u is covered with probability u. Each output pixel
+    Monte Carlo integrates the grain coverage under a small Gaussian filter.
+*/
+
+var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+struct GrainSettings {
+    grain_radius: f32, // pixels
+    intensity: f32,
+    color_variation: f32,
+    filter_sigma: f32, // pixels
+    seed: u32,
+    samples: u32,
+}
+
+@group(0) @binding(0) var input_texture: texture_2d<f32>;
+@group(0) @binding(1) var<uniform> settings: GrainSettings;
+
+const PI: f32 = 3.14159265;
+const MAX_GRAINS_PER_CELL: u32 = 16u;
+
+@vertex fn vs(@builtin(vertex_index) index : u32) -> @builtin(position) vec4f {
+    return vec4<f32>(quad_verts[index], 0.0, 1.0);
+}
+
+// Integer hash, the same seed gives the same pattern on a given GPU. The gaussian and poisson
+// sampling go through log / cos / exp, whose precision is up to the implementation, so other GPUs can differ
+fn pcg(v: u32) -> u32 {
+    let state = v * 747796405u + 2891336453u;
+    let word = ((state >> ((state >> 28u) + 4u)) ^ state) * 277803737u;
+    return (word >> 22u) ^ word;
+}
+
+var<private> rng_state: u32;
+
+fn seed_rng(a: u32, b: u32, c: u32) {
+    rng_state = pcg(a ^ pcg(b ^ pcg(c ^ settings.seed)));
+}
+
+fn rand() -> f32 {
+    rng_state = pcg(rng_state);
+    return f32(rng_state) / 4294967295.0;
+}
+
+fn rand_gaussian2() -> vec2f {
+    let r = sqrt(-2.0 * log(max(rand(), 1e-7)));
+    let theta = 2.0 * PI * rand();
+    return r * vec2f(cos(theta), sin(theta));
+}
+
+// Knuth's method, lambda stays small here
+fn rand_poisson(lambda: f32) -> u32 {
+    let limit = exp(-lambda);
+    var k = 0u;
+    var p = rand();
+    while (p > limit && k < MAX_GRAINS_PER_CELL) {
+        k++;
+        p *= rand();
+    }
+    return k;
+}
+
+fn to_display(linear: vec3f) -> vec3f {
+    return pow(max(linear, vec3f(0.0)), vec3f(1.0 / 2.2));
+}
+
+fn to_linear(display: vec3f) -> vec3f {
+    return pow(max(display, vec3f(0.0)), vec3f(2.2));
+}
+
+// Intensity of one channel (3 = luminance) at a pixel, in display space
+fn intensity_at(pixel: vec2i, channel: u32) -> f32 {
+    let dim = vec2i(textureDimensions(input_texture));
+    let color = to_display(textureLoad(input_texture, clamp(pixel, vec2i(0), dim - 1), 0).rgb);
+    if (channel == 3u) {
+        return dot(color, vec3f(0.2126, 0.7152, 0.0722));
+    }
+    return color[channel];
+}
+
+// Fraction of filtered Monte Carlo samples around pos that land inside a grain
+fn grain_coverage(pos: vec2f, channel: u32) -> f32 {
+    let radius = settings.grain_radius;
+    var covered = 0u;
+
+    for (var s = 0u; s < settings.samples; s++) {
+        seed_rng(u32(pos.x) * 4096u + u32(pos.y), s, channel);
+        let p = pos + rand_gaussian2() * settings.filter_sigma;
+        let cell = floor(p / radius);
+
+        var hit = false;
+        for (var dy = -1; dy <= 1 && !hit; dy++) {
+            for (var dx = -1; dx <= 1 && !hit; dx++) {
+                let c = cell + vec2f(f32(dx), f32(dy));
+
+                // Grain density of a cell depends only on the image under it
+                let u = clamp(intensity_at(vec2i((c + 0.5) * radius), channel), 0.0, 0.999);
+                let lambda = -log(1.0 - u) / PI;
+
+                seed_rng(bitcast<u32>(i32(c.x)), bitcast<u32>(i32(c.y)), channel + 4u);
+                let grains = rand_poisson(lambda);
+                for (var k = 0u; k < grains; k++) {
+                    let center = (c + vec2f(rand(), rand())) * radius;
+                    if (distance(center, p) < radius) {
+                        hit = true;
+                    }
+                }
+            }
+        }
+
+        if (hit) {
+            covered++;
+        }
+    }
+
+    return f32(covered) / f32(max(settings.samples, 1u));
+}
+
+@fragment fn fs(@builtin(position) pos: vec4f) -> @location(0) vec4f {
+    let color = textureLoad(input_texture, vec2u(pos.xy), 0).rgb;
+    let display = to_display(color);
+
+    // Monochrome grain from luminance, scaled back onto the original hue
+    let luminance = dot(display, vec3f(0.2126, 0.7152, 0.0722));
+    var grained = display * (grain_coverage(pos.xy, 3u) / max(luminance, 1e-4));
+
+    // Independent grain layers per channel, like the separate dye layers of colour film
+    if (settings.color_variation > 0.0) {
+        let layered = vec3f(
+            grain_coverage(pos.xy, 0u),
+            grain_coverage(pos.xy, 1u),
+            grain_coverage(pos.xy, 2u),
+        );
+        grained = mix(grained, layered, settings.color_variation);
+    }
+
+    return vec4f(to_linear(mix(display, grained, settings.intensity)), 1.0);
+}
@@ -0,0 +1,24 @@
+// Shared by downsample.wgsl and bloom_compute.wgsl, Bloom prepends it when creating their shader modules
+
+// Luma weighted average of the 5 overlapping 2x2 boxes of the 13 taps (Karis, Call of Duty: Advanced Warfare),
+// a single very bright texel can't dominate the mip, which stops fireflies flickering as stars move sub-pixel
+fn karis_weight(color: vec4f) -> f32 {
+    let luma = dot(color.rgb, vec3f(0.2126, 0.7152, 0.0722));
+    return 1.0 / (1.0 + luma);
+}
+
+fn karis_average(a: vec4f, b: vec4f, c: vec4f, d: vec4f, e: vec4f, f: vec4f, g: vec4f, h: vec4f, i: vec4f, j: vec4f, k: vec4f, l: vec4f, m: vec4f) -> vec4f {
+    let center = (d + e + i + j) * 0.25;
+    let top_left = (a + b + f + g) * 0.25;
+    let top_right = (b + c + g + h) * 0.25;
+    let bottom_left = (f + g + k + l) * 0.25;
+    let bottom_right = (g + h + l + m) * 0.25;
+
+    let w0 = 0.5 * karis_weight(center);
+    let w1 = 0.125 * karis_weight(top_left);
+    let w2 = 0.125 * karis_weight(top_right);
+    let w3 = 0.125 * karis_weight(bottom_left);
+    let w4 = 0.125 * karis_weight(bottom_right);
+
+    return (center * w0 + top_left * w1 + top_right * w2 + bottom_left * w3 + bottom_right * w4) / (w0 + w1 + w2 + w3 + w4);
+}
@@ -0,0 +1,63 @@
+/*
+    Lens stage, applied to the tonemapped image before film grain (grain sits on the film, not in the lens).
+    Radial distortion (Brown's model, one coefficient), chromatic aberration as a per channel change
+    of that coefficient, and a cos^4 style vignette.
+*/
+
+var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+struct LensSettings {
+    distortion: f32, // > 0 barrel, < 0 pincushion
+    chromatic_aberration: f32,
+    vignette: f32,
+    vignette_falloff: f32,
+    aspect_ratio: f32,
+}
+
+@group(0) @binding(0) var input_texture: texture_2d<f32>;
+@group(0) @binding(1) var input_sampler: sampler;
+@group(0) @binding(2) var<uniform> settings: LensSettings;
+
+struct VertexOutput {
+    @builtin(position) pos: vec4f,
+    @location(0) uv: vec2f,
+}
+
+@vertex fn vs(@builtin(vertex_index) index : u32) -> VertexOutput {
+    let vert = quad_verts[index];
+    return VertexOutput(vec4<f32>(vert, 0.0, 1.0), vert * vec2f(0.5, -0.5) + 0.5);
+}
+
+// Returns the uv to sample, the radius is measured in half heights so the distortion stays round whatever the aspect ratio
+fn distort(offset: vec2f, k: f32) -> vec2f {
+    let scaled = offset * vec2f(settings.aspect_ratio, 1.0);
+    let r2 = dot(scaled, scaled);
+
+    // Barrel distortion pulls the corners inwards, zoom in so they stay on screen
+    let corner2 = settings.aspect_ratio * settings.aspect_ratio + 1.0;
+    let zoom = 1.0 + max(settings.distortion, 0.0) * corner2;
+
+    return offset * (1.0 + k * r2) / zoom * 0.5 + 0.5;
+}
+
+@fragment fn fs(in: VertexOutput) -> @location(0) vec4f {
+    let offset = in.uv * 2.0 - 1.0;
+
+    let ca = settings.chromatic_aberration;
+    let r = textureSampleLevel(input_texture, input_sampler, distort(offset, settings.distortion + ca), 0.0).r;
+    let g = textureSampleLevel(input_texture, input_sampler, distort(offset, settings.distortion), 0.0).g;
+    let b = textureSampleLevel(input_texture, input_sampler, distort(offset, settings.distortion - ca), 0.0).b;
+
+    let scaled = offset * vec2f(settings.aspect_ratio, 1.0);
+    let falloff = 1.0 / (1.0 + dot(scaled, scaled) * settings.vignette_falloff);
+    let vignette = mix(1.0, falloff * falloff, settings.vignette);
+
+    return vec4f(vec3f(r, g, b) * vignette, 1.0);
+}
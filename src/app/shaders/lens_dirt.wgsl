@@ -0,0 +1,34 @@
+var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+struct LensDirtSettings {
+    intensity: f32,
+}
+
+@group(0) @binding(0) var bloom_texture: texture_2d<f32>;
+@group(0) @binding(1) var dirt_texture: texture_2d<f32>;
+@group(0) @binding(2) var dirt_sampler: sampler;
+@group(0) @binding(3) var<uniform> settings: LensDirtSettings;
+
+struct VertexOutput {
+    @builtin(position) pos: vec4f,
+    @location(0) uv: vec2f,
+}
+
+@vertex fn vs(@builtin(vertex_index) index : u32) -> VertexOutput {
+    let vert = quad_verts[index];
+    return VertexOutput(vec4<f32>(vert, 0.0, 1.0), vert * vec2f(0.5, -0.5) + 0.5);
+}
+
+// Smudges only show up where bright light hits the lens, added onto the chain image
+@fragment fn fs(in: VertexOutput) -> @location(0) vec4f {
+    let bloom = textureSample(bloom_texture, dirt_sampler, in.uv).rgb;
+    let dirt = textureSample(dirt_texture, dirt_sampler, in.uv).rgb;
+    return vec4f(bloom * dirt * settings.intensity, 0.0);
+}
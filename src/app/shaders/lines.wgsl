@@ -0,0 +1,29 @@
+// World space lines with a per-vertex color and intensity, shared by the octree overlay and the star trails
+
+struct CameraUniform {
+    view_matrix: mat4x4<f32>,
+    projection_matrix: mat4x4<f32>,
+}
+
+@group(0) @binding(0) var<uniform> camera: CameraUniform;
+
+struct VertexInput {
+    @location(0) position: vec3<f32>,
+    @location(1) color: vec4<f32>,
+}
+
+struct VertexOutput {
+    @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec4<f32>,
+}
+
+@vertex fn vs(in: VertexInput) -> VertexOutput {
+    var out: VertexOutput;
+    out.clip_position = camera.projection_matrix * camera.view_matrix * vec4<f32>(in.position, 1.0);
+    out.color = in.color;
+    return out;
+}
+
+@fragment fn fs(in: VertexOutput) -> @location(0) vec4<f32> {
+    return vec4<f32>(in.color.rgb * in.color.a, 0.0);
+}
@@ -0,0 +1,81 @@
+struct AutoExposureSettings {
+    min_log_luminance: f32,
+    log_luminance_range: f32,
+    dt: f32, // seconds
+    speed_up: f32,
+    speed_down: f32,
+    key: f32, // target middle grey
+}
+
+struct ExposureState {
+    luminance: f32, // adapted average luminance
+    exposure: f32, // key / luminance
+}
+
+@group(0) @binding(0) var hdr_texture: texture_2d<f32>;
+@group(0) @binding(1) var<storage, read_write> histogram: array<atomic<u32>, 256>;
+@group(0) @binding(2) var<storage, read_write> state: ExposureState;
+@group(0) @binding(3) var<uniform> settings: AutoExposureSettings;
+
+// Bin 0 holds (near) black pixels and is left out of the average
+fn luminance_to_bin(luminance: f32) -> u32 {
+    if (luminance < 1e-6) {
+        return 0u;
+    }
+    let t = clamp((log2(luminance) - settings.min_log_luminance) / settings.log_luminance_range, 0.0, 1.0);
+    return u32(t * 254.0 + 1.0);
+}
+
+var<workgroup> local_bins: array<atomic<u32>, 256>;
+
+@compute @workgroup_size(16, 16) fn build_histogram(
+    @builtin(global_invocation_id) global_id: vec3<u32>,
+    @builtin(local_invocation_index) local_index: u32,
+) {
+    atomicStore(&local_bins[local_index], 0u);
+    workgroupBarrier();
+
+    let dim = textureDimensions(hdr_texture);
+    if (all(global_id.xy < dim)) {
+        let color = textureLoad(hdr_texture, global_id.xy, 0).rgb;
+        let luminance = dot(color, vec3<f32>(0.2126, 0.7152, 0.0722));
+        atomicAdd(&local_bins[luminance_to_bin(luminance)], 1u);
+    }
+    workgroupBarrier();
+
+    atomicAdd(&histogram[local_index], atomicLoad(&local_bins[local_index]));
+}
+
+var<workgroup> weighted_bins: array<f32, 256>;
+var<workgroup> counted_bins: array<f32, 256>;
+
+// Reduces the histogram to its mean bin, adapts towards it and clears the histogram for the next frame
+@compute @workgroup_size(256) fn average_histogram(@builtin(local_invocation_index) local_index: u32) {
+    let count = f32(atomicLoad(&histogram[local_index]));
+    weighted_bins[local_index] = count * f32(local_index);
+    counted_bins[local_index] = select(count, 0.0, local_index == 0u);
+    atomicStore(&histogram[local_index], 0u);
+    workgroupBarrier();
+
+    for (var stride = 128u; stride > 0u; stride = stride / 2u) {
+        if (local_index < stride) {
+            weighted_bins[local_index] += weighted_bins[local_index + stride];
+            counted_bins[local_index] += counted_bins[local_index + stride];
+        }
+        workgroupBarrier();
+    }
+
+    if (local_index == 0u) {
+        var target_luminance = state.luminance;
+        if (counted_bins[0] > 0.0) {
+            let mean_bin = weighted_bins[0] / counted_bins[0];
+            target_luminance = exp2((mean_bin - 1.0) / 254.0 * settings.log_luminance_range + settings.min_log_luminance);
+        }
+
+        let speed = select(settings.speed_down, settings.speed_up, target_luminance > state.luminance);
+        let luminance = state.luminance + (target_luminance - state.luminance) * (1.0 - exp(-settings.dt * speed));
+
+        state.luminance = luminance;
+        state.exposure = settings.key / luminance;
+    }
+}
@@ -7,14 +7,133 @@ var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
     vec2<f32>(-1.0, 1.0)
 );
 
+struct TonemapSettings {
+    tonemapper: u32,
+    exposure: f32,
+    white_point: f32,
+    auto_exposure: u32,
+}
+
+// Written by luminance_histogram.wgsl
+struct ExposureState {
+    luminance: f32,
+    exposure: f32,
+}
+
 @group(0) @binding(0) var input_texture: texture_2d<f32>;
 
+@group(1) @binding(0) var<uniform> tonemap_settings: TonemapSettings;
+@group(1) @binding(1) var<storage, read> exposure_state: ExposureState;
+
+// Operator ids, must match present::Tonemapper
+const LINEAR: u32 = 0u;
+const REINHARD: u32 = 1u;
+const UNCHARTED2: u32 = 2u;
+const ACES: u32 = 3u;
+const AGX: u32 = 4u;
+
 @vertex fn vs(@builtin(vertex_index) index : u32) -> @builtin(position) vec4f {
     let pos = vec4<f32>(quad_verts[index], 0.0, 1.0);
     
     return pos;
 }
 
+// Extended Reinhard, maps white_point to 1.0
+fn reinhard(x: vec3f, white_point: f32) -> vec3f {
+    return x * (1.0 + x / (white_point * white_point)) / (1.0 + x);
+}
+
+// John Hable's filmic curve
+fn hable(x: vec3f) -> vec3f {
+    let a = 0.15;
+    let b = 0.50;
+    let c = 0.10;
+    let d = 0.20;
+    let e = 0.02;
+    let f = 0.30;
+    return ((x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f)) - e / f;
+}
+
+fn uncharted2(x: vec3f, white_point: f32) -> vec3f {
+    return hable(x) / hable(vec3f(white_point));
+}
+
+// Stephen Hill's fit of the ACES RRT + ODT
+fn aces(x: vec3f) -> vec3f {
+    let input_mat = mat3x3f(
+        vec3f(0.59719, 0.07600, 0.02840),
+        vec3f(0.35458, 0.90834, 0.13383),
+        vec3f(0.04823, 0.01566, 0.83777),
+    );
+    let output_mat = mat3x3f(
+        vec3f(1.60475, -0.10208, -0.00327),
+        vec3f(-0.53108, 1.10813, -0.07276),
+        vec3f(-0.07367, -0.00605, 1.07602),
+    );
+
+    let v = input_mat * x;
+    let a = v * (v + 0.0245786) - 0.000090537;
+    let b = v * (0.983729 * v + 0.4329510) + 0.238081;
+    return clamp(output_mat * (a / b), vec3f(0.0), vec3f(1.0));
+}
+
+// Minimal AgX (Benjamin Wrensch), polynomial fit of the default contrast curve
+fn agx_contrast(x: vec3f) -> vec3f {
+    let x2 = x * x;
+    let x4 = x2 * x2;
+    return 15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x - 0.00232;
+}
+
+fn agx(x: vec3f) -> vec3f {
+    let inset = mat3x3f(
+        vec3f(0.842479062253094, 0.0423282422610123, 0.0423756549057051),
+        vec3f(0.0784335999999992, 0.878468636469772, 0.0784336),
+        vec3f(0.0792237451477643, 0.0791661274605434, 0.879142973793104),
+    );
+    let outset = mat3x3f(
+        vec3f(1.19687900512017, -0.0528968517574562, -0.0529716355144438),
+        vec3f(-0.0980208811401368, 1.15190312990417, -0.0980434501171241),
+        vec3f(-0.0990297440797205, -0.0989611768448433, 1.15107367264116),
+    );
+    let min_ev = -12.47393;
+    let max_ev = 4.026069;
+
+    var v = inset * x;
+    v = clamp(log2(max(v, vec3f(1e-10))), vec3f(min_ev), vec3f(max_ev));
+    v = (v - min_ev) / (max_ev - min_ev);
+    v = agx_contrast(v);
+    v = outset * v;
+
+    // AgX outputs display encoded values, the sRGB surface expects linear
+    return pow(max(v, vec3f(0.0)), vec3f(2.2));
+}
+
+fn tonemap(x: vec3f) -> vec3f {
+    switch tonemap_settings.tonemapper {
+        case REINHARD: {
+            return reinhard(x, tonemap_settings.white_point);
+        }
+        case UNCHARTED2: {
+            return uncharted2(x, tonemap_settings.white_point);
+        }
+        case ACES: {
+            return aces(x);
+        }
+        case AGX: {
+            return agx(x);
+        }
+        default: {
+            return clamp(x, vec3f(0.0), vec3f(1.0));
+        }
+    }
+}
+
 @fragment fn fs(@builtin(position) pos: vec4f) -> @location(0) vec4f {
-    return textureLoad(input_texture, vec2u(pos.xy), 0);
-}
\ No newline at end of file
+    let hdr = textureLoad(input_texture, vec2u(pos.xy), 0);
+
+    var exposure = tonemap_settings.exposure;
+    if (tonemap_settings.auto_exposure != 0u) {
+        exposure *= exposure_state.exposure;
+    }
+    return vec4f(tonemap(hdr.rgb * exposure), 1.0);
+}
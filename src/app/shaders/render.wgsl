@@ -1,9 +1,17 @@
 struct StarInput {
     @location(0) position: vec3<f32>,
+    @location(1) population: u32,
+    @location(2) velocity: vec3<f32>,
+    @location(3) mass: f32,
+    @location(4) birth_time: f32,
+    @location(5) property: f32, // ColorMode::Property only
 }
 
 struct VertexOutput {
     @builtin(position) clip_position: vec4<f32>,
+    @location(0) color: vec3<f32>,
+    @location(1) offset: vec2<f32>, // [-1, 1] across the sprite
+    @location(2) radius: f32, // pixels
 }
 
 struct CameraUniform {
@@ -11,15 +19,197 @@ struct CameraUniform {
     projection_matrix: mat4x4<f32>,
 }
 
+struct RenderSettings {
+    time: f32, // Myr
+    luminosity_scale: f32,
+    luminosity_exponent: f32,
+    star_size: f32, // world units
+    min_star_pixels: f32,
+    max_star_pixels: f32,
+    profile: u32, // 0 Gaussian, 1 Airy
+    color_mode: u32, // 0 Temperature, 1 Velocity, 2 Density, 3 Property
+    viewport: vec2<f32>,
+    velocity_range: f32,
+    max_density: f32,
+    grid_origin: vec3<f32>,
+    cell_size: f32,
+    range_min: f32, // log10 when log_scale is set
+    range_max: f32,
+    log_scale: u32,
+}
+
+// See render/black_body.rs, log spaced between 1000K and 40000K
+const LUT_SIZE: u32 = 256u;
+const MIN_TEMPERATURE: f32 = 1000.0;
+const MAX_TEMPERATURE: f32 = 40000.0;
+
+var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
 @group(0) @binding(0) var<uniform> camera: CameraUniform;
 
-@vertex fn vs_main(in: StarInput) -> VertexOutput {
+@group(1) @binding(0) var<uniform> settings: RenderSettings;
+@group(1) @binding(1) var<uniform> black_body_lut: array<vec4<f32>, LUT_SIZE>;
+@group(1) @binding(2) var density_grid: texture_3d<f32>;
+@group(1) @binding(3) var<uniform> colormap_lut: array<vec4<f32>, LUT_SIZE>;
+
+// Indexed by population id: host, satellite
+var<private> population_tints: array<vec3<f32>, 2> = array<vec3<f32>, 2>(
+    vec3<f32>(1.0, 1.0, 1.0),
+    vec3<f32>(1.0, 0.55, 0.2),
+);
+
+struct StellarState {
+    temperature: f32, // Kelvin
+    luminosity: f32, // solar luminosities
+}
+
+// Simplified single-star track, see galaxy/evolution.rs
+fn evolve(mass: f32, age: f32) -> StellarState {
+    let ms_lifetime = 10000.0 * pow(mass, -2.5);
+    let ms_luminosity = pow(mass, 3.5);
+    let ms_temperature = 5772.0 * pow(mass, 0.475);
+
+    if (age < ms_lifetime) {
+        // Slow brightening over the main sequence
+        let x = max(age, 0.0) / ms_lifetime;
+        return StellarState(ms_temperature, ms_luminosity * (1.0 + 0.5 * x));
+    }
+
+    let giant_lifetime = 0.1 * ms_lifetime;
+    if (age < ms_lifetime + giant_lifetime) {
+        let x = (age - ms_lifetime) / giant_lifetime;
+        let temperature = mix(ms_temperature, 3500.0, smoothstep(0.0, 0.3, x));
+        return StellarState(temperature, ms_luminosity * 1.5 * mix(1.0, 20.0, x));
+    }
+
+    // Remnants: white dwarfs cool and fade, neutron stars and black holes go dark
+    if (mass < 8.0) {
+        let cooling = 1.0 + (age - ms_lifetime - giant_lifetime) / 100.0;
+        return StellarState(max(30000.0 * pow(cooling, -0.4), 3000.0), 0.01 * pow(cooling, -1.4));
+    }
+    return StellarState(0.0, 0.0);
+}
+
+// Linearly interpolated, t in [0, 1] across the LUT
+fn black_body_lut_sample(t: f32) -> vec3<f32> {
+    let x = clamp(t, 0.0, 1.0) * f32(LUT_SIZE - 1u);
+    let i = min(u32(x), LUT_SIZE - 2u);
+    return mix(black_body_lut[i].rgb, black_body_lut[i + 1u].rgb, x - f32(i));
+}
+
+fn black_body(temperature: f32) -> vec3<f32> {
+    return black_body_lut_sample(log(temperature / MIN_TEMPERATURE) / log(MAX_TEMPERATURE / MIN_TEMPERATURE));
+}
+
+fn colormap_sample(t: f32) -> vec3<f32> {
+    let x = clamp(t, 0.0, 1.0) * f32(LUT_SIZE - 1u);
+    let i = min(u32(x), LUT_SIZE - 2u);
+    return mix(colormap_lut[i].rgb, colormap_lut[i + 1u].rgb, x - f32(i));
+}
+
+// Velocity and density reuse the black-body palette, low values red, high values blue
+fn palette_color(in: StarInput, state: StellarState) -> vec3<f32> {
+    switch settings.color_mode {
+        case 1u: {
+            return black_body_lut_sample(length(in.velocity) / settings.velocity_range);
+        }
+        case 2u: {
+            let cell = vec3<i32>((in.position - settings.grid_origin) / settings.cell_size);
+            let density = textureLoad(density_grid, clamp(cell, vec3<i32>(0), vec3<i32>(textureDimensions(density_grid)) - 1), 0).r;
+            return black_body_lut_sample(log(1.0 + density) / log(1.0 + settings.max_density));
+        }
+        case 3u: {
+            var value = in.property;
+            if (settings.log_scale != 0u) {
+                value = log(max(abs(value), 1e-30)) / log(10.0);
+            }
+            return colormap_sample((value - settings.range_min) / (settings.range_max - settings.range_min));
+        }
+        default: {
+            return black_body(state.temperature);
+        }
+    }
+}
+
+// Camera facing quad, star_size projected to pixels and clamped, so distant stars never drop below min_star_pixels
+@vertex fn vs_main(@builtin(vertex_index) index: u32, in: StarInput) -> VertexOutput {
     var out: VertexOutput;
-    let transformed_pos = camera.projection_matrix * camera.view_matrix * vec4<f32>(in.position, 1.0);
-    out.clip_position = transformed_pos;
+
+    let state = evolve(in.mass, settings.time - in.birth_time);
+    let center = camera.projection_matrix * camera.view_matrix * vec4<f32>(in.position, 1.0);
+    if (state.luminosity <= 0.0 || center.w <= 0.0) {
+        // Dark remnant or behind the camera, push it outside the clip volume
+        out.clip_position = vec4<f32>(2.0, 2.0, 2.0, 1.0);
+        return out;
+    }
+
+    let projected = settings.star_size * camera.projection_matrix[1][1] / center.w * 0.5 * settings.viewport.y;
+    let radius = clamp(projected, settings.min_star_pixels, settings.max_star_pixels);
+
+    let corner = quad_verts[index];
+    out.clip_position = center + vec4<f32>(corner * radius * 2.0 / settings.viewport * center.w, 0.0, 0.0);
+    out.offset = corner;
+    out.radius = radius;
+
+    let brightness = settings.luminosity_scale * pow(state.luminosity, settings.luminosity_exponent);
+    if (settings.color_mode == 3u) {
+        // Analysis, every star equally bright so only the colormap carries information
+        out.color = palette_color(in, state) * settings.luminosity_scale;
+    } else {
+        out.color = palette_color(in, state) * brightness * population_tints[min(in.population, 1u)];
+    }
     return out;
 }
 
+// Bessel function of the first kind, order 1 (Numerical Recipes rational approximation)
+fn bessel_j1(x: f32) -> f32 {
+    let ax = abs(x);
+    if (ax < 8.0) {
+        let y = x * x;
+        let a = x * (72362614232.0 + y * (-7895059235.0 + y * (242396853.1 + y * (-2972611.439 + y * (15704.48260 + y * -30.16036606)))));
+        let b = 144725228442.0 + y * (2300535178.0 + y * (18583304.74 + y * (99447.43394 + y * (376.9991397 + y))));
+        return a / b;
+    }
+
+    let z = 8.0 / ax;
+    let y = z * z;
+    let shifted = ax - 2.356194491;
+    let p = 1.0 + y * (0.183105e-2 + y * (-0.3516396496e-4 + y * (0.2457520174e-5 + y * -0.240337019e-6)));
+    let q = 0.04687499995 + y * (-0.2002690873e-3 + y * (0.8449199096e-5 + y * (-0.88228987e-6 + y * 0.105787412e-6)));
+    let result = sqrt(0.636619772 / ax) * (cos(shifted) * p - z * sin(shifted) * q);
+    return select(result, -result, x < 0.0);
+}
+
+const PI: f32 = 3.14159265;
+const AIRY_THIRD_ZERO: f32 = 10.1735;
+
+// Both profiles are normalized to unit flux, so a star keeps its brightness whatever its size on screen
 @fragment fn fs_main(in: VertexOutput) -> @location(0) vec4<f32>{
-    return vec4<f32>(1.0, 1.0, 1.0, 1.0);
-}
\ No newline at end of file
+    let d = length(in.offset);
+    if (d > 1.0) {
+        return vec4<f32>(0.0);
+    }
+    let r = d * in.radius;
+
+    var profile: f32;
+    if (settings.profile == 0u) {
+        // Quad covers 3 sigma
+        let sigma = in.radius / 3.0;
+        profile = exp(-r * r / (2.0 * sigma * sigma)) / (2.0 * PI * sigma * sigma);
+    } else {
+        // Quad covers the first two rings
+        let k = AIRY_THIRD_ZERO / in.radius;
+        let x = max(k * r, 1e-4);
+        let amplitude = 2.0 * bessel_j1(x) / x;
+        profile = amplitude * amplitude * k * k / (4.0 * PI);
+    }
+
+    return vec4<f32>(in.color * profile, 0.0);
+}
@@ -0,0 +1,31 @@
+// Decaying trail accumulation, see render/trails.rs
+// Each frame writes max(scene, previous * decay) into the other accumulation texture, which is then copied over the scene
+
+struct TrailSettings {
+    decay: f32,
+}
+
+@group(0) @binding(0) var scene_texture: texture_2d<f32>;
+@group(0) @binding(1) var previous_texture: texture_2d<f32>;
+@group(0) @binding(2) var<uniform> settings: TrailSettings;
+
+var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
+    vec2<f32>(-1.0, 1.0),
+    vec2<f32>(-1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, -1.0),
+    vec2<f32>(1.0, 1.0),
+    vec2<f32>(-1.0, 1.0)
+);
+
+@vertex fn vs(@builtin(vertex_index) index : u32) -> @builtin(position) vec4f {
+    return vec4<f32>(quad_verts[index], 0.0, 1.0);
+}
+
+// Max rather than a sum, static stars keep their brightness instead of building up
+@fragment fn fs(@builtin(position) pos: vec4f) -> @location(0) vec4f {
+    let texel = vec2<i32>(pos.xy);
+    let scene = textureLoad(scene_texture, texel, 0);
+    let previous = textureLoad(previous_texture, texel, 0);
+    return vec4f(max(scene.rgb, previous.rgb * settings.decay), 1.0);
+}
@@ -1,5 +1,9 @@
-struct UpsampleSettings {
+struct BloomSettings {
     filter_size: f32,
+    threshold: f32,
+    soft_knee: f32,
+    intensity: f32,
+    karis_average: u32,
 }
 
 var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
@@ -14,7 +18,17 @@ var<private> quad_verts: array<vec2<f32>,6> = array<vec2<f32>, 6>(
 @group(0) @binding(0) var input_texture: texture_2d<f32>;
 @group(0) @binding(1) var samp: sampler;
 
-@group(1) @binding(0) var<uniform> upsample_settings: UpsampleSettings;
+@group(1) @binding(0) var<uniform> bloom_settings: BloomSettings;
+
+// Weight * tint of the source mip, only the deepest mip is scaled here,
+// every other level is scaled through the blend constant when it is the render target.
+// target_scale is only used by the compute path, which can't blend
+struct MipSettings {
+    scale: vec4f,
+    target_scale: vec4f,
+}
+
+@group(2) @binding(0) var<uniform> mip_settings: MipSettings;
 
 struct VertexOutput {
     @builtin(position) position: vec4<f32>,
@@ -26,7 +40,7 @@ struct VertexOutput {
     
     var vertexOutput: VertexOutput;
     vertexOutput.position = pos;
-    vertexOutput.texcoord = (pos.xy / 2.0) + 0.5;
+    vertexOutput.texcoord = pos.xy * vec2f(0.5, -0.5) + 0.5; // Texture space has y pointing down
     return vertexOutput;
 }
 
@@ -35,7 +49,7 @@ struct VertexOutput {
     //Use TextureDimension with miplevel instead..
     let texcoord = input.texcoord;
 
-    let filter_size = upsample_settings.filter_size;
+    let filter_size = bloom_settings.filter_size;
 
     //9 Samples, Tent Filter
     //
@@ -61,5 +75,5 @@ struct VertexOutput {
     weighted_average += (a + c + g + i);
     weighted_average /= 16.0;
 
-    return weighted_average;
+    return weighted_average * mip_settings.scale;
 }
\ No newline at end of file
@@ -1,62 +1,112 @@
-use std::{cell::RefCell, collections::VecDeque, rc::Rc, sync::{Arc, Mutex}};
+use std::{cell::Cell, collections::VecDeque, sync::{Arc, Mutex}};
 
 use wgpu::*;
 use bytemuck;
 
 pub struct Timestamps {
     pub query_set: QuerySet,
+    scopes: u32,
     buffer: Buffer,
-    pub last_frame_times: Arc<Mutex<[u64; TIMESTAMP_QUERY_COUNT as usize]>>,
+    // Ticks per scope, None when the scope wasn't written that frame
+    pub last_frame_times: Arc<Mutex<Vec<Option<u64>>>>,
+    pub period: f32, // Nanoseconds per tick, depends on the backend
+    written: Vec<bool>, // Of the frame resolved last, handed to its readback
 
     unmapped_ring: Arc<Mutex<VecDeque<Buffer>>>,
 }
 
-const TIMESTAMP_QUERY_COUNT: u32 = 4;
+// Begin / end query pair handed to a single timed pass, or split across the first and last of several.
+// Handing out the end write marks the scope as written this frame, only written scopes are resolved
+#[derive(Clone, Copy)]
+pub struct TimestampScope<'a> {
+    pub query_set: &'a QuerySet,
+    pub begin: u32,
+    pub end: u32,
+    pub written: &'a Cell<bool>,
+}
+
+impl<'a> TimestampScope<'a> {
+    pub fn render_writes(&self, begin: bool, end: bool) -> RenderPassTimestampWrites<'a> {
+        if end {
+            self.written.set(true);
+        }
+        RenderPassTimestampWrites {
+            query_set: self.query_set,
+            beginning_of_pass_write_index: begin.then_some(self.begin),
+            end_of_pass_write_index: end.then_some(self.end),
+        }
+    }
+
+    pub fn compute_writes(&self, begin: bool, end: bool) -> ComputePassTimestampWrites<'a> {
+        if end {
+            self.written.set(true);
+        }
+        ComputePassTimestampWrites {
+            query_set: self.query_set,
+            beginning_of_pass_write_index: begin.then_some(self.begin),
+            end_of_pass_write_index: end.then_some(self.end),
+        }
+    }
+}
+
 impl Timestamps {
     
-    pub fn new(device: &Device) -> Self {
-        // Start, After Render, After Bloom, After Presenting
+    // Two queries per scope, the render graph hands out the pairs.
+    // Scopes resolve separately, so each gets a QUERY_RESOLVE_BUFFER_ALIGNMENT slot in the buffers
+    pub fn new(device: &Device, queue: &Queue, scopes: u32) -> Self {
         let timestamps = device.create_query_set(&QuerySetDescriptor { 
             label: Some("Timestamp QuerySet"), 
             ty: QueryType::Timestamp, 
-            count: TIMESTAMP_QUERY_COUNT
+            count: 2 * scopes
         });
         let timestamps_buffer = device.create_buffer(&BufferDescriptor { 
             label: Some("Timestamp Buffer"), 
-            size: (8 * TIMESTAMP_QUERY_COUNT as u64),
+            size: Self::buffer_size(scopes),
             usage: BufferUsages::QUERY_RESOLVE | BufferUsages::COPY_SRC, 
             mapped_at_creation: false
         });
-        let timestamps_mapped_buffer = device.create_buffer(&Timestamps::get_ring_buffer_descriptor());
+        let timestamps_mapped_buffer = device.create_buffer(&Timestamps::get_ring_buffer_descriptor(scopes));
         
         Self {
             query_set: timestamps,
+            scopes,
             buffer: timestamps_buffer,
+            period: queue.get_timestamp_period(),
             unmapped_ring: Arc::new(Mutex::new(vec![timestamps_mapped_buffer].into())),
-            last_frame_times: Arc::new(Mutex::new([0;4]))
+            last_frame_times: Arc::new(Mutex::new(vec![None; scopes as usize])),
+            written: vec![false; scopes as usize],
         }
             
     }
 
-    fn get_ring_buffer_descriptor() -> BufferDescriptor<'static> {
+    fn buffer_size(scopes: u32) -> u64 {
+        scopes as u64 * QUERY_RESOLVE_BUFFER_ALIGNMENT
+    }
+
+    fn get_ring_buffer_descriptor(scopes: u32) -> BufferDescriptor<'static> {
         BufferDescriptor {
             label: Some("Timestamp Mapped Buffer"), 
-            size: (8 * TIMESTAMP_QUERY_COUNT as u64), 
+            size: Self::buffer_size(scopes), 
             usage: BufferUsages::COPY_DST | BufferUsages::MAP_READ, 
             mapped_at_creation: false
         }
     }
 
 
-    pub fn resolve(&mut self, encoder: &mut CommandEncoder, device: &Device) {
-        encoder.resolve_query_set(&self.query_set, 0..TIMESTAMP_QUERY_COUNT, &self.buffer, 0);
+    // written holds one flag per scope, unwritten queries aren't resolved and read back as None
+    pub fn resolve(&mut self, encoder: &mut CommandEncoder, device: &Device, written: &[bool]) {
+        for (scope, _) in written.iter().enumerate().filter(|(_, written)| **written) {
+            let first = 2 * scope as u32;
+            encoder.resolve_query_set(&self.query_set, first..first + 2, &self.buffer, scope as u64 * QUERY_RESOLVE_BUFFER_ALIGNMENT);
+        }
+        self.written = written.to_vec();
 
         let mut unmapped_ring = self.unmapped_ring.lock().unwrap();
         
         if unmapped_ring.is_empty() {
-            unmapped_ring.push_back(device.create_buffer(&Timestamps::get_ring_buffer_descriptor()));
+            unmapped_ring.push_back(device.create_buffer(&Timestamps::get_ring_buffer_descriptor(self.scopes)));
         };
-        encoder.copy_buffer_to_buffer(&self.buffer, 0, unmapped_ring.front().unwrap(), 0, 8 * TIMESTAMP_QUERY_COUNT as u64);
+        encoder.copy_buffer_to_buffer(&self.buffer, 0, unmapped_ring.front().unwrap(), 0, Self::buffer_size(self.scopes));
     }
 
     pub fn update_times(&mut self, device: &mut Device) {
@@ -64,10 +114,17 @@ impl Timestamps {
         let buffer_to_map = Arc::<Buffer>::new(self.unmapped_ring.lock().unwrap().pop_front().unwrap());
         let last_frame_times = self.last_frame_times.clone();
         let mut unmapped_ring = self.unmapped_ring.clone();
+        let size = Self::buffer_size(self.scopes);
+        let written = std::mem::take(&mut self.written);
         
-        buffer_to_map.clone().slice(..(8 * TIMESTAMP_QUERY_COUNT as u64)).map_async(MapMode::Read, move |res| {
-            let buffer_view = buffer_to_map.slice(..(8 * TIMESTAMP_QUERY_COUNT as u64)).get_mapped_range();
-            (*last_frame_times.lock().unwrap()).copy_from_slice(bytemuck::cast_slice(&buffer_view));
+        buffer_to_map.clone().slice(..size).map_async(MapMode::Read, move |res| {
+            let buffer_view = buffer_to_map.slice(..size).get_mapped_range();
+            let queries: &[u64] = bytemuck::cast_slice(&buffer_view);
+            let stride = (QUERY_RESOLVE_BUFFER_ALIGNMENT / 8) as usize;
+            for (scope, time) in last_frame_times.lock().unwrap().iter_mut().enumerate() {
+                let (begin, end) = (queries[scope * stride], queries[scope * stride + 1]);
+                *time = written[scope].then(|| end.saturating_sub(begin));
+            }
             drop(buffer_view);
 
             buffer_to_map.unmap();
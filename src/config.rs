@@ -1,9 +1,37 @@
 use serde::Deserialize;
 
+use crate::app::galaxy::emission::EmissionMode;
+use crate::app::galaxy::scenario::ScenarioKind;
+use crate::app::post_processing::present::Tonemapper;
+use crate::app::post_processing::bloom::BloomMode;
+use crate::app::post_processing::chain::PostEffectKind;
+use crate::app::render::{ColorMode, RenderMode, StarProfile};
+use crate::app::render::colormap::Colormap;
+use crate::app::render::octree_overlay::NodeColoring;
+use crate::app::render::visualization::StarProperty;
+
 #[derive(Deserialize, Debug)]
 pub struct Config {
     pub window_config: WindowConfig,
     pub sim_config: SimConfig,
+    pub keybind_config: KeybindConfig,
+    pub scenario_config: ScenarioConfig,
+    pub evolution_config: EvolutionConfig,
+    pub star_formation_config: StarFormationConfig,
+    pub render_config: RenderConfig,
+    pub visualization_config: VisualizationConfig,
+    pub density_splat_config: DensitySplatConfig,
+    pub dust_config: DustConfig,
+    pub octree_overlay_config: OctreeOverlayConfig,
+    pub trails_config: TrailsConfig,
+    pub post_chain_config: PostChainConfig,
+    pub bloom_config: BloomConfig,
+    pub tonemap_config: TonemapConfig,
+    pub auto_exposure_config: AutoExposureConfig,
+    pub film_grain_config: FilmGrainConfig,
+    pub lens_config: LensConfig,
+    pub color_grading_config: ColorGradingConfig,
+    pub lens_dirt_config: LensDirtConfig,
 }
 
 #[derive(Deserialize, Debug)]
@@ -22,6 +50,222 @@ pub struct SimConfig {
     pub noise_scale: f32,
     pub orbit_speed: f32,
     pub zoom_speed: f32,
+    pub seed: u64,
+    pub emission_mode: EmissionMode,
+    pub emission_rate: f32,
+    pub burst_size: usize,
+}
+
+// Simulation units: G = 1, time_scale is simulation time per real second
+#[derive(Deserialize, Debug, Clone)]
+pub struct ScenarioConfig {
+    pub scenario: ScenarioKind,
+    pub time_scale: f32,
+    pub max_step: f32,
+    pub host_mass: f32,
+    pub host_scale_radius: f32,
+    pub satellite_star_count: u32,
+    pub satellite_mass: f32,
+    pub satellite_scale_radius: f32,
+    pub satellite_position: [f32; 3],
+    pub satellite_velocity: [f32; 3],
+}
+
+#[derive(Deserialize, Debug, Clone)]
+pub struct EvolutionConfig {
+    pub myr_per_second: f32,
+    pub age_spread: f32, // Myr, how far back pre-existing stars were born
+    pub min_mass: f32,
+    pub max_mass: f32,
+    pub imf_slope: f32,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct StarFormationConfig {
+    pub enabled: bool,
+    pub grid_resolution: u32,
+    pub gas_mass: f32, // solar masses, spread over the grid
+    pub interarm_fraction: f32, // gas density between arms relative to the arm peak
+    pub depletion_time: f32, // Myr
+    pub replenish_time: f32, // Myr
+    pub particle_mass: f32, // gas mass converted per spawned star
+    pub young_min_mass: f32,
+    pub max_stars_per_frame: u32,
+}
+
+// star_size is in world units, the projected sprite is clamped to [min_star_pixels, max_star_pixels]
+#[derive(Deserialize, Debug)]
+pub struct RenderConfig {
+    pub render_mode: RenderMode,
+    pub luminosity_scale: f32,
+    pub luminosity_exponent: f32,
+    pub star_size: f32,
+    pub min_star_pixels: f32,
+    pub max_star_pixels: f32,
+    pub star_profile: StarProfile,
+    pub color_mode: ColorMode,
+    pub velocity_range: f32, // speed mapped to the hot end of the palette
+}
+
+// Used by ColorMode::Property, range is in log10 when log_scale is set, theta / softening drive the Barnes-Hut pass
+#[derive(Deserialize, Debug)]
+pub struct VisualizationConfig {
+    pub property: StarProperty,
+    pub colormap: Colormap,
+    pub auto_range: bool,
+    pub range_min: f32,
+    pub range_max: f32,
+    pub log_scale: bool,
+    pub theta: f32,
+    pub softening: f32,
+    pub update_interval: f32, // seconds
+}
+
+// Used by RenderMode::DensitySplat, the log range is log10 solar masses per pixel
+#[derive(Deserialize, Debug)]
+pub struct DensitySplatConfig {
+    pub kernel_radius: u32, // pixels
+    pub log_min: f32,
+    pub log_max: f32,
+    pub brightness: f32,
+    pub colormap: Colormap,
+}
+
+// Extinction is the V band optical depth through the densest face-on gas column, reddening the
+// wavelength exponent of the extinction curve, scale_height is in world units
+// There is no per-pixel depth, every star is treated as sitting in the disk plane, so halo, satellite and
+// tidal tail stars in front of the disk are dimmed by the dust behind them as well
+#[derive(Deserialize, Debug)]
+pub struct DustConfig {
+    pub enabled: bool,
+    pub extinction: f32,
+    pub reddening: f32,
+    pub scale_height: f32,
+    pub steps: u32,
+}
+
+// Node bounds down to max_depth, the nodes opened for the force on selected_star are highlighted
+#[derive(Deserialize, Debug)]
+pub struct OctreeOverlayConfig {
+    pub enabled: bool,
+    pub max_depth: u32,
+    pub coloring: NodeColoring,
+    pub colormap: Colormap,
+    pub intensity: f32,
+    pub highlight_opened: bool,
+    pub selected_star: usize,
+    pub theta: f32,
+    pub softening: f32,
+    pub update_interval: f32, // seconds
+}
+
+// Persistence is the 1 / e time of the accumulation in seconds,
+// history tracks the stars inside the selection region, history_length is in samples
+#[derive(Deserialize, Debug)]
+pub struct TrailsConfig {
+    pub accumulation: bool,
+    pub persistence: f32,
+    pub history: bool,
+    pub history_length: u32,
+    pub sample_interval: f32, // seconds
+    pub color: [f32; 3],
+    pub intensity: f32,
+}
+
+// Effects in the order they run, effects left out start disabled and can be enabled from the UI
+#[derive(Deserialize, Debug)]
+pub struct PostChainConfig {
+    pub order: Vec<PostEffectKind>,
+}
+
+// Threshold is in HDR luminance, soft_knee is a fraction of the threshold
+// mip_levels = 0 derives the count from the resolution (at least 2), missing mip weights / tints default to 1.0 / white
+#[derive(Deserialize, Debug)]
+pub struct BloomConfig {
+    pub mode: BloomMode,
+    pub filter_size: f32,
+    pub threshold: f32,
+    pub soft_knee: f32,
+    pub intensity: f32,
+    pub karis_average: bool,
+    pub mip_levels: u32,
+    pub min_mip_size: u32,
+    pub mip_weights: Vec<f32>,
+    pub mip_tints: Vec<[f32; 3]>,
+}
+
+#[derive(Deserialize, Debug)]
+pub struct TonemapConfig {
+    pub operator: Tonemapper,
+    pub exposure: f32, // EV
+    pub white_point: f32,
+}
+
+// The EV range bounds both the histogram and the adapted luminance
+#[derive(Deserialize, Debug)]
+pub struct AutoExposureConfig {
+    pub enabled: bool,
+    pub min_ev: f32,
+    pub max_ev: f32,
+    pub speed_up: f32,
+    pub speed_down: f32,
+    pub key: f32,
+}
+
+// Grain radius and filter sigma are in pixels
+#[derive(Deserialize, Debug)]
+pub struct FilmGrainConfig {
+    pub enabled: bool,
+    pub grain_radius: f32,
+    pub intensity: f32,
+    pub color_variation: f32,
+    pub filter_sigma: f32,
+    pub samples: u32,
+    pub animated: bool,
+    pub deterministic: bool,
+    pub seed: u32,
+}
+
+// Distortion > 0 is barrel, < 0 pincushion, chromatic aberration shifts the red / blue distortion either way
+#[derive(Deserialize, Debug)]
+pub struct LensConfig {
+    pub enabled: bool,
+    pub distortion: f32,
+    pub chromatic_aberration: f32,
+    pub vignette: f32,
+    pub vignette_falloff: f32,
+}
+
+// An empty LUT path means no LUT, files are .cube and reload when they change on disk
+#[derive(Deserialize, Debug)]
+pub struct ColorGradingConfig {
+    pub enabled: bool,
+    pub lut: String,
+    pub lut_intensity: f32,
+    pub lift: [f32; 3],
+    pub gamma: [f32; 3],
+    pub gain: [f32; 3],
+    pub saturation: f32,
+}
+
+// An empty texture path means procedural dirt, files are .ppm / .pgm
+// Samples bloom's mip 0, so it only draws with bloom ahead of it in the chain order
+#[derive(Deserialize, Debug)]
+pub struct LensDirtConfig {
+    pub enabled: bool,
+    pub intensity: f32,
+    pub texture: String,
+    pub seed: u64,
+}
+
+// Key names match winit's KeyCode variants, e.g. "KeyR" or "Delete"
+#[derive(Deserialize, Debug)]
+pub struct KeybindConfig {
+    pub clear: String,
+    pub reset: String,
+    pub delete_selection: String,
+    pub burst: String,
+    pub dump_render_graph: String,
 }
 
 const CONFIG_DIR: &str = "./config/";
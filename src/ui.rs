@@ -12,14 +12,26 @@ use crate::app::AppState;
 use crate::wgpu_state::{self, WgpuState};
 
 use crate::app::camera::*;
-use crate::app::galaxy::Galaxy;
+use crate::app::galaxy::{bhot, Galaxy, Region};
+use crate::app::galaxy::emission::EmissionMode;
+use crate::app::galaxy::scenario::ScenarioKind;
 use crate::app::post_processing::bloom::*;
-use crate::app::timestamps::Timestamps;
+use crate::app::post_processing::chain::PostChain;
+use crate::app::render::{ColorMode, RenderMode, StarProfile};
+use crate::app::render::colormap::Colormap;
+use crate::app::render::octree_overlay::{NodeColoring, MAX_NODES};
+use crate::app::render::trails::{MAX_HISTORY_LENGTH, MAX_TRACKED_STARS};
+use crate::app::render::visualization::StarProperty;
+use crate::app::timestamps::TimestampScope;
 
 pub struct UI {
     pub context: Context,
     state: State,
     renderer: Renderer,
+
+    // Widget state
+    remove_index: usize,
+    bloom_times: [f64; 2], // Last measured time per BloomMode, to compare them
 }
 
 impl UI {
@@ -46,12 +58,21 @@ impl UI {
             context,
             renderer, 
             state,
+            remove_index: 0,
+            bloom_times: [0.0; 2],
         }
     } 
 
-    pub fn update(&mut self, wgpu_state: &WgpuState, camera: &mut Camera<PerspectiveProjection>, bloom: &mut Bloom, timestamps: &Timestamps, galaxy: &Galaxy) -> FullOutput {
+    // times are the render graph's last measured scopes, in pass order, None when skipped last frame
+    pub fn update(&mut self, wgpu_state: &WgpuState, camera: &mut Camera<PerspectiveProjection>, times: &[(&'static str, Option<f64>)], galaxy: &mut Galaxy, renderer: &mut crate::app::render::Renderer, post_chain: &mut PostChain) -> FullOutput {
         let raw_input = self.state.take_egui_input(wgpu_state.window);
-        let timestamps = timestamps.last_frame_times.lock().unwrap();
+        let time = |scope: &str| times.iter().find(|(name, _)| *name == scope).and_then(|(_, time)| *time);
+        let show = |time: Option<f64>| time.map_or("n/a".to_string(), |time| time.to_string());
+        if let Some(bloom) = post_chain.get::<Bloom>().filter(|bloom| bloom.enabled) {
+            if let Some(bloom_time) = time("Bloom") {
+                self.bloom_times[bloom.mode as usize] = bloom_time;
+            }
+        }
 
         self.context.run(raw_input, |ui| {
             egui::SidePanel::right("Controls")
@@ -62,6 +83,236 @@ impl UI {
                 ui.group(|ui| {
                     ui.label("Galaxy");
                     ui.label(format!("{} stars",galaxy.stars.len()));
+                    ui.horizontal(|ui| {
+                        if ui.button("Clear").clicked() {
+                            galaxy.clear();
+                        }
+                        if ui.button("Reset").clicked() {
+                            galaxy.reset(&wgpu_state.queue);
+                        }
+                    });
+                    ui.horizontal(|ui| {
+                        let max_index = galaxy.stars.len().saturating_sub(1);
+                        self.remove_index = self.remove_index.min(max_index);
+                        ui.add(egui::DragValue::new(&mut self.remove_index).clamp_range(0..=max_index));
+                        if ui.button("Remove Star").clicked() {
+                            galaxy.remove_star(&wgpu_state.queue, self.remove_index);
+                        }
+                    });
+                });
+                ui.group(|ui| {
+                    ui.label("Scenario");
+                    egui::ComboBox::from_label("Scenario (applied on reset)")
+                        .selected_text(galaxy.scenario.name())
+                        .show_ui(ui, |ui| {
+                            for scenario in ScenarioKind::ALL {
+                                ui.selectable_value(&mut galaxy.scenario, scenario, scenario.name());
+                            }
+                        });
+                    if let Some(satellite) = &galaxy.satellite {
+                        let p = satellite.position;
+                        ui.label(format!("Satellite at ({:.0}, {:.0}, {:.0}), r = {:.0}", p[0], p[1], p[2], (p[0] * p[0] + p[1] * p[1] + p[2] * p[2]).sqrt()));
+                    }
+                });
+                ui.group(|ui| {
+                    ui.label("Emission");
+                    let emitter = &mut galaxy.emitter;
+                    egui::ComboBox::from_label("Mode")
+                        .selected_text(emitter.mode.name())
+                        .show_ui(ui, |ui| {
+                            for mode in EmissionMode::ALL {
+                                ui.selectable_value(&mut emitter.mode, mode, mode.name());
+                            }
+                        });
+                    ui.label(format!("{} / {} emitted, {} from bursts", emitter.emitted(), emitter.target, emitter.burst_emitted()));
+                    ui.add(egui::Slider::new(&mut emitter.rate, 0.0..=200000.0).text("Stars / Second"));
+                    ui.add(egui::Slider::new(&mut emitter.burst_size, 0..=100000).text("Burst Size"));
+                    if ui.button("Burst").clicked() {
+                        emitter.burst();
+                    }
+                });
+                ui.group(|ui| {
+                    ui.label("Selection");
+                    ui.horizontal(|ui| {
+                        let is_sphere = matches!(galaxy.selection, Region::Sphere { .. });
+                        if ui.selectable_label(is_sphere, "Sphere").clicked() && !is_sphere {
+                            galaxy.selection = Region::default();
+                        }
+                        if ui.selectable_label(!is_sphere, "Box").clicked() && is_sphere {
+                            galaxy.selection = Region::Box { min: [-100.0, -100.0, -100.0], max: [100.0, 100.0, 100.0] };
+                        }
+                    });
+                    match &mut galaxy.selection {
+                        Region::Sphere { center, radius } => {
+                            ui.add(egui::Slider::new(&mut center[0], -2000.0..=2000.0).text("Center X"));
+                            ui.add(egui::Slider::new(&mut center[1], -2000.0..=2000.0).text("Center Y"));
+                            ui.add(egui::Slider::new(&mut center[2], -2000.0..=2000.0).text("Center Z"));
+                            ui.add(egui::Slider::new(radius, 0.0..=2000.0).text("Radius"));
+                        },
+                        Region::Box { min, max } => {
+                            for (axis, name) in ["X", "Y", "Z"].iter().enumerate() {
+                                ui.add(egui::Slider::new(&mut min[axis], -2000.0..=2000.0).text(format!("Min {}", name)));
+                                ui.add(egui::Slider::new(&mut max[axis], -2000.0..=2000.0).text(format!("Max {}", name)));
+                            }
+                        }
+                    }
+                    if ui.button("Delete Selection").clicked() {
+                        galaxy.remove_selection(&wgpu_state.queue);
+                    }
+                });
+                ui.group(|ui| {
+                    ui.label("Stellar Evolution");
+                    ui.label(format!("t = {:.0} Myr", galaxy.time));
+                    ui.add(egui::Slider::new(&mut galaxy.myr_per_second, 0.0..=1000.0).logarithmic(true).text("Myr / Second"));
+                    ui.checkbox(&mut galaxy.star_formation.enabled, "Star Formation");
+                    ui.label(format!("Gas: {:.0} solar masses", galaxy.star_formation.total_gas()));
+                    ui.add(egui::Slider::new(&mut galaxy.star_formation.depletion_time, 100.0..=20000.0).logarithmic(true).text("Depletion Time (Myr)"));
+                    ui.add(egui::Slider::new(&mut galaxy.star_formation.replenish_time, 10.0..=10000.0).logarithmic(true).text("Replenish Time (Myr)"));
+                    ui.add(egui::Slider::new(&mut renderer.luminosity_scale, 0.0..=5.0).text("Luminosity Scale"));
+                    ui.add(egui::Slider::new(&mut renderer.luminosity_exponent, 0.0..=1.0).text("Luminosity Exponent"));
+                    egui::ComboBox::from_label("Color")
+                        .selected_text(renderer.color_mode.name())
+                        .show_ui(ui, |ui| {
+                            for mode in ColorMode::ALL {
+                                ui.selectable_value(&mut renderer.color_mode, mode, mode.name());
+                            }
+                        });
+                    if renderer.color_mode == ColorMode::Velocity {
+                        ui.add(egui::Slider::new(&mut renderer.velocity_range, 1.0..=1000.0).logarithmic(true).text("Velocity Range"));
+                    }
+                });
+                if renderer.color_mode == ColorMode::Property {
+                    let visualization = &mut renderer.visualization;
+                    ui.group(|ui| {
+                        ui.label("Visualization");
+                        egui::ComboBox::from_label("Property")
+                            .selected_text(visualization.property.name())
+                            .show_ui(ui, |ui| {
+                                for property in StarProperty::ALL {
+                                    ui.selectable_value(&mut visualization.property, property, property.name());
+                                }
+                            });
+                        egui::ComboBox::from_label("Colormap")
+                            .selected_text(visualization.colormap.name())
+                            .show_ui(ui, |ui| {
+                                for colormap in Colormap::ALL {
+                                    ui.selectable_value(&mut visualization.colormap, colormap, colormap.name());
+                                }
+                            });
+                        ui.checkbox(&mut visualization.log_scale, "Log Scale");
+                        ui.checkbox(&mut visualization.auto_range, "Auto Range");
+                        ui.add_enabled_ui(!visualization.auto_range, |ui| {
+                            ui.horizontal(|ui| {
+                                ui.add(egui::DragValue::new(&mut visualization.range[0]).speed(0.01).prefix("Min: "));
+                                ui.add(egui::DragValue::new(&mut visualization.range[1]).speed(0.01).prefix("Max: "));
+                            });
+                        });
+                        ui.add(egui::Slider::new(&mut visualization.theta, 0.0..=1.5).text("Opening Angle (Theta)"));
+                        ui.add(egui::Slider::new(&mut visualization.softening, 0.01..=100.0).logarithmic(true).text("Softening"));
+                        ui.add(egui::Slider::new(&mut visualization.update_interval, 0.0..=10.0).text("Update Interval (s)"));
+
+                        Self::draw_colorbar(ui, visualization.colormap, visualization.range, visualization.log_scale);
+                    });
+                }
+                ui.group(|ui| {
+                    ui.label("Star Sprites");
+                    egui::ComboBox::from_label("Render Mode")
+                        .selected_text(renderer.mode.name())
+                        .show_ui(ui, |ui| {
+                            for mode in RenderMode::ALL {
+                                ui.selectable_value(&mut renderer.mode, mode, mode.name());
+                            }
+                        });
+                    egui::ComboBox::from_label("Profile")
+                        .selected_text(renderer.profile.name())
+                        .show_ui(ui, |ui| {
+                            for profile in StarProfile::ALL {
+                                ui.selectable_value(&mut renderer.profile, profile, profile.name());
+                            }
+                        });
+                    ui.add(egui::Slider::new(&mut renderer.star_size, 0.01..=10.0).logarithmic(true).text("Star Size"));
+                    ui.add(egui::Slider::new(&mut renderer.min_star_pixels, 0.5..=8.0).text("Min Pixels"));
+                    ui.add(egui::Slider::new(&mut renderer.max_star_pixels, 1.0..=64.0).text("Max Pixels"));
+                });
+                if renderer.mode == RenderMode::DensitySplat {
+                    let density_splat = &mut renderer.density_splat;
+                    ui.group(|ui| {
+                        ui.label("Density Splat");
+                        egui::ComboBox::from_label("Splat Colormap")
+                            .selected_text(density_splat.colormap.name())
+                            .show_ui(ui, |ui| {
+                                for colormap in Colormap::ALL {
+                                    ui.selectable_value(&mut density_splat.colormap, colormap, colormap.name());
+                                }
+                            });
+                        ui.add(egui::Slider::new(&mut density_splat.kernel_radius, 0..=8).text("Kernel Radius (px)"));
+                        ui.horizontal(|ui| {
+                            ui.add(egui::DragValue::new(&mut density_splat.log_range[0]).speed(0.01).prefix("Log Min: "));
+                            ui.add(egui::DragValue::new(&mut density_splat.log_range[1]).speed(0.01).prefix("Log Max: "));
+                        });
+                        ui.add(egui::Slider::new(&mut density_splat.brightness, 0.0..=10.0).text("Brightness"));
+
+                        Self::draw_colorbar(ui, density_splat.colormap, density_splat.log_range, true);
+                    });
+                }
+                ui.group(|ui| {
+                    ui.label("Dust");
+                    let dust = &mut renderer.dust;
+                    ui.checkbox(&mut dust.enabled, "Enabled")
+                        .on_hover_text("Treats every star as lying in the disk plane, stars off the plane in front of the disk are dimmed too");
+                    ui.add(egui::Slider::new(&mut dust.extinction, 0.0..=10.0).text("Extinction (Face-on V Band)"));
+                    ui.add(egui::Slider::new(&mut dust.reddening, 0.0..=3.0).text("Reddening"));
+                    ui.add(egui::Slider::new(&mut dust.scale_height, 1.0..=100.0).logarithmic(true).text("Scale Height"));
+                    ui.add(egui::Slider::new(&mut dust.steps, 4..=128).text("Raymarch Steps"));
+                });
+                ui.group(|ui| {
+                    ui.label("Trails");
+                    let trails = &mut renderer.trails;
+                    ui.checkbox(&mut trails.accumulation, "Accumulation (Motion Blur)");
+                    ui.add(egui::Slider::new(&mut trails.persistence, 0.01..=10.0).logarithmic(true).text("Persistence (s)"));
+                    ui.checkbox(&mut trails.history_enabled, "Position History");
+                    ui.add(egui::Slider::new(&mut trails.history_length, 2..=MAX_HISTORY_LENGTH).text("History Length (Samples)"));
+                    ui.add(egui::Slider::new(&mut trails.sample_interval, 0.0..=1.0).text("Sample Interval (s)"));
+                    ui.horizontal(|ui| {
+                        ui.color_edit_button_rgb(&mut trails.color);
+                        ui.add(egui::Slider::new(&mut trails.intensity, 0.0..=5.0).text("Intensity"));
+                    });
+                    ui.horizontal(|ui| {
+                        if ui.button("Track Selection").clicked() {
+                            trails.track_selection(galaxy);
+                        }
+                        ui.label(format!("{} / {} stars tracked", trails.tracked_count(), MAX_TRACKED_STARS));
+                    });
+                });
+                ui.group(|ui| {
+                    ui.label("Octree Overlay");
+                    let overlay = &mut renderer.octree_overlay;
+                    ui.checkbox(&mut overlay.enabled, "Enabled");
+                    ui.add(egui::Slider::new(&mut overlay.max_depth, 0..=bhot::MAX_DEPTH).text("Max Depth"));
+                    egui::ComboBox::from_label("Node Color")
+                        .selected_text(overlay.coloring.name())
+                        .show_ui(ui, |ui| {
+                            for coloring in NodeColoring::ALL {
+                                ui.selectable_value(&mut overlay.coloring, coloring, coloring.name());
+                            }
+                        });
+                    egui::ComboBox::from_label("Node Colormap")
+                        .selected_text(overlay.colormap.name())
+                        .show_ui(ui, |ui| {
+                            for colormap in Colormap::ALL {
+                                ui.selectable_value(&mut overlay.colormap, colormap, colormap.name());
+                            }
+                        });
+                    ui.add(egui::Slider::new(&mut overlay.intensity, 0.0..=1.0).logarithmic(true).text("Intensity"));
+                    ui.checkbox(&mut overlay.highlight_opened, "Highlight Opened Nodes");
+                    ui.add_enabled_ui(overlay.highlight_opened, |ui| {
+                        ui.add(egui::Slider::new(&mut overlay.selected_star, 0..=galaxy.stars.len().saturating_sub(1)).text("Selected Star"));
+                        ui.add(egui::Slider::new(&mut overlay.theta, 0.0..=1.5).text("Opening Angle (Theta)"));
+                        ui.add(egui::Slider::new(&mut overlay.softening, 0.01..=100.0).logarithmic(true).text("Softening"));
+                    });
+                    ui.add(egui::Slider::new(&mut overlay.update_interval, 0.0..=10.0).text("Update Interval (s)"));
+                    ui.label(format!("{} nodes, {} drawn{}, {} opened", overlay.node_count, overlay.drawn_nodes,
+                        if overlay.drawn_nodes + overlay.opened_nodes >= MAX_NODES { " (capped)" } else { "" }, overlay.opened_nodes));
                 });
                 ui.group(|ui| {
                     ui.label("Camera");
@@ -69,8 +320,8 @@ impl UI {
                 });
 
                 ui.group(|ui| {
-                    ui.label("Bloom");
-                    ui.add(egui::Slider::new(&mut bloom.filter_size,0.0..=0.003).text("Filter Size"));
+                    ui.label("Post Processing");
+                    post_chain.ui(ui, &wgpu_state.device, &wgpu_state.queue);
                 });
 
                 //ui.add(egui::)
@@ -84,16 +335,50 @@ impl UI {
             .default_width(600.0)
             .show(&ui, |ui| {
                 ui.heading("Times");
-                ui.label(format!("Render Time: {}", (timestamps[1] - timestamps[0]) as f64 / 1000.0));
+                ui.label(format!("Render Time: {}", show(time("Scene"))));
+                for mode in BloomMode::ALL {
+                    ui.label(format!("Bloom Time ({}): {}", mode.name(), self.bloom_times[mode as usize]));
+                }
+                ui.collapsing("Render Graph Scopes", |ui| {
+                    for (name, time) in times {
+                        ui.label(format!("{}: {}", name, show(*time)));
+                    }
+                });
             });
         })
     }
 
+    // Gradient with the range underneath, values are log10 when the log scale is on
+    fn draw_colorbar(ui: &mut egui::Ui, colormap: Colormap, range: [f32; 2], log_scale: bool) {
+        let (response, painter) = ui.allocate_painter(egui::vec2(ui.available_width(), 16.0), egui::Sense::hover());
+        let rect = response.rect;
+
+        const STEPS: usize = 64;
+        let step_width = rect.width() / STEPS as f32;
+        for i in 0..STEPS {
+            let [r, g, b] = colormap.sample((i as f32 + 0.5) / STEPS as f32).map(|c| (c * 255.0) as u8);
+            let x = rect.left() + i as f32 * step_width;
+            painter.rect_filled(
+                egui::Rect::from_min_max(egui::pos2(x, rect.top()), egui::pos2(x + step_width + 0.5, rect.bottom())),
+                0.0,
+                egui::Color32::from_rgb(r, g, b),
+            );
+        }
+
+        let prefix = if log_scale { "log10 " } else { "" };
+        ui.horizontal(|ui| {
+            ui.label(format!("{}{:.3e}", prefix, range[0]));
+            ui.with_layout(egui::Layout::right_to_left(egui::Align::Center), |ui| {
+                ui.label(format!("{}{:.3e}", prefix, range[1]));
+            });
+        });
+    }
+
     pub fn handle_input(&mut self, window: &winit::window::Window, event:&WindowEvent) {
         let _ = self.state.on_window_event(window, event);
     }
 
-    pub fn render(&mut self, encoder:&mut CommandEncoder, wgpu_state: &WgpuState, screen_descriptor: ScreenDescriptor, output_view: &TextureView, full_output: FullOutput) {
+    pub fn render(&mut self, encoder:&mut CommandEncoder, wgpu_state: &WgpuState, screen_descriptor: ScreenDescriptor, output_view: &TextureView, full_output: FullOutput, timestamps: Option<TimestampScope>) {
         //update_viewport_info(&mut raw_input.viewports.get_mut(&self.context.viewport_id()).unwrap(), &self.context, &wgpu_state.window);
         self.state.handle_platform_output(wgpu_state.window, full_output.platform_output);
 
@@ -118,7 +403,7 @@ impl UI {
                 ops: wgpu::Operations { load: wgpu::LoadOp::Load, store: wgpu::StoreOp::Store }
             })],
             depth_stencil_attachment: None,
-            timestamp_writes: None,
+            timestamp_writes: timestamps.map(|scope| scope.render_writes(true, true)),
             occlusion_query_set: None
         });
 